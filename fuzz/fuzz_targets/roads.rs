@@ -0,0 +1,16 @@
+#![no_main]
+use allez_ropi_romi::roads::RoadNetwork;
+use libfuzzer_sys::fuzz_target;
+use nalgebra::Vector2;
+
+// Road networks are read from a file given with --roads; whatever it holds, it is either rejected
+// or can be routed over
+fuzz_target!(|data: &[u8]| {
+    let roads = match std::str::from_utf8(data).map(RoadNetwork::parse) {
+        Ok(Ok(roads)) => roads,
+        _ => return,
+    };
+    let corners = [Vector2::new(-200.0, -200.0), Vector2::new(200.0, 150.0)];
+    let _ = roads.route(&corners[0], &corners[1], &Default::default());
+    let _ = roads.nearest_node(&corners[1]);
+});
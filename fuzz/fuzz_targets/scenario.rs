@@ -0,0 +1,20 @@
+#![no_main]
+use allez_ropi_romi::init_scenario_grid;
+use allez_ropi_romi::scenario::Scenario;
+use libfuzzer_sys::fuzz_target;
+
+// Scenario files come from the command line, as JSON or TOML; whatever they hold, they are either
+// rejected or can be laid out on the grid
+fuzz_target!(|data: &[u8]| {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    for scenario in [Scenario::parse(text), Scenario::parse_toml(text)]
+        .iter()
+        .filter_map(|parsed| parsed.as_ref().ok())
+    {
+        init_scenario_grid(scenario);
+        scenario.centralized_only();
+    }
+});
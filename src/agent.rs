@@ -1,35 +1,375 @@
+use crate::approach;
+use crate::clustering::{centroids, kmeans, route_order};
+use crate::congestion::Congestion;
+use crate::consts::{
+    AGENT_RADIUS, ANOMALY_DETECTION_RANGE, BASE_STATION, CELL_SIZE, CLUSTERING_ITERATIONS,
+    COMM_RANGE, DISTANCE_TO_TARGET, DIVERGENCE_FACTOR, GRID_HALF_SIZE, HANDOFF_WEAR,
+    INTENT_CLEARANCE, LOS_EPSILON, MAINTENANCE_RECOVERY_PER_S, MAINTENANCE_STATIONS,
+    MAINTENANCE_WEAR_THRESHOLD, MAX_ACCELERATION, MAX_AGENT_RESTARTS, MAX_AGGRESSIVENESS,
+    MAX_ANGULAR_ACCELERATION, MAX_COLLISION_PUSHES, MAX_CONTROL_CATCHUP, MAX_REACTION_DELAY_MS,
+    MAX_SPEED, MAX_WEAR, MIN_AGGRESSIVENESS, MIN_REWARD_SHARE, PEDESTRIAN_AVOID_RADIUS,
+    RESTARTS_FORGOTTEN_AFTER_S, ROUTED_PAST_PENALTY, SAFETY_ACTIVATION, SAFETY_MARGIN, SPAM_FACTOR,
+    SPEED_SPREAD, SPREAD_RADIUS, TICK_RATE_REPORT_PERIOD_S, TRACKING_GAIN, TRAJECTORY_MARGIN,
+    VELOCITY_RETAINED_PER_S, WEAR_PER_DISTANCE,
+};
+use crate::dataset::{Action, DatasetRecorder, Outcome};
+use crate::demand::DemandMap;
+use crate::error::{Error, Result};
+use crate::metrics::Metrics;
 use crate::missions::*;
+use crate::observation::ObservationConfig;
+use crate::obstacles::{distance_to_segment, Obstacle};
+use crate::pedestrians::Pedestrians;
+use crate::planning::Planner;
+use crate::realtime::{DeadlineMonitor, LoadShedding};
+use crate::rect::Rect;
+use crate::roads::RoadNetwork;
+use crate::safety::{self, Constraint};
+use crate::scenario::PendingMission;
+use crate::sequence::{Arrival, SequenceTracker};
 use crate::system::*;
+use crate::units::{Meters, MetersPerSecond, MetersPerSecondSquared, Seconds};
 use log::*;
 use nalgebra::Vector2;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 
 pub enum Message {
     Mission(MissionMessage),
-    MissionFinished(usize),
+    MissionCancelled(usize),
+    MissionCompleted { mission: usize, by_agent: usize },
     Agent(AgentMessage),
+    Urgent { mission: Mission, assignee: usize },
+    Awarded { mission: Mission, agent: usize },
+    WorldSync(WorldSync),
+    SwitchAllocation(AllocationStrategy),
+    MissionProposal(MissionProposal),
+    Handoff(Handoff),
+}
+
+// Mission an agent gives up on halfway to be serviced, for the hub to hand to another one
+#[derive(Clone, Debug)]
+pub struct Handoff {
+    pub agent: usize,
+    pub mission: usize,
+    // Goals left to reach to complete the mission, in order
+    pub remaining: Vec<Vector2<f32>>,
+}
+
+// Mission an agent came up with on its own, which the hub registers and hands to whoever is best
+// placed for it
+#[derive(Clone, Debug)]
+pub struct MissionProposal {
+    pub agent: usize,
+    pub mission: PendingMission,
+    pub reason: String,
 }
 
 #[derive(Clone, Debug)]
 pub struct AgentMessage {
     pub id: usize,
+    // Numbers the states the agent sent, from 1 on, so that receivers notice the ones lost
+    pub seq: u64,
+    pub tick: u64,
     pub kinematics: Kinematics,
     pub mission: Option<Mission>,
+    pub wear: f32,
+    pub completed: usize,
+    // Positions the agent plans to be at every PLAN_SAMPLE_PERIOD_S, if it follows a trajectory
+    pub plan: Vec<Vector2<f32>>,
+    // Next INTENT_WAYPOINTS points of its path, ending on its goal, if it is going anywhere
+    pub waypoints: Vec<Vector2<f32>>,
+    // Transport missions picked up and not yet dropped off
+    pub cargo: Vec<Mission>,
+}
+
+impl AgentMessage {
+    // Squared distance to the target, scaled by how much the agent is slowed down by its wear
+    pub fn cost_to(&self, target: &Vector2<f32>) -> f32 {
+        (target - self.kinematics.p).norm_squared() / capability(self.wear).powi(2)
+    }
+
+    // Whether the target lies along the route the agent shared, from where it is on
+    pub fn routes_past(&self, target: &Vector2<f32>) -> bool {
+        let mut from = self.kinematics.p;
+        self.waypoints.iter().any(|waypoint| {
+            let near = distance_to_segment(target, &from, waypoint) < INTENT_CLEARANCE;
+            from = *waypoint;
+            near
+        })
+    }
+}
+
+// Fraction of its nominal speed and acceleration a worn agent still has
+pub fn capability(wear: f32) -> f32 {
+    1.0 - wear
+}
+
+// What an agent keeps from the messages it received, besides the missions and agents it knows of
+#[derive(Default)]
+pub struct Inbox {
+    last_mission_tick: u64,
+    sequences: SequenceTracker,
+    // Our mission as of the last conflict check, which only has to run again once it changes or
+    // another agent claims it too
+    checked_mission: Option<usize>,
+    contested: bool,
+    // When to pick a mission again, after something changed
+    reaction_deadline: Option<Instant>,
 }
 
 pub struct Agent {
     pub id: usize,
     pub kinematics: Kinematics,
     pub mission: Option<Mission>,
+    pub personality: Personality,
+    pub allocation: AllocationStrategy,
+    // Strategy to switch to once the mission claimed under the current one is over
+    pub next_allocation: Option<AllocationStrategy>,
+    pub route: Vec<usize>,
+    pub misbehavior: Option<Misbehavior>,
+    pub last_broadcast: Option<AgentMessage>,
+    pub metrics: Metrics,
+    pub wear: f32,
+    pub in_maintenance: bool,
+    pub control_rate_hz: f32,
+    pub completed: usize,
+    pub idle: IdlePolicy,
+    pub home: Vector2<f32>,
+    pub idle_target: Option<Vector2<f32>>,
+    pub demand: DemandMap,
+    pub observation: ObservationConfig,
+    pub dataset: Option<DatasetRecorder>,
+    pub pedestrians: Pedestrians,
+    pub congestion: Congestion,
+    pub planner: Planner,
+    pub clock: SimClock,
+    pub keep_connected: bool,
+    pub safety_filter: bool,
+    pub load_shedding: Option<LoadShedding>,
+    pub reconnect_to: Option<Vector2<f32>>,
+    // Transport missions picked up at once before dropping them all off
+    pub capacity: usize,
+    pub cargo: Vec<Mission>,
+    // Anomalies of the scenario the agent has not come across yet
+    pub anomalies: Vec<Vector2<f32>>,
+    // Agents and missions are only acted upon when in view within this range; all of them are
+    // without it
+    pub sensing_range: Option<f32>,
+    // Number of the last state sent
+    pub seq: u64,
+    pub boundary: BoundaryPolicy,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AllocationStrategy {
+    Nearest,
+    Clustered,
+}
+
+impl AllocationStrategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AllocationStrategy::Nearest => "nearest",
+            AllocationStrategy::Clustered => "clustered",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            AllocationStrategy::Nearest => AllocationStrategy::Clustered,
+            AllocationStrategy::Clustered => AllocationStrategy::Nearest,
+        }
+    }
+}
+
+// What an agent does while it has no mission
+#[derive(Clone, Copy, Debug)]
+pub enum IdlePolicy {
+    Drift,
+    Hold,
+    ReturnHome,
+    SpreadOut,
+    Anticipate,
+}
+
+impl IdlePolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IdlePolicy::Drift => "drift",
+            IdlePolicy::Hold => "hold",
+            IdlePolicy::ReturnHome => "home",
+            IdlePolicy::SpreadOut => "spread",
+            IdlePolicy::Anticipate => "anticipate",
+        }
+    }
+}
+
+// What happens to agents reaching the edge of the world: they are stopped against it, come back
+// in from the opposite edge as on a torus, or bounce back off it
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BoundaryPolicy {
+    #[default]
+    Clamp,
+    Wrap,
+    Bounce,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Misbehavior {
+    LieAboutPosition,
+    PhantomClaims,
+    Spam,
+}
+
+#[derive(Clone, Debug)]
+pub struct Personality {
+    pub max_speed: MetersPerSecond,
+    pub reaction_delay: Duration,
+    pub aggressiveness: f32,
+}
+
+impl Personality {
+    pub fn random<R: Rng>(rng: &mut R) -> Self {
+        Personality {
+            max_speed: MAX_SPEED * rng.gen_range(1.0 - SPEED_SPREAD..=1.0 + SPEED_SPREAD),
+            reaction_delay: Duration::from_millis(rng.gen_range(0..=MAX_REACTION_DELAY_MS)),
+            aggressiveness: rng.gen_range(MIN_AGGRESSIVENESS..=MAX_AGGRESSIVENESS),
+        }
+    }
 }
 
 pub struct Grid {
     pub cells: Vec<Cell>,
     pub width: usize,
+    pub obstacles: Vec<Obstacle>,
+    // In a road world, agents and mission targets stay on the network
+    pub roads: Option<Arc<RoadNetwork>>,
+    pub labels: HashMap<String, Rect>,
 }
 
-impl Grid {}
+impl Grid {
+    // Cells whose center lies inside an obstacle become uncrossable
+    pub fn add_obstacles(&mut self, obstacles: Vec<Obstacle>) {
+        for (k, cell) in self.cells.iter_mut().enumerate() {
+            let center = Vector2::new(
+                (k % self.width) as f32 * CELL_SIZE - GRID_HALF_SIZE,
+                (k / self.width) as f32 * CELL_SIZE - GRID_HALF_SIZE,
+            );
+            if obstacles.iter().any(|o| o.signed_distance(&center) < 0.0) {
+                *cell = Cell::Uncrossable;
+            }
+        }
+        self.obstacles.extend(obstacles);
+    }
+
+    // Cells whose center lies inside the box take the given cost, None for uncrossable
+    pub fn paint(&mut self, rect: Rect, cost: Option<f32>) {
+        for (k, cell) in self.cells.iter_mut().enumerate() {
+            let center = Vector2::new(
+                (k % self.width) as f32 * CELL_SIZE - GRID_HALF_SIZE,
+                (k / self.width) as f32 * CELL_SIZE - GRID_HALF_SIZE,
+            );
+            if (rect.min.x..=rect.max.x).contains(&center.x)
+                && (rect.min.y..=rect.max.y).contains(&center.y)
+            {
+                *cell = match cost {
+                    Some(cost) => Cell::Crossable(cost),
+                    None => Cell::Uncrossable,
+                };
+            }
+        }
+    }
+
+    pub fn label_region(&mut self, name: String, rect: Rect) {
+        self.labels.insert(name, rect);
+    }
+
+    pub fn region(&self, name: &str) -> Option<Rect> {
+        self.labels.get(name).copied()
+    }
+
+    fn is_blocked(&self, (col, row): (i64, i64)) -> bool {
+        if col < 0 || row < 0 || col as usize >= self.width {
+            return false;
+        }
+        matches!(
+            self.cells.get(row as usize * self.width + col as usize),
+            Some(Cell::Uncrossable)
+        )
+    }
+
+    // Whether the segment crosses no uncrossable cell, walking the cells it goes through in order
+    // (DDA). Through a corner, both cells beside it have to be free too, so that nothing is seen
+    // between two diagonal neighbours. Cells off the grid block nothing.
+    pub fn line_of_sight(&self, a: &Vector2<f32>, b: &Vector2<f32>) -> bool {
+        // In cell units, where cell (i, j) spans [i, i + 1) x [j, j + 1)
+        let to_cells = |p: &Vector2<f32>| {
+            (p + Vector2::repeat(GRID_HALF_SIZE)) / CELL_SIZE + Vector2::repeat(0.5)
+        };
+        let (from, to) = (to_cells(a), to_cells(b));
+        // Direction of the steps along an axis, fraction of the segment until the first cell
+        // border and between two borders
+        let axis = |start: f32, delta: f32| {
+            if delta > 0.0 {
+                (1, (start.floor() + 1.0 - start) / delta, 1.0 / delta)
+            } else if delta < 0.0 {
+                (-1, (start - start.floor()) / -delta, -1.0 / delta)
+            } else {
+                (0, f32::INFINITY, f32::INFINITY)
+            }
+        };
+        let (step_x, mut next_x, delta_x) = axis(from.x, to.x - from.x);
+        let (step_y, mut next_y, delta_y) = axis(from.y, to.y - from.y);
+        let mut cell = (from.x.floor() as i64, from.y.floor() as i64);
+        let last = (to.x.floor() as i64, to.y.floor() as i64);
+        if self.is_blocked(cell) {
+            return false;
+        }
+        while cell != last {
+            let x_left = cell.0 != last.0;
+            let y_left = cell.1 != last.1;
+            let through_corner = x_left && y_left && (next_x - next_y).abs() <= LOS_EPSILON;
+            if through_corner {
+                if self.is_blocked((cell.0 + step_x, cell.1))
+                    || self.is_blocked((cell.0, cell.1 + step_y))
+                {
+                    return false;
+                }
+                cell = (cell.0 + step_x, cell.1 + step_y);
+                next_x += delta_x;
+                next_y += delta_y;
+            } else if x_left && (!y_left || next_x < next_y) {
+                cell.0 += step_x;
+                next_x += delta_x;
+            } else {
+                cell.1 += step_y;
+                next_y += delta_y;
+            }
+            if self.is_blocked(cell) {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Cell whose center is nearest to the given position, if it lies on the grid
+    pub fn cell_at(&self, p: &Vector2<f32>) -> Option<&Cell> {
+        let col = ((p.x + GRID_HALF_SIZE) / CELL_SIZE).round();
+        let row = ((p.y + GRID_HALF_SIZE) / CELL_SIZE).round();
+        if col < 0.0 || row < 0.0 || col as usize >= self.width {
+            return None;
+        }
+        self.cells.get(row as usize * self.width + col as usize)
+    }
+}
 
 pub enum Cell {
     Uncrossable,
@@ -42,135 +382,1142 @@ pub struct Kinematics {
     pub v: Vector2<f32>,
     pub a: Vector2<f32>,
     pub theta: f32,
+    pub omega: f32,
+    pub alpha: f32,
     pub radius: f32,
 }
 
+impl Kinematics {
+    pub fn is_finite(&self) -> bool {
+        self.p.iter().all(|c| c.is_finite())
+            && self.v.iter().all(|c| c.is_finite())
+            && self.a.iter().all(|c| c.is_finite())
+            && self.theta.is_finite()
+            && self.omega.is_finite()
+            && self.alpha.is_finite()
+    }
+}
+
 impl Agent {
-    pub fn simulate_motion(&mut self, old: Instant) -> (Instant, f32) {
-        let friction = (0.8f32).ln();
-        let now = Instant::now();
-        let dt = (now - old).as_secs_f32();
-        debug!("wat: {}", (dt / friction).exp());
+    fn sanitize_kinematics(&mut self, stage: &str) {
+        let speed_bound = (DIVERGENCE_FACTOR * self.personality.max_speed).0;
         let k = &mut self.kinematics;
+        if !k.is_finite() {
+            warn!(
+                "Agent {} has diverging kinematics after {}: {:?}",
+                self.id, stage, k
+            );
+            self.metrics
+                .increment(&format!("agent.{}.kinematics_resets", self.id));
+            let fallback = self
+                .last_broadcast
+                .as_ref()
+                .map(|state| state.kinematics.clone())
+                .filter(|k| k.is_finite());
+            let k = &mut self.kinematics;
+            match fallback {
+                Some(fallback) => {
+                    k.p = fallback.p;
+                    k.theta = fallback.theta;
+                }
+                None => {
+                    if !k.p.iter().all(|c| c.is_finite()) {
+                        k.p = Vector2::zeros();
+                    }
+                    if !k.theta.is_finite() {
+                        k.theta = 0.0;
+                    }
+                }
+            }
+            k.v = Vector2::zeros();
+            k.a = Vector2::zeros();
+            k.omega = 0.0;
+            k.alpha = 0.0;
+        } else if k.v.norm() > speed_bound {
+            warn!(
+                "Agent {} exceeds its speed bound after {} ({} > {}), clamping it",
+                self.id,
+                stage,
+                k.v.norm(),
+                speed_bound
+            );
+            self.metrics
+                .increment(&format!("agent.{}.speed_clamps", self.id));
+            k.v *= speed_bound / k.v.norm();
+        }
+    }
+
+    pub fn simulate_motion(&mut self, dt: f32) {
+        let friction = VELOCITY_RETAINED_PER_S.ln();
+        let max_speed = (self.personality.max_speed
+            * capability(self.wear)
+            * self.congestion.speed_factor(&self.kinematics.p))
+        .0;
+        let k = &mut self.kinematics;
+        let previous = k.p;
         k.p += dt * (k.v + dt * k.a / 2.0);
         k.v = dt * k.a + (dt * friction).exp() * k.v;
-        (now, dt)
+        if k.v.norm() > max_speed {
+            k.v *= max_speed / k.v.norm();
+        }
+        let travelled = (k.p - previous).norm();
+        if travelled.is_finite() {
+            self.wear = (self.wear + travelled * WEAR_PER_DISTANCE).min(MAX_WEAR);
+        }
+        k.theta += dt * (k.omega + dt * k.alpha / 2.0);
+        k.theta = angle_difference(0.0, k.theta);
+        k.omega = dt * k.alpha + (dt * friction).exp() * k.omega;
     }
 
-    pub fn run(&mut self, connection_handle: &mut ConnectionHandle, _grid: &Grid) {
-        info!("Starting agent");
+    // Keeps the whole body of the agent inside the world, bar wrapping where its center moves to
+    // the opposite edge once it crosses one
+    fn apply_boundary(&mut self) {
+        let k = &mut self.kinematics;
+        let limit = GRID_HALF_SIZE - k.radius;
+        for i in 0..2 {
+            match self.boundary {
+                BoundaryPolicy::Wrap => {
+                    k.p[i] =
+                        (k.p[i] + GRID_HALF_SIZE).rem_euclid(2.0 * GRID_HALF_SIZE) - GRID_HALF_SIZE;
+                }
+                BoundaryPolicy::Clamp if k.p[i].abs() > limit => {
+                    k.p[i] = k.p[i].clamp(-limit, limit);
+                    if k.v[i] * k.p[i] > 0.0 {
+                        k.v[i] = 0.0;
+                    }
+                }
+                BoundaryPolicy::Bounce if k.p[i].abs() > limit => {
+                    let edge = limit.copysign(k.p[i]);
+                    k.p[i] = (2.0 * edge - k.p[i]).clamp(-limit, limit);
+                    if k.v[i] * k.p[i] > 0.0 {
+                        k.v[i] = -k.v[i];
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Pushes the agent out of any obstacle it overlaps, and cancels its velocity into it. Deep
+    // inside a shape one push may not be enough, e.g. on the medial axis of a polygon.
+    fn resolve_collisions(&mut self, grid: &Grid) {
+        let k = &mut self.kinematics;
+        for obstacle in &grid.obstacles {
+            for _ in 0..MAX_COLLISION_PUSHES {
+                let distance = obstacle.signed_distance(&k.p);
+                if distance >= k.radius {
+                    break;
+                }
+                let normal = obstacle.outward(&k.p);
+                k.p += (k.radius - distance) * normal;
+                let into = k.v.dot(&normal);
+                if into < 0.0 {
+                    k.v -= into * normal;
+                }
+            }
+        }
+    }
+
+    fn nearest_station(&self) -> Vector2<f32> {
+        let p = self.kinematics.p;
+        MAINTENANCE_STATIONS
+            .iter()
+            .map(|&(x, y)| Vector2::new(x, y))
+            .min_by(|a, b| {
+                (a - p)
+                    .norm_squared()
+                    .partial_cmp(&(b - p).norm_squared())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or(p)
+    }
+
+    // Where the agent is heading: a maintenance station when it is being serviced, back into
+    // communication range when it lost contact, its mission otherwise
+    fn target(&self, grid: &Grid) -> Option<(Vector2<f32>, Option<f32>)> {
+        if self.in_maintenance {
+            Some((self.nearest_station(), None))
+        } else if let Some(anchor) = self.reconnect_to {
+            Some((anchor, None))
+        } else if let Some(mission) = &self.mission {
+            Some(mission.goal_from(grid, &self.kinematics.p))
+        } else if let Some(carried) = self.cargo.first() {
+            Some(carried.goal_from(grid, &self.kinematics.p))
+        } else {
+            self.idle_target.map(|p| (p, None))
+        }
+    }
+
+    fn update_idle_target(&mut self, agents: &HashMap<usize, AgentMessage>) {
+        if self.mission.is_some() {
+            self.idle_target = None;
+            return;
+        }
+        let p = self.kinematics.p;
+        self.idle_target = match self.idle {
+            IdlePolicy::Drift => None,
+            IdlePolicy::Hold => Some(self.idle_target.unwrap_or(p)),
+            IdlePolicy::ReturnHome => Some(self.home),
+            IdlePolicy::SpreadOut => {
+                // Pushed away from every agent closer than SPREAD_RADIUS, the closer the harder
+                let push: Vector2<f32> = agents
+                    .values()
+                    .filter(|a| a.id != self.id)
+                    .map(|a| p - a.kinematics.p)
+                    .filter(|d| d.norm() > 0.0 && d.norm() < SPREAD_RADIUS)
+                    .map(|d| d.normalize() * (SPREAD_RADIUS - d.norm()))
+                    .sum();
+                let bound = Vector2::repeat(GRID_HALF_SIZE - CELL_SIZE - AGENT_RADIUS);
+                Some((p + push).sup(&-bound).inf(&bound))
+            }
+            IdlePolicy::Anticipate => self.demand.best_spot(
+                agents
+                    .values()
+                    .filter(|a| a.id != self.id)
+                    .map(|a| a.kinematics.p),
+            ),
+        };
+    }
+
+    fn perceives(&self, grid: &Grid, p: &Vector2<f32>) -> bool {
+        let own = &self.kinematics.p;
+        self.sensing_range
+            .is_none_or(|range| (p - own).norm() <= range && grid.line_of_sight(own, p))
+    }
+
+    // The agents and missions in view among those the agent heard of, None if it sees them all
+    fn perceive(
+        &self,
+        grid: &Grid,
+        agents: &HashMap<usize, AgentMessage>,
+        missions: &MissionPool,
+    ) -> Option<(HashMap<usize, AgentMessage>, MissionPool)> {
+        self.sensing_range?;
+        let seen_agents = agents
+            .iter()
+            .filter(|(_, a)| a.id == self.id || self.perceives(grid, &a.kinematics.p))
+            .map(|(&id, a)| (id, a.clone()))
+            .collect();
+        let mut seen_missions = MissionPool::default();
+        for m in missions.iter().filter(|m| self.perceives(grid, &m.target)) {
+            seen_missions.insert(m.clone());
+        }
+        Some((seen_agents, seen_missions))
+    }
+
+    fn anchors<'a>(
+        &'a self,
+        agents: &'a HashMap<usize, AgentMessage>,
+    ) -> impl Iterator<Item = Vector2<f32>> + 'a {
+        agents
+            .values()
+            .filter(move |a| a.id != self.id)
+            .map(|a| a.kinematics.p)
+            .chain(std::iter::once(Vector2::new(
+                BASE_STATION.0,
+                BASE_STATION.1,
+            )))
+    }
+
+    // Whether the agent can still complete the mission before its window closes, travelling
+    // straight at full speed to the target and back to the base if it has one
+    fn can_make_in_time(&self, mission: &Mission) -> bool {
+        let (_, latest) = match mission.window {
+            Some(window) => window,
+            None => return true,
+        };
+        let p = self.kinematics.p;
+        let distance = (mission.target - p).norm()
+            + mission
+                .return_to
+                .map_or(0.0, |base| (base - mission.target).norm());
+        let travel = Meters(distance) / (capability(self.wear) * self.personality.max_speed);
+        self.clock.now() + travel.ticks() <= latest
+    }
+
+    // Missions another agent is already heading past are left to it, unless much cheaper
+    fn routed_past_factor(&self, mission: &Mission, agents: &HashMap<usize, AgentMessage>) -> f32 {
+        if agents
+            .values()
+            .any(|a| a.id != self.id && a.routes_past(&mission.target))
+        {
+            ROUTED_PAST_PENALTY
+        } else {
+            1.0
+        }
+    }
+
+    // Selection cost of the mission, raised as the reward left by the time the agent gets there
+    // decays, so that missions losing their value quickly are served first
+    fn bid(&self, mission: &Mission) -> f32 {
+        let p = self.kinematics.p;
+        let cost = mission.selection_cost(&p);
+        let reward = match mission.reward {
+            Some(reward) => reward,
+            None => return cost,
+        };
+        let travel = Meters((mission.target - p).norm())
+            / (capability(self.wear) * self.personality.max_speed);
+        let expected = mission
+            .reward_at(self.clock.now() + travel.ticks())
+            .unwrap_or(0.0);
+        cost / (expected / reward.value).max(MIN_REWARD_SHARE).powi(2)
+    }
+
+    // Within range of another agent or of the base, with nothing in the way
+    fn is_connected_at(
+        &self,
+        grid: &Grid,
+        p: &Vector2<f32>,
+        agents: &HashMap<usize, AgentMessage>,
+    ) -> bool {
+        self.anchors(agents)
+            .any(|a| (a - p).norm() <= COMM_RANGE && grid.line_of_sight(&a, p))
+    }
+
+    // When asked to keep the network connected, an agent out of range of every other agent and
+    // of the base station drops what it is doing and heads back to the closest of them
+    fn update_connectivity(&mut self, grid: &Grid, agents: &HashMap<usize, AgentMessage>) {
+        if !self.keep_connected {
+            return;
+        }
+        let p = self.kinematics.p;
+        if self.is_connected_at(grid, &p, agents) {
+            if self.reconnect_to.take().is_some() {
+                info!("Agent {} is back in communication range", self.id);
+            }
+            return;
+        }
+        if self.reconnect_to.is_none() {
+            warn!("Agent {} lost contact with the fleet", self.id);
+            self.metrics
+                .increment(&format!("agent.{}.connectivity_violations", self.id));
+        }
+        self.reconnect_to = self.anchors(agents).min_by(|a, b| {
+            (a - p)
+                .norm_squared()
+                .partial_cmp(&(b - p).norm_squared())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+    }
+
+    // Sends idle worn agents to the closest station, and restores them while they stand there.
+    // Returns true when the agent just came out of maintenance.
+    fn maintain(&mut self, dt: f32) -> bool {
+        if !self.in_maintenance {
+            if self.mission.is_none() && self.wear >= MAINTENANCE_WEAR_THRESHOLD {
+                info!(
+                    "Agent {} is worn out ({}), heading to maintenance",
+                    self.id, self.wear
+                );
+                self.in_maintenance = true;
+            }
+            return false;
+        }
+
+        if (self.nearest_station() - self.kinematics.p).norm() < DISTANCE_TO_TARGET {
+            self.wear = (self.wear - MAINTENANCE_RECOVERY_PER_S * dt).max(0.0);
+            if self.wear == 0.0 {
+                info!("Agent {} is done with maintenance", self.id);
+                self.metrics
+                    .increment(&format!("agent.{}.maintenances", self.id));
+                self.in_maintenance = false;
+                return true;
+            }
+        }
+        false
+    }
+
+    // Missions bringing something back are first completed at their target, then at the base.
+    // Vehicles with room left keep what they picked up as cargo and may go for another pickup,
+    // in which case this returns true for them to select one.
+    fn collect(&mut self, grid: &Grid) -> bool {
+        let k = &self.kinematics;
+        let picked_up = match &mut self.mission {
+            Some(mission)
+                if mission.return_to.is_some()
+                    && !mission.visited
+                    && mission.goal_reached_by(grid, k) =>
+            {
+                mission.visited = true;
+                mission.id
+            }
+            _ => return false,
+        };
+        if self.cargo.len() + 1 < self.capacity {
+            self.cargo.extend(self.mission.take());
+            info!(
+                "Agent {} picked up mission {} ({} of {} loaded)",
+                self.id,
+                picked_up,
+                self.cargo.len(),
+                self.capacity
+            );
+            return true;
+        }
+        info!(
+            "Agent {} visited the target of mission {}, returning to base",
+            self.id, picked_up
+        );
+        false
+    }
+
+    // Agents too worn to go on give up the mission they have not reached yet, and head to
+    // maintenance right away rather than once idle. Missions already picked up are brought back
+    // first.
+    fn hand_off_if_worn(&mut self, connection_handle: &ConnectionHandle) -> Result<()> {
+        if self.in_maintenance || self.wear < HANDOFF_WEAR {
+            return Ok(());
+        }
+        let mission = match self.mission.take_if(|m| !m.visited) {
+            Some(mission) => mission,
+            None => return Ok(()),
+        };
+        warn!(
+            "Agent {} is worn out ({}), handing off mission {}",
+            self.id, self.wear, mission.id
+        );
+        self.metrics
+            .increment(&format!("agent.{}.handoffs", self.id));
+        self.route.clear();
+        self.in_maintenance = true;
+        connection_handle.tx.send(Message::Handoff(Handoff {
+            agent: self.id,
+            mission: mission.id,
+            remaining: std::iter::once(mission.target)
+                .chain(mission.return_to)
+                .collect(),
+        }))?;
+        Ok(())
+    }
+
+    // Anomalies within range are proposed as missions to investigate them, once
+    fn report_anomalies(&mut self, connection_handle: &ConnectionHandle) -> Result<()> {
+        let p = self.kinematics.p;
+        let (seen, unseen): (Vec<_>, Vec<_>) = self
+            .anomalies
+            .iter()
+            .partition(|anomaly| (*anomaly - p).norm() <= ANOMALY_DETECTION_RANGE);
+        self.anomalies = unseen;
+        for anomaly in seen {
+            info!("Agent {} noticed an anomaly at {}", self.id, anomaly);
+            connection_handle
+                .tx
+                .send(Message::MissionProposal(MissionProposal {
+                    agent: self.id,
+                    mission: PendingMission::at(&anomaly),
+                    reason: format!("investigate anomaly seen at {}", anomaly),
+                }))?;
+        }
+        Ok(())
+    }
+
+    fn pedestrian_push(&self) -> Vector2<f32> {
+        let p = self.kinematics.p;
+        self.pedestrians
+            .positions()
+            .iter()
+            .map(|pedestrian| p - pedestrian)
+            .filter(|d| d.norm() > 0.0 && d.norm() < PEDESTRIAN_AVOID_RADIUS)
+            .map(|d| d.normalize() * (1.0 - d.norm() / PEDESTRIAN_AVOID_RADIUS))
+            .sum()
+    }
+
+    fn filter_for_safety(
+        &mut self,
+        grid: &Grid,
+        agents: &HashMap<usize, AgentMessage>,
+        max_acceleration: MetersPerSecondSquared,
+    ) {
+        let k = &self.kinematics;
+        let mut constraints: Vec<Constraint> = grid
+            .obstacles
+            .iter()
+            .map(|obstacle| Constraint {
+                normal: obstacle.outward(&k.p),
+                gap: obstacle.signed_distance(&k.p) - k.radius - SAFETY_MARGIN,
+                relative_velocity: k.v,
+                share: 1.0,
+            })
+            .collect();
+        constraints.extend(
+            agents
+                .values()
+                .filter(|other| other.id != self.id)
+                .filter_map(|other| {
+                    let d = k.p - other.kinematics.p;
+                    let distance = d.norm();
+                    if distance == 0.0 {
+                        return None;
+                    }
+                    Some(Constraint {
+                        normal: d / distance,
+                        gap: distance - k.radius - other.kinematics.radius - SAFETY_MARGIN,
+                        relative_velocity: k.v - other.kinematics.v,
+                        share: 0.5,
+                    })
+                }),
+        );
+        let (a, interventions) =
+            safety::filter(k.a, &constraints, max_acceleration, SAFETY_ACTIVATION);
+        if interventions > 0 {
+            debug!(
+                "Safety filter of agent {} changed its acceleration from {} to {}",
+                self.id, k.a, a
+            );
+            self.metrics.add(
+                &format!("agent.{}.safety_interventions", self.id),
+                interventions as f64,
+            );
+            self.kinematics.a = a;
+        }
+    }
+
+    fn control(&mut self, dt: f32, grid: &Grid, agents: &HashMap<usize, AgentMessage>) {
+        debug!("Current mission: {:?}", self.mission);
+        let max_acceleration = capability(self.wear) * MAX_ACCELERATION;
+        // Friction eats into the caps while tracking, so trajectories are planned with a margin.
+        // Trajectories head straight for the goal, which does not keep to the roads.
+        let reference = self
+            .target(grid)
+            .filter(|_| grid.roads.is_none())
+            .and_then(|(goal, _)| {
+                let margin = TRAJECTORY_MARGIN * capability(self.wear);
+                self.planner.track(
+                    Seconds(dt),
+                    &self.kinematics,
+                    goal,
+                    margin * self.personality.max_speed,
+                    margin * MAX_ACCELERATION,
+                )
+            });
+        // The heading only matters once on the goal, not at intermediate waypoints
+        let target = self.target(grid).map(|(goal, goal_theta)| {
+            let k = &self.kinematics;
+            let waypoint =
+                match self
+                    .planner
+                    .next_waypoint(grid, &self.congestion, &k.p, goal, k.radius)
+                {
+                    Ok(waypoint) => waypoint,
+                    Err(err) => {
+                        warn!("Agent {}: {}, heading straight for the goal", self.id, err);
+                        self.metrics
+                            .increment(&format!("agent.{}.planning_failures", self.id));
+                        goal
+                    }
+                };
+            if waypoint == goal {
+                (goal, goal_theta, goal)
+            } else {
+                (waypoint, None, goal)
+            }
+        });
+        if let Some(reference) = reference {
+            // Feedforward of the planned acceleration and of friction, plus a critically damped
+            // correction of the tracking error
+            let k = &mut self.kinematics;
+            let friction = -VELOCITY_RETAINED_PER_S.ln() * reference.v;
+            let a = reference.a
+                + friction
+                + TRACKING_GAIN * TRACKING_GAIN * (reference.p - k.p)
+                + 2.0 * TRACKING_GAIN * (reference.v - k.v);
+            k.a = if a.norm() > max_acceleration.0 {
+                a * max_acceleration.0 / a.norm()
+            } else {
+                a
+            };
+        } else if let Some((target, _, goal)) = target {
+            // Braking is planned for the rest of the way, as if the path went straight on from
+            // the waypoint to the goal
+            let max_speed = self.personality.max_speed
+                * capability(self.wear)
+                * self.congestion.speed_factor(&self.kinematics.p);
+            let k = &mut self.kinematics;
+            k.a = approach::acceleration(
+                &k.p,
+                &k.v,
+                &target,
+                Meters((goal - target).norm()),
+                max_speed,
+                max_acceleration,
+                Seconds(dt),
+            );
+            debug!("dt:\t{}", dt);
+            debug!("target:\t{}", target);
+            debug!("Acceleration:\t{}", k.a);
+            debug!("Position:\t{}", k.p);
+            debug!("Velocity:\t{}", k.v);
+        } else {
+            self.kinematics.a = Vector2::zeros();
+            self.planner.forget();
+            debug!("New acceleration is null, because it has no associated mission",);
+        }
+
+        // Pedestrians are avoided whatever the agent is doing, at the expense of its goal
+        let push = self.pedestrian_push();
+        if push != Vector2::zeros() {
+            let a = self.kinematics.a + 2.0 * max_acceleration.0 * push;
+            self.kinematics.a = if a.norm() > max_acceleration.0 {
+                a * max_acceleration.0 / a.norm()
+            } else {
+                a
+            };
+        }
+
+        if self.safety_filter {
+            self.filter_for_safety(grid, agents, max_acceleration);
+        }
+
+        let k = &mut self.kinematics;
+        let alpha = match target.and_then(|(_, target_theta, _)| target_theta) {
+            Some(target_theta) => {
+                let error = angle_difference(k.theta, target_theta);
+                (2.0 / dt) * (error / dt) - (2.0 / dt) * k.omega
+            }
+            None => -(2.0 / dt) * k.omega,
+        };
+        k.alpha = alpha.clamp(-MAX_ANGULAR_ACCELERATION, MAX_ANGULAR_ACCELERATION);
+    }
+
+    pub fn spawn(
+        mut self,
+        mut connection_handle: ConnectionHandle,
+        grid: Arc<Grid>,
+    ) -> std::io::Result<JoinHandle<()>> {
+        std::thread::Builder::new()
+            .name(format!("Agent {}", self.id))
+            .spawn(move || self.run_supervised(&mut connection_handle, &grid))
+    }
+
+    // What the agent knows of the others and of the missions outlives a crash, so that it does not
+    // restart blind. Crashes are only held against the agent for RESTARTS_FORGOTTEN_AFTER_S, so
+    // that scattered transient faults do not add up to its removal.
+    pub fn run_supervised(&mut self, connection_handle: &mut ConnectionHandle, grid: &Grid) {
         let mut agents = HashMap::new();
-        let mut missions = HashMap::new();
-        let mut now = Instant::now();
+        let mut missions = MissionPool::default();
+        let mut restarts = 0;
         loop {
-            let (new_now, dt) = self.simulate_motion(now);
-            now = new_now;
-            loop {
-                match connection_handle.rx.recv_timeout(Duration::from_millis(10)) {
-                    Ok(message) => match message {
-                        Message::Mission(mission_message) => {
-                            debug!("Received new mission: {:?}", mission_message);
-                            for m in mission_message.0 {
-                                missions.insert(m.id, m);
-                            }
-                            self.get_new_mission(&missions, &agents);
-                        }
-                        Message::Agent(agent_message) => {
-                            debug!("Updating info from agent {}", agent_message.id);
-                            agents.insert(agent_message.id, agent_message);
-                        }
-                        Message::MissionFinished(mission_id) => {
-                            if let Some(mission) = &self.mission {
-                                if mission.id == mission_id {
-                                    self.mission = None;
-                                    self.get_new_mission(&missions, &agents);
-                                }
-                            }
-                            missions.remove(&mission_id);
+            let started = self.clock.now();
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+                self.run(connection_handle, grid, &mut agents, &mut missions)
+            }));
+            match outcome {
+                Ok(Ok(())) => return,
+                Ok(Err(err)) => {
+                    info!("Agent {} stopped: {}", self.id, err);
+                    return;
+                }
+                Err(_) => {}
+            }
+
+            if self.clock.now() - started >= Seconds(RESTARTS_FORGOTTEN_AFTER_S).ticks() {
+                restarts = 0;
+            }
+            if restarts >= MAX_AGENT_RESTARTS {
+                error!(
+                    "Agent {} crashed {} times, removing it from the simulation",
+                    self.id,
+                    restarts + 1
+                );
+                return;
+            }
+            restarts += 1;
+            warn!(
+                "Agent {} crashed, restarting it from its last broadcast state ({}/{})",
+                self.id, restarts, MAX_AGENT_RESTARTS
+            );
+            self.restore_last_broadcast();
+        }
+    }
+
+    fn restore_last_broadcast(&mut self) {
+        if let Some(state) = self.last_broadcast.clone() {
+            self.kinematics = state.kinematics;
+            self.mission = state.mission;
+        }
+    }
+
+    pub fn run(
+        &mut self,
+        connection_handle: &mut ConnectionHandle,
+        grid: &Grid,
+        agents: &mut HashMap<usize, AgentMessage>,
+        missions: &mut MissionPool,
+    ) -> Result<()> {
+        info!("Starting agent");
+        let mut inbox = Inbox::default();
+        let mut tick = 0usize;
+        let control_period = Duration::from_secs_f32(1.0 / self.control_rate_hz);
+        let mut last_rate_report = (Instant::now(), tick);
+        let dt = control_period.as_secs_f32();
+        let mut last_step = Instant::now();
+        let mut accumulator = Duration::from_secs(0);
+        let mut deadlines = self.load_shedding.clone().map(|shedding| {
+            DeadlineMonitor::new(
+                format!("agent.{}", self.id),
+                control_period,
+                shedding,
+                self.metrics.clone(),
+            )
+        });
+        let mut batches = 0usize;
+        loop {
+            let now = Instant::now();
+            let pending = accumulator + (now - last_step);
+            accumulator = pending.min(control_period * MAX_CONTROL_CATCHUP);
+            last_step = now;
+
+            let mut stepped = false;
+            while accumulator >= control_period {
+                accumulator -= control_period;
+                tick += 1;
+                self.simulate_motion(dt);
+                self.apply_boundary();
+                self.resolve_collisions(grid);
+                self.sanitize_kinematics("integration");
+                let perceived = self.perceive(grid, agents, missions);
+                let (seen_agents, seen_missions) = match &perceived {
+                    Some((seen_agents, seen_missions)) => (seen_agents, seen_missions),
+                    None => (&*agents, &*missions),
+                };
+                let observation = self.dataset.as_ref().map(|_| {
+                    self.observation
+                        .build(&self.state(), seen_agents, seen_missions, grid)
+                });
+                self.control(dt, grid, seen_agents);
+                self.sanitize_kinematics("control");
+                if self.maintain(dt) {
+                    self.schedule_reaction(&mut inbox.reaction_deadline);
+                }
+                if self.collect(grid) {
+                    self.schedule_reaction(&mut inbox.reaction_deadline);
+                }
+                if let (Some(dataset), Some(observation)) = (&self.dataset, observation) {
+                    let k = &self.kinematics;
+                    let action = Action {
+                        ax: k.a.x,
+                        ay: k.a.y,
+                        alpha: k.alpha,
+                    };
+                    let outcome = Outcome {
+                        mission: self.mission.as_ref().map(|m| m.id),
+                        completed: self.completed,
+                        distance_to_goal: self
+                            .mission
+                            .as_ref()
+                            .map(|m| (m.current_goal().0 - k.p).norm()),
+                    };
+                    if let Err(err) = dataset.record(&observation, &action, &outcome) {
+                        warn!("Agent {} could not record its step: {}", self.id, err);
+                    }
+                }
+                stepped = true;
+            }
+
+            if stepped {
+                batches += 1;
+                // How late the last due step ended, including any time dropped to catch up
+                if let Some(deadlines) = &mut deadlines {
+                    deadlines.record(pending.saturating_sub(control_period) + now.elapsed());
+                }
+            }
+            let shed = self
+                .load_shedding
+                .as_ref()
+                .is_some_and(|shedding| !batches.is_multiple_of(shedding.divider()));
+            if stepped && !shed {
+                let our_state = self.next_state();
+                if log_enabled!(Level::Trace) {
+                    let perceived = self.perceive(grid, agents, missions);
+                    let (seen_agents, seen_missions) = match &perceived {
+                        Some((seen_agents, seen_missions)) => (seen_agents, seen_missions),
+                        None => (&*agents, &*missions),
+                    };
+                    let observation =
+                        self.observation
+                            .build(&our_state, seen_agents, seen_missions, grid);
+                    trace!("Observation {:?}", observation);
+                }
+                debug!("Sending new state {:?}", our_state);
+                match self.misbehavior {
+                    None => connection_handle.tx.send(Message::Agent(our_state))?,
+                    Some(misbehavior) => {
+                        for state in Agent::misbehave(misbehavior, our_state, missions, tick) {
+                            connection_handle.tx.send(Message::Agent(state))?;
                         }
-                    },
+                    }
+                }
+            }
+            if stepped {
+                self.hand_off_if_worn(connection_handle)?;
+                self.report_anomalies(connection_handle)?;
+            }
+
+            let since_report = now - last_rate_report.0;
+            if since_report.as_secs_f32() >= TICK_RATE_REPORT_PERIOD_S {
+                self.metrics.set(
+                    &format!("agent.{}.tick_rate_hz", self.id),
+                    (tick - last_rate_report.1) as f64 / since_report.as_secs_f64(),
+                );
+                if self.load_shedding.is_some() {
+                    self.metrics.set(
+                        &format!("agent.{}.realtime_factor", self.id),
+                        (tick - last_rate_report.1) as f64 * dt as f64 / since_report.as_secs_f64(),
+                    );
+                }
+                last_rate_report = (now, tick);
+            }
+
+            let next_step = now + (control_period - accumulator);
+            loop {
+                let timeout = next_step.saturating_duration_since(Instant::now());
+                match connection_handle.rx.recv_timeout(timeout) {
+                    Ok(message) => self.receive(message, missions, agents, &mut inbox),
                     Err(err) => match err {
                         std::sync::mpsc::RecvTimeoutError::Timeout => {
                             debug!("Rx channel timed out");
                             break;
                         }
                         std::sync::mpsc::RecvTimeoutError::Disconnected => {
-                            error!("Could not retrieve message from channel")
+                            return Err(Error::Transport(
+                                "system manager closed the connection".to_owned(),
+                            ));
                         }
                     },
                 }
             }
 
-            self.check_missions(connection_handle, &missions, &mut agents);
+            // Decisions are made on what is in view, while what comes through communication, e.g.
+            // the plans of the others and connectivity, is known of everyone
+            let perceived = self.perceive(grid, agents, missions);
+            let (seen_agents, seen_missions) = match &perceived {
+                Some((seen_agents, seen_missions)) => (seen_agents, seen_missions),
+                None => (&*agents, &*missions),
+            };
+            self.decide(grid, seen_missions, seen_agents, &mut inbox, Instant::now());
+            self.update_connectivity(grid, agents);
+            self.update_idle_target(seen_agents);
+            self.planner.schedule(&self.state(), agents);
+        }
+    }
 
-            debug!("Current mission: {:?}", self.mission);
-            if let Some(mission) = &self.mission {
-                let k = &mut self.kinematics;
-                let m = mission.target - k.p;
-                let mut ppart = (2.0 / dt) * (m / dt);
-                if ppart.norm() > 2.0 * 100.0 {
-                    ppart *= 2.0 * 100.0 / ppart.norm();
+    // Takes in what the hub sent, and schedules a reaction to anything that may change our choice
+    // of mission
+    pub fn receive(
+        &mut self,
+        message: Message,
+        missions: &mut MissionPool,
+        agents: &mut HashMap<usize, AgentMessage>,
+        inbox: &mut Inbox,
+    ) {
+        match message {
+            Message::Mission(mission_message) => {
+                debug!("Received new mission: {:?}", mission_message);
+                if mission_message.tick < inbox.last_mission_tick {
+                    debug!(
+                        "Discarding missions from tick {} (already at {})",
+                        mission_message.tick, inbox.last_mission_tick
+                    );
+                    return;
+                }
+                inbox.last_mission_tick = mission_message.tick;
+                for m in mission_message.missions {
+                    // Known missions are only sent again when their priority changes
+                    if missions.get(m.id).is_none() {
+                        self.demand.record(&m.target);
+                    }
+                    missions.insert(m);
+                }
+                self.schedule_reaction(&mut inbox.reaction_deadline);
+            }
+            Message::WorldSync(sync) => {
+                info!(
+                    "Agent {} synced {} missions and {} agents",
+                    self.id,
+                    sync.missions.len(),
+                    sync.agents.len()
+                );
+                inbox.last_mission_tick = inbox.last_mission_tick.max(sync.tick);
+                for m in sync.missions {
+                    missions.insert(m);
                 }
-                let mut vpart = -(2.0 / dt) * k.v;
-                if vpart.norm() > 100.0 {
-                    vpart *= 100.0 / vpart.norm();
+                for state in sync.agents {
+                    agents.insert(state.id, state);
                 }
-                let a = ppart + vpart;
-                k.a = if a.norm() > 100.0 {
-                    a * 100.0 / a.norm()
+                self.schedule_reaction(&mut inbox.reaction_deadline);
+            }
+            Message::Agent(agent_message) => {
+                debug!("Updating info from agent {}", agent_message.id);
+                if let Arrival::AfterGap(lost) =
+                    inbox.sequences.observe(agent_message.id, agent_message.seq)
+                {
+                    debug!(
+                        "Agent {} missed {} states of agent {}",
+                        self.id, lost, agent_message.id
+                    );
+                    self.metrics
+                        .add(&format!("agent.{}.lost", self.id), lost as f64);
+                    self.metrics.set(
+                        &format!("agent.{}.loss_ratio", self.id),
+                        inbox.sequences.loss_ratio(),
+                    );
+                }
+                let stale = agents
+                    .get(&agent_message.id)
+                    .is_some_and(|known: &AgentMessage| known.tick > agent_message.tick);
+                if stale {
+                    debug!("Discarding stale state of agent {}", agent_message.id);
+                    return;
+                }
+                let own = self.mission.as_ref().map(|m| m.id);
+                if own.is_some() && agent_message.mission.as_ref().map(|m| m.id) == own {
+                    inbox.contested = true;
+                }
+                // Whatever the others carry has been picked up for good. Should two
+                // agents have picked up the same mission, the lowest id keeps it.
+                for carried in &agent_message.cargo {
+                    let ours = self.cargo.iter().any(|m| m.id == carried.id);
+                    if missions.get(carried.id).is_some() && (!ours || agent_message.id < self.id) {
+                        self.drop_mission(carried.id, missions, &mut inbox.reaction_deadline);
+                    }
+                }
+                agents.insert(agent_message.id, agent_message);
+            }
+            Message::MissionCancelled(mission_id) => {
+                self.drop_mission(mission_id, missions, &mut inbox.reaction_deadline);
+            }
+            Message::MissionCompleted { mission, by_agent } => {
+                if by_agent == self.id {
+                    self.completed += 1;
+                    info!(
+                        "Agent {} credited with mission {} ({} so far)",
+                        self.id, mission, self.completed
+                    );
+                }
+                self.drop_mission(mission, missions, &mut inbox.reaction_deadline);
+            }
+            Message::Urgent { mission, assignee } => {
+                if assignee == self.id {
+                    info!("Agent {} takes urgent mission {}", self.id, mission);
+                    self.route.clear();
+                    self.mission = Some(mission.clone());
+                    missions.insert(mission);
                 } else {
-                    a
-                };
-                debug!("dt:\t{}", dt);
-                debug!("target:\t{}", mission.target);
-                debug!("Acceleration:\t{}", k.a);
-                debug!("Position:\t{}", k.p);
-                debug!("Velocity:\t{}", k.v);
-            } else {
-                self.kinematics.a = Vector2::zeros();
-                debug!("New acceleration is null, because it has no associated mission",);
+                    if self.mission.as_ref().map(|m| m.id) == Some(mission.id) {
+                        info!(
+                            "Agent {} pre-empted on mission {} by agent {}",
+                            self.id, mission.id, assignee
+                        );
+                        self.mission = None;
+                        self.schedule_reaction(&mut inbox.reaction_deadline);
+                    }
+                    missions.remove(mission.id);
+                }
+            }
+            Message::SwitchAllocation(allocation) => {
+                if allocation != self.allocation || self.next_allocation.is_some() {
+                    self.next_allocation = Some(allocation);
+                    self.schedule_reaction(&mut inbox.reaction_deadline);
+                }
+            }
+            Message::Awarded { mission, agent } => {
+                let holding = self.mission.as_ref().map(|m| m.id) == Some(mission.id);
+                if agent == self.id {
+                    match &mut self.mission {
+                        Some(own) if holding => own.agent = mission.agent,
+                        _ => {
+                            info!("Agent {} was awarded mission {}", self.id, mission);
+                            self.route.clear();
+                            self.mission = Some(mission.clone());
+                        }
+                    }
+                    missions.insert(mission);
+                } else {
+                    if holding {
+                        info!(
+                            "Agent {} lost mission {} to agent {}",
+                            self.id, mission.id, agent
+                        );
+                        self.mission = None;
+                        self.schedule_reaction(&mut inbox.reaction_deadline);
+                    }
+                    missions.remove(mission.id);
+                }
+            }
+            Message::MissionProposal(_) | Message::Handoff(_) => {
+                debug!("Ignoring a message only the hub handles");
+            }
+        }
+    }
+
+    // Picks a mission once the reaction delay is over, and settles conflicts over the one we hold
+    pub fn decide(
+        &mut self,
+        grid: &Grid,
+        missions: &MissionPool,
+        agents: &HashMap<usize, AgentMessage>,
+        inbox: &mut Inbox,
+        now: Instant,
+    ) {
+        if inbox
+            .reaction_deadline
+            .is_some_and(|deadline| now >= deadline)
+        {
+            inbox.reaction_deadline = None;
+            self.get_new_mission(grid, missions, agents);
+        }
+        let own = self.mission.as_ref().map(|m| m.id);
+        if inbox.contested || own != inbox.checked_mission {
+            inbox.contested = false;
+            self.check_missions(missions, agents);
+            inbox.checked_mission = self.mission.as_ref().map(|m| m.id);
+        }
+    }
+
+    fn misbehave(
+        misbehavior: Misbehavior,
+        mut state: AgentMessage,
+        missions: &MissionPool,
+        tick: usize,
+    ) -> Vec<AgentMessage> {
+        match misbehavior {
+            Misbehavior::LieAboutPosition => {
+                let sign = if tick.is_multiple_of(2) { 1.0 } else { -1.0 };
+                state.kinematics.p += Vector2::repeat(sign * 5.0 * AGENT_RADIUS);
+                vec![state]
+            }
+            Misbehavior::PhantomClaims => {
+                state.mission = missions
+                    .iter()
+                    .filter(|m| Some(m.id) != state.mission.as_ref().map(|own| own.id))
+                    .min_by_key(|m| m.id)
+                    .cloned();
+                vec![state]
             }
+            Misbehavior::Spam => vec![state; SPAM_FACTOR],
+        }
+    }
 
-            let our_state = self.state();
-            debug!("Sending new state {:?}", our_state);
-            connection_handle.tx.send(our_state).unwrap();
+    fn drop_mission(
+        &mut self,
+        mission_id: usize,
+        missions: &mut MissionPool,
+        reaction_deadline: &mut Option<Instant>,
+    ) {
+        if self.mission.as_ref().map(|m| m.id) == Some(mission_id) {
+            self.mission = None;
+            self.schedule_reaction(reaction_deadline);
+        }
+        self.cargo.retain(|m| m.id != mission_id);
+        missions.remove(mission_id);
+    }
+
+    fn schedule_reaction(&self, reaction_deadline: &mut Option<Instant>) {
+        if reaction_deadline.is_none() {
+            *reaction_deadline = Some(Instant::now() + self.personality.reaction_delay);
         }
     }
 
     fn state(&self) -> AgentMessage {
         AgentMessage {
             id: self.id,
+            seq: self.seq,
+            tick: self.clock.now(),
             kinematics: self.kinematics.clone(),
             mission: self.mission.clone(),
+            wear: self.wear,
+            completed: self.completed,
+            plan: self.planner.intent(),
+            waypoints: self.planner.waypoints(),
+            cargo: self.cargo.clone(),
         }
     }
 
-    fn get_new_mission(
+    // The state to broadcast next, remembered in case it has to be restored
+    pub fn next_state(&mut self) -> AgentMessage {
+        self.seq += 1;
+        let state = self.state();
+        self.last_broadcast = Some(state.clone());
+        state
+    }
+
+    pub fn get_new_mission(
         &mut self,
-        missions: &HashMap<usize, Mission>,
-        _agents: &HashMap<usize, AgentMessage>,
+        grid: &Grid,
+        missions: &MissionPool,
+        agents: &HashMap<usize, AgentMessage>,
     ) {
-        let mut best_dist = std::f32::MAX;
-        let mut best_mission = None;
+        if self.in_maintenance {
+            debug!("In maintenance: not taking any mission");
+            return;
+        }
+
+        // Claims made under the previous strategy are honoured before switching
+        if let Some(next) = self.next_allocation {
+            if self.mission.is_some() {
+                debug!("Finishing the current mission before switching strategy");
+                return;
+            }
+            info!(
+                "Agent {} switches from {} to {} allocation",
+                self.id,
+                self.allocation.name(),
+                next.name()
+            );
+            self.allocation = next;
+            self.next_allocation = None;
+            self.route.clear();
+        }
+
+        if self
+            .mission
+            .as_ref()
+            .is_some_and(|m| m.urgent || m.visited || m.agent == Some(self.id))
+        {
+            debug!("Working on an urgent, assigned or half-done mission: not changing");
+            return;
+        }
+
+        // With cargo on board, only pickups for the same base that are closer than it are worth
+        // the detour
         let p = self.kinematics.p;
-        for mission in missions.values() {
-            let n = (p - mission.target).norm_squared();
+        let drop_off = self.cargo.first().map(|m| m.current_goal().0);
+        let candidates: Vec<&Mission> = missions
+            .iter()
+            .filter(|m| !self.keep_connected || self.is_connected_at(grid, &m.target, agents))
+            .filter(|m| self.can_make_in_time(m))
+            .filter(|m| {
+                drop_off.is_none_or(|base| {
+                    m.return_to == Some(base)
+                        && (m.target - p).norm_squared() < (base - p).norm_squared()
+                })
+            })
+            .collect();
+
+        if let AllocationStrategy::Clustered = self.allocation {
+            if self.get_clustered_mission(&candidates, agents) {
+                return;
+            }
+        }
+
+        let mut best_dist = f32::MAX;
+        let mut best_mission = None;
+        for mission in candidates {
+            let n = self.bid(mission) * self.routed_past_factor(mission, agents);
             if n < best_dist {
                 best_dist = n;
-                best_mission = Some(mission.clone())
+                best_mission = Some(mission)
             }
         }
 
-        match &self.mission {
-            Some(m) => {
-                let current_mission_cost = (m.target - p).norm_squared();
-                if current_mission_cost < best_dist {
-                    debug!("Current mission is closer than any other mission: not changing");
+        if let Some(m) = &self.mission {
+            let current_mission_cost = self.bid(m);
+            if current_mission_cost < best_dist {
+                debug!("Current mission is closer than any other mission: not changing");
 
-                    return;
-                }
+                return;
             }
-            None => {}
         }
 
         match &best_mission {
@@ -181,65 +1528,136 @@ impl Agent {
                 debug!("Has no mission");
             }
         }
-        self.mission = best_mission;
+        self.mission = best_mission.cloned();
         debug!("Chosen mission {:?}", self.mission);
     }
 
-    fn check_missions(
+    // Splits the pending missions in one cluster per agent, assigns clusters to agents greedily by
+    // distance to their centroid, and orders our own cluster into a route. Returns false if we did
+    // not get a cluster.
+    fn get_clustered_mission(
         &mut self,
-        _connection_handle: &mut ConnectionHandle,
-        missions: &HashMap<usize, Mission>,
-        agents: &mut HashMap<usize, AgentMessage>,
+        missions: &[&Mission],
+        agents: &HashMap<usize, AgentMessage>,
+    ) -> bool {
+        let mut pending = missions.to_vec();
+        pending.sort_by_key(|m| m.id);
+        let targets: Vec<_> = pending.iter().map(|m| m.target).collect();
+
+        let mut fleet: Vec<AgentMessage> = agents
+            .values()
+            .filter(|a| a.id != self.id)
+            .cloned()
+            .collect();
+        fleet.push(self.state());
+        fleet.sort_by_key(|a| a.id);
+
+        let k = fleet.len().min(targets.len());
+        let labels = kmeans(&targets, k, CLUSTERING_ITERATIONS);
+        let centroids = centroids(&targets, &labels, k);
+
+        let mut pairs = Vec::new();
+        for agent in &fleet {
+            for (cluster, centroid) in centroids.iter().enumerate() {
+                if let Some(centroid) = centroid {
+                    pairs.push((agent.cost_to(centroid), agent.id, cluster));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        let mut taken_agents = HashSet::new();
+        let mut taken_clusters = HashSet::new();
+        let mut own_cluster = None;
+        for (_, agent, cluster) in pairs {
+            if taken_agents.contains(&agent) || taken_clusters.contains(&cluster) {
+                continue;
+            }
+            taken_agents.insert(agent);
+            taken_clusters.insert(cluster);
+            if agent == self.id {
+                own_cluster = Some(cluster);
+                break;
+            }
+        }
+
+        let own_cluster = match own_cluster {
+            Some(cluster) => cluster,
+            None => {
+                self.route.clear();
+                return false;
+            }
+        };
+        let members: Vec<&Mission> = pending
+            .iter()
+            .zip(&labels)
+            .filter(|(_, &label)| label == own_cluster)
+            .map(|(m, _)| *m)
+            .collect();
+        let order = route_order(
+            self.kinematics.p,
+            &members.iter().map(|m| m.target).collect::<Vec<_>>(),
+        );
+        self.route = order.iter().map(|&i| members[i].id).collect();
+        self.mission = order.first().map(|&i| members[i].clone());
+        debug!("Chose cluster route {:?}", self.route);
+        true
+    }
+
+    pub fn check_missions(
+        &mut self,
+        missions: &MissionPool,
+        agents: &HashMap<usize, AgentMessage>,
     ) {
         let k = &self.kinematics;
         let mut assigned_missions = HashSet::new();
-        if let Some(curr_m) = &self.mission {
+        if let Some(curr_m) = self
+            .mission
+            .as_ref()
+            .filter(|m| !m.urgent && !m.visited && m.agent != Some(self.id))
+        {
             let mut reassign = false;
-            for (_, a) in agents.iter_mut() {
+            for a in agents.values() {
                 if a.id == self.id {
                     continue;
                 }
-                match &a.mission {
-                    Some(m) => {
-                        assigned_missions.insert(m.id);
-                        if m.id == curr_m.id {
-                            match missions.get(&m.id) {
-                                Some(other_mission) => {
-                                    let other_cost =
-                                        (other_mission.target - a.kinematics.p).norm_squared();
-                                    let my_cost = (missions[&m.id].target - k.p).norm_squared();
-                                    debug!(
-                                "Agent {} (cost {}) works on the same mission ({}) as us (our cost {})",
-                                a.id, other_cost, m.id , my_cost,
-                            );
-                                    reassign = my_cost > other_cost;
-                                    break;
-                                }
-                                None => warn!(
-                                    "Agent {} appears to still be working on mission {}",
-                                    a.id,
-                                    m.id
-                                ),
+                if let Some(m) = &a.mission {
+                    assigned_missions.insert(m.id);
+                    if m.id == curr_m.id {
+                        match missions.get(m.id) {
+                            Some(other_mission) => {
+                                let other_cost = a.cost_to(&other_mission.target);
+                                let my_cost = (other_mission.target - k.p).norm_squared()
+                                    / capability(self.wear).powi(2);
+                                debug!(
+                            "Agent {} (cost {}) works on the same mission ({}) as us (our cost {})",
+                            a.id, other_cost, m.id , my_cost,
+                        );
+                                reassign = m.visited
+                                    || my_cost > other_cost * self.personality.aggressiveness;
+                                break;
                             }
+                            None => warn!(
+                                "Agent {} appears to still be working on mission {}",
+                                a.id, m.id
+                            ),
                         }
                     }
-                    None => {}
                 }
             }
 
             debug!("Is looking for a new mission: {}", reassign);
             if reassign {
-                let mut best_score = std::f32::MAX;
+                let mut best_score = f32::MAX;
                 let mut best_mission = None;
-                for m in missions.values() {
-                    if assigned_missions.contains(&m.id) {
+                for m in missions.iter() {
+                    if assigned_missions.contains(&m.id) || !self.can_make_in_time(m) {
                         continue;
                     }
 
-                    let score = (k.p - m.target).norm_squared();
+                    let score = self.bid(m);
                     if score < best_score {
                         best_score = score;
-                        best_mission = Some(m.clone());
+                        best_mission = Some(m);
                     }
                 }
 
@@ -247,8 +1665,123 @@ impl Agent {
                     Some(bm) => debug!("Reassigned itself to {}", bm),
                     None => debug!("Did not reassign itself"),
                 };
-                self.mission = best_mission;
+                self.mission = best_mission.cloned();
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 20;
+
+    fn grid_with(blocked: &[(usize, usize)]) -> Grid {
+        let mut cells: Vec<Cell> = (0..WIDTH * WIDTH).map(|_| Cell::Crossable(1.0)).collect();
+        for &(col, row) in blocked {
+            cells[row * WIDTH + col] = Cell::Uncrossable;
+        }
+        Grid {
+            cells,
+            width: WIDTH,
+            obstacles: Vec::new(),
+            roads: None,
+            labels: HashMap::new(),
+        }
+    }
+
+    fn center(col: usize, row: usize) -> Vector2<f32> {
+        Vector2::new(col as f32, row as f32) * CELL_SIZE - Vector2::repeat(GRID_HALF_SIZE)
+    }
+
+    fn sees(grid: &Grid, a: Vector2<f32>, b: Vector2<f32>) -> bool {
+        let seen = grid.line_of_sight(&a, &b);
+        assert_eq!(seen, grid.line_of_sight(&b, &a), "{} <-> {}", a, b);
+        seen
+    }
+
+    #[test]
+    fn diagonal_rays_are_cut_by_the_cells_they_cross() {
+        let (a, b) = (center(2, 2), center(8, 8));
+        assert!(sees(&grid_with(&[]), a, b));
+        assert!(!sees(&grid_with(&[(5, 5)]), a, b));
+        assert!(sees(&grid_with(&[(6, 3), (3, 6)]), a, b));
+
+        let (c, d) = (center(2, 2), center(8, 5));
+        assert!(!sees(&grid_with(&[(5, 3)]), c, d));
+        assert!(sees(&grid_with(&[(5, 5), (5, 2)]), c, d));
+    }
+
+    // A ray going exactly through the corner of a blocked cell sees nothing past it
+    #[test]
+    fn rays_grazing_a_blocked_corner_are_cut() {
+        let (a, b) = (center(2, 2), center(8, 8));
+        assert!(!sees(&grid_with(&[(5, 4)]), a, b));
+        assert!(!sees(&grid_with(&[(4, 5)]), a, b));
+
+        let (c, d) = (center(2, 8), center(8, 2));
+        assert!(!sees(&grid_with(&[(5, 5)]), c, d));
+        assert!(!sees(&grid_with(&[(4, 5)]), c, d));
+        assert!(sees(&grid_with(&[(4, 4)]), c, d));
+    }
+
+    #[test]
+    fn rays_ending_inside_an_obstacle_are_cut() {
+        let grid = grid_with(&[(6, 2)]);
+        assert!(!sees(&grid, center(2, 2), center(6, 2)));
+        let inside = center(6, 2) + Vector2::new(0.3, -0.2) * CELL_SIZE;
+        assert!(!sees(&grid, center(2, 5), inside));
+        assert!(sees(&grid, center(2, 2), center(5, 2)));
+    }
+
+    #[test]
+    fn cells_off_the_grid_block_nothing() {
+        let grid = grid_with(&[]);
+        assert!(sees(
+            &grid,
+            center(2, 2),
+            center(2, 2) - Vector2::repeat(50.0)
+        ));
+        assert!(sees(&grid, center(2, 2), center(30, 2)));
+    }
+
+    fn cost(grid: &Grid, col: usize, row: usize) -> Option<f32> {
+        match grid.cell_at(&center(col, row))? {
+            Cell::Crossable(cost) => Some(*cost),
+            Cell::Uncrossable => None,
+        }
+    }
+
+    #[test]
+    fn later_patches_are_painted_over_earlier_ones() {
+        let mut grid = grid_with(&[]);
+        let at = |col, row| {
+            let p = center(col, row);
+            [p.x, p.y]
+        };
+        grid.paint(Rect::new(at(2, 2), at(6, 6)), Some(5.0));
+        grid.paint(Rect::new(at(4, 4), at(8, 8)), None);
+        grid.paint(Rect::new(at(5, 5), at(5, 5)), Some(2.0));
+        assert_eq!(cost(&grid, 3, 3), Some(5.0));
+        assert_eq!(cost(&grid, 4, 4), None);
+        assert_eq!(cost(&grid, 5, 5), Some(2.0));
+        assert_eq!(cost(&grid, 6, 6), None);
+        assert_eq!(cost(&grid, 9, 9), Some(1.0));
+    }
+
+    #[test]
+    fn patches_are_clipped_to_the_grid() {
+        let mut grid = grid_with(&[]);
+        let corner = center(1, 1);
+        grid.paint(
+            Rect::new([-1000.0, -1000.0], [corner.x, corner.y]),
+            Some(3.0),
+        );
+        assert_eq!(cost(&grid, 0, 0), Some(3.0));
+        assert_eq!(cost(&grid, 1, 0), Some(3.0));
+        assert_eq!(cost(&grid, 1, 1), Some(3.0));
+        assert_eq!(cost(&grid, 2, 1), Some(1.0));
+        assert_eq!(cost(&grid, 2, 2), Some(1.0));
+    }
+}
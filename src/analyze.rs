@@ -0,0 +1,293 @@
+use crate::error::{Error, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+const PANEL_WIDTH: f32 = 240.0;
+const PANEL_HEIGHT: f32 = 200.0;
+const BAR_WIDTH: f32 = 24.0;
+
+// Two-sided 95% quantiles of Student's t distribution for 1 to 30 degrees of freedom
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+const Z_95: f64 = 1.96;
+
+const METRICS: [&str; 6] = [
+    "completed/min",
+    "completion ratio",
+    "completion time s",
+    "abandoned",
+    "distance/mission",
+    "reward/min",
+];
+
+// The parts of summary.json the comparison needs
+#[derive(Deserialize)]
+struct RunRecord {
+    mode: String,
+    // Missing from summaries written before the strategy was recorded
+    #[serde(default)]
+    strategy: Option<String>,
+    #[serde(default)]
+    strategy_switches: Vec<SwitchRecord>,
+    duration_s: f64,
+    agents: Vec<AgentRecord>,
+    totals: TotalsRecord,
+}
+
+#[derive(Deserialize)]
+struct SwitchRecord {
+    strategy: String,
+}
+
+#[derive(Deserialize)]
+struct AgentRecord {
+    missions_abandoned: usize,
+    distance: f64,
+}
+
+#[derive(Deserialize)]
+struct TotalsRecord {
+    missions_created: usize,
+    missions_completed: usize,
+    mean_completion_time_s: Option<f64>,
+    // Missing from summaries written before missions had rewards
+    #[serde(default)]
+    reward: Option<f64>,
+}
+
+impl RunRecord {
+    fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        serde_json::from_reader(file)
+            .map_err(|err| Error::Config(format!("invalid summary {}: {}", path.display(), err)))
+    }
+
+    // Runs that switched strategy are compared as their own group, e.g. centralized/nearest>clustered
+    fn group(&self) -> String {
+        let mut group = format!(
+            "{}/{}",
+            self.mode,
+            self.strategy.as_deref().unwrap_or("unknown")
+        );
+        for switch in &self.strategy_switches {
+            group.push('>');
+            group.push_str(&switch.strategy);
+        }
+        group
+    }
+
+    // In the order of METRICS; None when the run has nothing to measure it on
+    fn metrics(&self) -> [Option<f64>; 6] {
+        let completed = self.totals.missions_completed as f64;
+        let ratio = |num: f64, den: f64| if den > 0.0 { Some(num / den) } else { None };
+        [
+            ratio(60.0 * completed, self.duration_s),
+            ratio(completed, self.totals.missions_created as f64),
+            self.totals.mean_completion_time_s,
+            Some(
+                self.agents
+                    .iter()
+                    .map(|a| a.missions_abandoned as f64)
+                    .sum(),
+            ),
+            ratio(self.agents.iter().map(|a| a.distance).sum(), completed),
+            self.totals
+                .reward
+                .and_then(|reward| ratio(60.0 * reward, self.duration_s)),
+        ]
+    }
+}
+
+struct Estimate {
+    mean: f64,
+    // Half width of the 95% confidence interval of the mean, none with a single sample
+    half_width: Option<f64>,
+}
+
+fn estimate(samples: &[f64]) -> Option<Estimate> {
+    if samples.is_empty() {
+        return None;
+    }
+    let n = samples.len();
+    let mean = samples.iter().sum::<f64>() / n as f64;
+    let half_width = if n > 1 {
+        let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+        let t = T_95.get(n - 2).copied().unwrap_or(Z_95);
+        Some(t * (variance / n as f64).sqrt())
+    } else {
+        None
+    };
+    Some(Estimate { mean, half_width })
+}
+
+fn format_estimate(estimate: &Option<Estimate>) -> String {
+    match estimate {
+        Some(Estimate {
+            mean,
+            half_width: Some(half_width),
+        }) => format!("{:.2} ± {:.2}", mean, half_width),
+        Some(Estimate {
+            mean,
+            half_width: None,
+        }) => format!("{:.2}", mean),
+        None => "-".to_owned(),
+    }
+}
+
+// Per group (mode/strategy): number of runs and the estimate of each metric
+type Comparison = BTreeMap<String, (usize, Vec<Option<Estimate>>)>;
+
+pub fn run_analysis(paths: &[String], plot: Option<&Path>) -> Result<()> {
+    if paths.is_empty() {
+        return Err(Error::Config(
+            "analyze needs at least one summary file".to_owned(),
+        ));
+    }
+    let mut groups: BTreeMap<String, Vec<[Option<f64>; 6]>> = BTreeMap::new();
+    for path in paths {
+        let run = RunRecord::load(Path::new(path))?;
+        groups.entry(run.group()).or_default().push(run.metrics());
+    }
+    let comparison: Comparison = groups
+        .into_iter()
+        .map(|(group, runs)| {
+            let estimates = (0..METRICS.len())
+                .map(|i| estimate(&runs.iter().filter_map(|m| m[i]).collect::<Vec<_>>()))
+                .collect();
+            (group, (runs.len(), estimates))
+        })
+        .collect();
+
+    print!("{}", render_table(&comparison));
+    if let Some(path) = plot {
+        std::fs::write(path, render_plot(&comparison))?;
+        println!("Wrote comparison plot to {}", path.display());
+    }
+    Ok(())
+}
+
+fn render_table(comparison: &Comparison) -> String {
+    let mut rows = vec![std::iter::once("group".to_owned())
+        .chain(std::iter::once("runs".to_owned()))
+        .chain(METRICS.iter().map(|m| m.to_string()))
+        .collect::<Vec<_>>()];
+    for (group, (runs, estimates)) in comparison {
+        rows.push(
+            std::iter::once(group.clone())
+                .chain(std::iter::once(runs.to_string()))
+                .chain(estimates.iter().map(format_estimate))
+                .collect(),
+        );
+    }
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|col| {
+            rows.iter()
+                .map(|r| r[col].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut table = String::new();
+    for (i, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        let _ = writeln!(table, "{}", cells.join(" | "));
+        if i == 0 {
+            let rule: Vec<String> = widths.iter().map(|&w| "-".repeat(w)).collect();
+            let _ = writeln!(table, "{}", rule.join("-|-"));
+        }
+    }
+    table
+}
+
+// One panel per metric, with a bar per group and its confidence interval as whiskers
+fn render_plot(comparison: &Comparison) -> String {
+    let groups: Vec<&String> = comparison.keys().collect();
+    let width = PANEL_WIDTH * METRICS.len() as f32;
+    let height = PANEL_HEIGHT + 20.0 * (groups.len() as f32 + 1.0);
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="10">"#,
+        width, height
+    );
+    let color = |i: usize| format!("hsl({},60%,50%)", i * 360 / groups.len().max(1));
+    for (m, metric) in METRICS.iter().enumerate() {
+        let x0 = m as f32 * PANEL_WIDTH;
+        let top = comparison
+            .values()
+            .filter_map(|(_, e)| e[m].as_ref())
+            .map(|e| e.mean + e.half_width.unwrap_or(0.0))
+            .fold(0.0, f64::max);
+        let scale = if top > 0.0 {
+            (PANEL_HEIGHT - 40.0) as f64 / top
+        } else {
+            0.0
+        };
+        let base = PANEL_HEIGHT - 20.0;
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="12">{}</text><line x1="{}" y1="{base}" x2="{}" y2="{base}" stroke="black"/>"#,
+            x0 + 10.0,
+            metric,
+            x0 + 10.0,
+            x0 + PANEL_WIDTH - 10.0,
+            base = base
+        );
+        for (g, (_, estimates)) in comparison.values().enumerate() {
+            let estimate = match &estimates[m] {
+                Some(estimate) => estimate,
+                None => continue,
+            };
+            let x = x0 + 20.0 + g as f32 * (BAR_WIDTH + 6.0);
+            let h = (estimate.mean * scale) as f32;
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"><title>{}: {:.3}</title></rect>"#,
+                x,
+                base - h,
+                BAR_WIDTH,
+                h,
+                color(g),
+                groups[g],
+                estimate.mean
+            );
+            if let Some(half_width) = estimate.half_width {
+                let cx = x + BAR_WIDTH / 2.0;
+                let low = base - ((estimate.mean - half_width).max(0.0) * scale) as f32;
+                let high = base - ((estimate.mean + half_width) * scale) as f32;
+                let _ = writeln!(
+                    svg,
+                    r#"<line x1="{cx}" y1="{}" x2="{cx}" y2="{}" stroke="black"/><line x1="{}" y1="{high}" x2="{}" y2="{high}" stroke="black"/>"#,
+                    low,
+                    high,
+                    cx - 4.0,
+                    cx + 4.0,
+                    cx = cx,
+                    high = high
+                );
+            }
+        }
+    }
+    for (g, group) in groups.iter().enumerate() {
+        let y = PANEL_HEIGHT + 20.0 * (g as f32 + 1.0);
+        let _ = writeln!(
+            svg,
+            r#"<rect x="10" y="{}" width="10" height="10" fill="{}"/><text x="26" y="{}">{}</text>"#,
+            y - 9.0,
+            color(g),
+            y,
+            group
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -0,0 +1,89 @@
+use crate::consts::{APPROACH_GAIN, BRAKING_SHARE, VELOCITY_RETAINED_PER_S};
+use crate::units::{Meters, MetersPerSecond, MetersPerSecondSquared, Seconds};
+use nalgebra::Vector2;
+
+// Acceleration heading for the waypoint at the highest speed the agent can still stop from, using
+// BRAKING_SHARE of its acceleration, before the end of its path; remaining is the length of the
+// path left after the waypoint. Close to the end the speed is also bounded by APPROACH_GAIN times
+// the distance left, so that the agent settles on its goal instead of overshooting and orbiting
+// it. The desired velocity is reached within a tick when the cap allows, friction included.
+pub fn acceleration(
+    p: &Vector2<f32>,
+    v: &Vector2<f32>,
+    waypoint: &Vector2<f32>,
+    remaining: Meters,
+    max_speed: MetersPerSecond,
+    max_acceleration: MetersPerSecondSquared,
+    dt: Seconds,
+) -> Vector2<f32> {
+    let d = waypoint - p;
+    let distance = d.norm();
+    let left = distance + remaining.0;
+    let braking = BRAKING_SHARE * max_acceleration.0;
+    let gain = APPROACH_GAIN.min(1.0 / dt.0);
+    let speed = max_speed
+        .0
+        .min((2.0 * braking * left).sqrt())
+        .min(gain * left);
+    let desired = if distance > 0.0 {
+        d * (speed / distance)
+    } else {
+        Vector2::zeros()
+    };
+    let a = (desired - VELOCITY_RETAINED_PER_S.powf(dt.0) * v) / dt.0;
+    if a.norm() > max_acceleration.0 {
+        a * max_acceleration.0 / a.norm()
+    } else {
+        a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::{CONTROL_RATE_HZ, DISTANCE_TO_TARGET, MAX_ACCELERATION, MAX_SPEED};
+
+    // Integrated as Agent::simulate_motion does; returns the tick the agent came to rest within
+    // DISTANCE_TO_TARGET of the goal, and how far past the goal it went along the way
+    fn settle(offset: Vector2<f32>, ticks: usize) -> (Option<usize>, f32) {
+        let dt = 1.0 / CONTROL_RATE_HZ;
+        let friction = VELOCITY_RETAINED_PER_S.ln();
+        let goal = Vector2::zeros();
+        let direction = -offset.normalize();
+        let (mut p, mut v) = (offset, Vector2::zeros());
+        let mut overshoot: f32 = 0.0;
+        for tick in 0..ticks {
+            let a = acceleration(
+                &p,
+                &v,
+                &goal,
+                Meters(0.0),
+                MAX_SPEED,
+                MAX_ACCELERATION,
+                Seconds(dt),
+            );
+            p += dt * (v + dt * a / 2.0);
+            v = dt * a + (dt * friction).exp() * v;
+            if v.norm() > MAX_SPEED.0 {
+                v *= MAX_SPEED.0 / v.norm();
+            }
+            overshoot = overshoot.max((p - goal).dot(&direction));
+            if (p - goal).norm() < DISTANCE_TO_TARGET && v.norm() < 0.1 {
+                return (Some(tick), overshoot);
+            }
+        }
+        (None, overshoot)
+    }
+
+    // 4 s at CONTROL_RATE_HZ from 200 m away, and 2.5 s from 50 m away
+    #[test]
+    fn settles_on_the_goal_without_overshooting() {
+        let (settled, overshoot) = settle(Vector2::new(200.0, 0.0), 200);
+        assert!(settled.is_some());
+        assert!(overshoot <= 0.0, "went {}m past the goal", overshoot);
+
+        let (settled, overshoot) = settle(Vector2::new(-30.0, 40.0), 125);
+        assert!(settled.is_some());
+        assert!(overshoot <= 0.0, "went {}m past the goal", overshoot);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::agent::Grid;
+use crate::consts::*;
+use crate::error::{Error, Result};
+use crate::render_sink::NullSink;
+use crate::system::{CoordinationMode, SystemManager};
+use crate::{init_agent_kinematics, start_simulation};
+use log::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn accepted_messages(snapshot: &BTreeMap<String, f64>) -> f64 {
+    snapshot
+        .iter()
+        .filter(|(name, _)| name.ends_with(".accepted"))
+        .map(|(_, value)| value)
+        .sum()
+}
+
+fn measure_tick_rate(grid: &Arc<Grid>, n_agents: usize) -> Result<f64> {
+    let mut system = SystemManager::new(
+        grid.clone(),
+        Arc::new(NullSink),
+        CoordinationMode::Centralized,
+    );
+    system.disable_reports();
+    let metrics = system.metrics();
+    let (shutdown, system_thread) =
+        start_simulation(system, grid, init_agent_kinematics(n_agents))?;
+
+    std::thread::sleep(Duration::from_secs_f32(CAPACITY_WARMUP_S));
+    let before = accepted_messages(&metrics.snapshot());
+    std::thread::sleep(Duration::from_secs_f32(CAPACITY_ROUND_S));
+    let after = accepted_messages(&metrics.snapshot());
+
+    shutdown.store(true, Ordering::Relaxed);
+    system_thread
+        .join()
+        .map_err(|_| Error::Transport("system manager panicked".to_owned()))??;
+    Ok((after - before) / n_agents as f64 / CAPACITY_ROUND_S as f64)
+}
+
+pub fn run_capacity_benchmark(grid: &Arc<Grid>) -> Result<()> {
+    let min_tick_rate = CAPACITY_MIN_TICK_RATIO * CONTROL_RATE_HZ;
+    let mut supported = 0;
+    let mut n_agents = CAPACITY_START_AGENTS;
+    while n_agents <= CAPACITY_MAX_AGENTS {
+        let tick_rate = measure_tick_rate(grid, n_agents)?;
+        info!(
+            "{} agents: {:.1} ticks per second per agent",
+            n_agents, tick_rate
+        );
+        if tick_rate < min_tick_rate as f64 {
+            break;
+        }
+        supported = n_agents;
+        n_agents *= 2;
+    }
+
+    println!(
+        "Supported fleet size: {} agents (each ticking at >= {:.0} Hz)",
+        supported, min_tick_rate
+    );
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use crate::agent::AgentMessage;
+use crate::missions::Mission;
+use log::*;
+use std::collections::HashMap;
+
+// Settles, at the hub, missions several agents claim at once, as the gossip leader does among its
+// peers: the cheapest claimant is granted the mission and the others are told to let go of it.
+// Agents keep what they are granted until it is over, so nobody is granted a second mission
+// before that.
+#[derive(Default)]
+pub struct ClaimArbiter {
+    // Agent each mission was granted to, until it is no longer pending
+    grants: HashMap<usize, usize>,
+}
+
+impl ClaimArbiter {
+    pub fn new() -> Self {
+        ClaimArbiter::default()
+    }
+
+    // Missions the hub hands out itself, e.g. proposed or urgent ones, are not up for grabs either
+    pub fn grant(&mut self, mission_id: usize, agent: usize) {
+        self.grants.insert(mission_id, agent);
+    }
+
+    // Awards to send for the state, which `states` already holds: the mission it claims if it is
+    // now contested, or if it was granted to another agent. `pending` looks up the missions not
+    // completed yet.
+    pub fn observe(
+        &mut self,
+        state: &AgentMessage,
+        states: &HashMap<usize, AgentMessage>,
+        pending: impl Fn(usize) -> Option<Mission>,
+    ) -> Vec<(Mission, usize)> {
+        self.grants
+            .retain(|&mission_id, _| pending(mission_id).is_some());
+        let claimed = match state.mission.as_ref().and_then(|m| pending(m.id)) {
+            Some(claimed) => claimed,
+            None => return Vec::new(),
+        };
+        let winner = match self.grants.get(&claimed.id) {
+            Some(&agent) if agent == state.id => return Vec::new(),
+            Some(&agent) => agent,
+            None => {
+                let claimants: Vec<&AgentMessage> = states
+                    .values()
+                    .filter(|s| s.mission.as_ref().map(|m| m.id) == Some(claimed.id))
+                    .collect();
+                if claimants.len() < 2 {
+                    return Vec::new();
+                }
+                let winner = claimants
+                    .into_iter()
+                    .filter(|s| !self.grants.values().any(|&agent| agent == s.id))
+                    .min_by(|a, b| {
+                        a.cost_to(&claimed.target)
+                            .partial_cmp(&b.cost_to(&claimed.target))
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                            .then(a.id.cmp(&b.id))
+                    });
+                match winner {
+                    Some(winner) => winner.id,
+                    None => return Vec::new(),
+                }
+            }
+        };
+        info!("Mission {} granted to agent {}", claimed.id, winner);
+        self.grants.insert(claimed.id, winner);
+        vec![(
+            Mission {
+                agent: Some(winner),
+                ..claimed
+            },
+            winner,
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::Kinematics;
+    use nalgebra::Vector2;
+
+    fn mission(id: usize) -> Mission {
+        Mission {
+            id,
+            agent: None,
+            target: Vector2::zeros(),
+            target_theta: None,
+            urgent: false,
+            priority: 0,
+            return_to: None,
+            visited: false,
+            group: None,
+            window: None,
+            camera: None,
+            created: 0,
+            reward: None,
+        }
+    }
+
+    fn claim(id: usize, x: f32, mission_id: usize) -> AgentMessage {
+        AgentMessage {
+            id,
+            seq: 1,
+            tick: 0,
+            kinematics: Kinematics {
+                p: Vector2::new(x, 0.0),
+                v: Vector2::zeros(),
+                a: Vector2::zeros(),
+                theta: 0.0,
+                omega: 0.0,
+                alpha: 0.0,
+                radius: 1.0,
+            },
+            mission: Some(mission(mission_id)),
+            wear: 0.0,
+            completed: 0,
+            plan: Vec::new(),
+            waypoints: Vec::new(),
+            cargo: Vec::new(),
+        }
+    }
+
+    fn observe(
+        arbiter: &mut ClaimArbiter,
+        states: &mut HashMap<usize, AgentMessage>,
+        state: AgentMessage,
+        pending: &[usize],
+    ) -> Vec<(usize, usize)> {
+        states.insert(state.id, state.clone());
+        arbiter
+            .observe(&state, states, |id| {
+                pending.contains(&id).then(|| mission(id))
+            })
+            .into_iter()
+            .map(|(m, agent)| {
+                assert_eq!(m.agent, Some(agent));
+                (m.id, agent)
+            })
+            .collect()
+    }
+
+    // The cheapest claimant gets the mission, and the others are reminded of it as long as their
+    // claim stands
+    #[test]
+    fn contested_missions_go_to_the_cheapest_claimant() {
+        let mut arbiter = ClaimArbiter::new();
+        let mut states = HashMap::new();
+        assert!(observe(&mut arbiter, &mut states, claim(0, 50.0, 7), &[7]).is_empty());
+        assert_eq!(
+            observe(&mut arbiter, &mut states, claim(1, 10.0, 7), &[7]),
+            vec![(7, 1)]
+        );
+        assert!(observe(&mut arbiter, &mut states, claim(1, 5.0, 7), &[7]).is_empty());
+        assert_eq!(
+            observe(&mut arbiter, &mut states, claim(0, 40.0, 7), &[7]),
+            vec![(7, 1)]
+        );
+
+        // Once completed, the mission is nobody's anymore
+        assert!(observe(&mut arbiter, &mut states, claim(0, 40.0, 7), &[]).is_empty());
+    }
+
+    // An agent granted a mission does not win another one before the first is over
+    #[test]
+    fn agents_hold_a_single_grant() {
+        let mut arbiter = ClaimArbiter::new();
+        let mut states = HashMap::new();
+        arbiter.grant(3, 0);
+        observe(&mut arbiter, &mut states, claim(1, 30.0, 7), &[3, 7]);
+        assert_eq!(
+            observe(&mut arbiter, &mut states, claim(0, 0.0, 7), &[3, 7]),
+            vec![(7, 1)]
+        );
+        assert_eq!(
+            observe(&mut arbiter, &mut states, claim(0, 0.0, 3), &[3]),
+            Vec::new()
+        );
+    }
+}
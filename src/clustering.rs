@@ -0,0 +1,154 @@
+use nalgebra::Vector2;
+
+// Deterministic k-means: centroids are seeded by farthest-point traversal from the first point
+pub fn kmeans(points: &[Vector2<f32>], k: usize, iterations: usize) -> Vec<usize> {
+    if points.is_empty() || k == 0 {
+        return vec![0; points.len()];
+    }
+
+    let mut centroids = vec![points[0]];
+    while centroids.len() < k.min(points.len()) {
+        let farthest = points
+            .iter()
+            .max_by(|a, b| {
+                let da = distance_to_closest(a, &centroids);
+                let db = distance_to_closest(b, &centroids);
+                da.partial_cmp(&db).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap();
+        centroids.push(*farthest);
+    }
+
+    let mut labels = vec![0; points.len()];
+    for _ in 0..iterations {
+        let mut changed = false;
+        for (label, p) in labels.iter_mut().zip(points) {
+            let closest = closest(p, &centroids);
+            changed |= *label != closest;
+            *label = closest;
+        }
+
+        for (i, centroid) in centroids.iter_mut().enumerate() {
+            let members: Vec<_> = points
+                .iter()
+                .zip(&labels)
+                .filter(|(_, &label)| label == i)
+                .map(|(p, _)| p)
+                .collect();
+            if !members.is_empty() {
+                *centroid = members.iter().copied().sum::<Vector2<f32>>() / members.len() as f32;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+    labels
+}
+
+pub fn centroids(points: &[Vector2<f32>], labels: &[usize], k: usize) -> Vec<Option<Vector2<f32>>> {
+    let mut sums = vec![(Vector2::zeros(), 0); k];
+    for (p, &label) in points.iter().zip(labels) {
+        sums[label].0 += p;
+        sums[label].1 += 1;
+    }
+    sums.into_iter()
+        .map(|(sum, n)| if n == 0 { None } else { Some(sum / n as f32) })
+        .collect()
+}
+
+// Greedy nearest-neighbour ordering of the points, starting from `start`
+pub fn route_order(start: Vector2<f32>, points: &[Vector2<f32>]) -> Vec<usize> {
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut order = Vec::with_capacity(points.len());
+    let mut current = start;
+    while !remaining.is_empty() {
+        let next = closest(
+            &current,
+            &remaining.iter().map(|&i| points[i]).collect::<Vec<_>>(),
+        );
+        let index = remaining.remove(next);
+        current = points[index];
+        order.push(index);
+    }
+    order
+}
+
+fn closest(p: &Vector2<f32>, candidates: &[Vector2<f32>]) -> usize {
+    candidates
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| {
+            (*a - p)
+                .norm_squared()
+                .partial_cmp(&(*b - p).norm_squared())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+fn distance_to_closest(p: &Vector2<f32>, candidates: &[Vector2<f32>]) -> f32 {
+    candidates
+        .iter()
+        .map(|c| (c - p).norm_squared())
+        .fold(f32::MAX, f32::min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::SEED;
+    use rand::Rng;
+    use rand_pcg::Pcg64;
+
+    fn random_points(n: usize) -> Vec<Vector2<f32>> {
+        let mut rng = Pcg64::new(SEED, 0);
+        (0..n)
+            .map(|_| Vector2::new(rng.gen_range(-100.0..100.0), rng.gen_range(-100.0..100.0)))
+            .collect()
+    }
+
+    #[test]
+    fn more_clusters_than_points_puts_each_point_alone() {
+        let points = random_points(4);
+        let mut labels = kmeans(&points, 10, 20);
+        labels.sort_unstable();
+        assert_eq!(labels, vec![0, 1, 2, 3]);
+        assert!(kmeans(&[], 3, 20).is_empty());
+    }
+
+    #[test]
+    fn duplicate_points_share_a_cluster() {
+        let a = Vector2::new(-50.0, 0.0);
+        let b = Vector2::new(50.0, 0.0);
+        let points = vec![a, a, b, a, b];
+        let labels = kmeans(&points, 3, 20);
+        assert!(labels.iter().all(|&label| label < 3));
+        assert_eq!(labels[0], labels[1]);
+        assert_eq!(labels[0], labels[3]);
+        assert_eq!(labels[2], labels[4]);
+        assert_ne!(labels[0], labels[2]);
+    }
+
+    #[test]
+    fn same_points_give_the_same_clusters() {
+        let labels = kmeans(&random_points(200), 5, 50);
+        assert_eq!(labels, kmeans(&random_points(200), 5, 50));
+        let centers = centroids(&random_points(200), &labels, 5);
+        assert!(centers.iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn route_visits_the_nearest_point_first() {
+        let points = vec![
+            Vector2::new(30.0, 0.0),
+            Vector2::new(-5.0, 0.0),
+            Vector2::new(10.0, 0.0),
+            Vector2::new(-40.0, 0.0),
+        ];
+        assert_eq!(route_order(Vector2::zeros(), &points), vec![1, 2, 0, 3]);
+        assert!(route_order(Vector2::zeros(), &[]).is_empty());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::consts::{
+    CONGESTION_COST_WEIGHT, CONGESTION_REGION_SIZE, CONGESTION_SLOWDOWN, CONGESTION_THRESHOLD,
+    GRID_HALF_SIZE,
+};
+use nalgebra::Vector2;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Number of agents in every square region of CONGESTION_REGION_SIZE, published by the system
+// manager. Crowded regions slow agents down and are avoided by the planners. Nothing is published
+// unless congestion is enabled, which leaves every region empty.
+#[derive(Clone, Default)]
+pub struct Congestion {
+    counts: Arc<Mutex<HashMap<(i32, i32), usize>>>,
+}
+
+fn region_of(p: &Vector2<f32>) -> (i32, i32) {
+    let index = |c: f32| ((c + GRID_HALF_SIZE) / CONGESTION_REGION_SIZE).floor() as i32;
+    (index(p.x), index(p.y))
+}
+
+impl Congestion {
+    pub fn new() -> Self {
+        Congestion::default()
+    }
+
+    // Returns the highest number of agents in a region
+    pub fn publish(&self, positions: impl Iterator<Item = Vector2<f32>>) -> usize {
+        let mut counts = HashMap::new();
+        for p in positions {
+            *counts.entry(region_of(&p)).or_insert(0) += 1;
+        }
+        let max = counts.values().copied().max().unwrap_or(0);
+        *self.counts.lock().unwrap() = counts;
+        max
+    }
+
+    pub fn density_at(&self, p: &Vector2<f32>) -> usize {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(&region_of(p))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // Fraction of its top speed an agent at p can drive at, given the others around it
+    pub fn speed_factor(&self, p: &Vector2<f32>) -> f32 {
+        let others = self.density_at(p).saturating_sub(1);
+        1.0 / (1.0 + CONGESTION_SLOWDOWN * others as f32)
+    }
+
+    // Multiplier of the length of a segment for path costs, from the crowd at its middle
+    pub fn cost_factor(&self, a: &Vector2<f32>, b: &Vector2<f32>) -> f32 {
+        1.0 + CONGESTION_COST_WEIGHT * self.density_at(&((a + b) / 2.0)) as f32
+    }
+
+    // Whether the segment crosses a crowded region, besides the one it starts in
+    pub fn is_congested_between(&self, a: &Vector2<f32>, b: &Vector2<f32>) -> bool {
+        let counts = self.counts.lock().unwrap();
+        if counts.is_empty() {
+            return false;
+        }
+        let start = region_of(a);
+        let steps = ((b - a).norm() / (CONGESTION_REGION_SIZE / 2.0))
+            .ceil()
+            .max(1.0) as usize;
+        (1..=steps).any(|i| {
+            let region = region_of(&(a + (b - a) * (i as f32 / steps as f32)));
+            region != start && counts.get(&region).copied().unwrap_or(0) >= CONGESTION_THRESHOLD
+        })
+    }
+
+    // Lower corner of every occupied region, with its number of agents
+    pub fn regions(&self) -> Vec<(Vector2<f32>, usize)> {
+        self.counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&(i, j), &count)| {
+                (
+                    Vector2::new(i as f32, j as f32) * CONGESTION_REGION_SIZE
+                        - Vector2::repeat(GRID_HALF_SIZE),
+                    count,
+                )
+            })
+            .collect()
+    }
+}
@@ -1,3 +1,5 @@
+use crate::units::{MetersPerSecond, MetersPerSecondSquared};
+
 pub const MAX_COST: f32 = 1000.0;
 pub const CELL_SIZE: f32 = 5.0;
 pub const GRID_SPLIT: f32 = 100.0;
@@ -8,3 +10,135 @@ pub const GRID_HALF_SIZE: f32 = GRID_SIZE / 2.0;
 pub const LINE_WIDTH: f32 = CELL_SIZE / 10.0;
 pub const AGENT_RADIUS: f32 = 2.0 * CELL_SIZE;
 pub const DISTANCE_TO_TARGET: f32 = AGENT_RADIUS / 2.0;
+
+pub const MAX_AGENT_RESTARTS: usize = 3;
+pub const RESTARTS_FORGOTTEN_AFTER_S: f32 = 30.0;
+
+pub const SEED: u128 = 0;
+pub const MAX_SPEED: MetersPerSecond = MetersPerSecond(150.0);
+pub const SPEED_SPREAD: f32 = 0.1;
+pub const MAX_REACTION_DELAY_MS: u64 = 500;
+pub const MIN_AGGRESSIVENESS: f32 = 0.75;
+pub const MAX_AGGRESSIVENESS: f32 = 1.25;
+pub const REGION_SPLIT: usize = 1;
+pub const MAX_REGION_SPLIT: usize = 10;
+pub const REGION_IMBALANCE: f32 = 2.0;
+pub const GOSSIP_PERIOD_MS: u64 = 50;
+pub const GOSSIP_FANOUT: usize = 2;
+pub const COMM_RANGE: f32 = 200.0;
+pub const COMM_DROPOFF_START: f32 = 150.0;
+pub const LEADER_TIMEOUT_MS: u64 = 1000;
+pub const DEMAND_SPLIT: usize = 10;
+pub const DEMAND_DECAY: f32 = 0.95;
+pub const SPREAD_RADIUS: f32 = 150.0;
+pub const ANOMALY_DETECTION_RANGE: f32 = 50.0;
+pub const CAMERA_STANDOFF: f32 = 0.5;
+pub const FOV_ARC_SEGMENTS: usize = 8;
+pub const VIEWPOINT_DIRECTIONS: usize = 16;
+pub const LOS_EPSILON: f32 = 1e-6;
+pub const BASE_STATION: (f32, f32) = (0.0, 0.0);
+pub const MAX_CLOCK_OFFSET_MS: u64 = 60_000;
+pub const MAX_CLOCK_DRIFT: f64 = 0.05;
+pub const CLOCK_SKEW_SLACK_MS: u64 = 1_000;
+
+pub const SPAM_FACTOR: usize = 20;
+pub const MAX_MESSAGE_RATE: f32 = 500.0;
+pub const CLAIM_PROGRESS_TIMEOUT_S: f32 = 10.0;
+pub const QUARANTINE_STRIKES: usize = 3;
+
+pub const RATE_LIMIT_PER_S: f32 = 200.0;
+pub const RATE_LIMIT_BURST: f32 = 20.0;
+pub const METRICS_REPORT_PERIOD_S: f32 = 10.0;
+pub const GANTT_PATH: &str = "gantt.svg";
+pub const SUMMARY_PATH: &str = "summary.json";
+pub const INVARIANT_DUMP_PATH: &str = "invariant_violation.txt";
+pub const CRASH_DIR: &str = "crashes";
+pub const CRASH_HISTORY_SIZE: usize = 1000;
+pub const TRACE_DUMP_PATH: &str = "trace.txt";
+pub const SCENE_SVG_PATH: &str = "scene.svg";
+pub const SCENARIO_SNAPSHOT_PATH: &str = "scenario_snapshot.json";
+pub const DIVERGENCE_FACTOR: f32 = 2.0;
+pub const CONTROL_RATE_HZ: f32 = 50.0;
+pub const MAX_CONTROL_CATCHUP: u32 = 5;
+pub const TICK_RATE_REPORT_PERIOD_S: f32 = 1.0;
+pub const MAX_SHEDDING_LEVEL: usize = 3;
+pub const SHEDDING_RAISE_PERIOD_S: f32 = 1.0;
+pub const SHEDDING_RECOVERY_S: f32 = 5.0;
+pub const HUB_LOOP_DEADLINE_MS: u64 = 100;
+pub const SHED_FRAME_PERIOD_MS: u64 = 16;
+
+pub const MAX_ANGULAR_ACCELERATION: f32 = 10.0;
+pub const ORIENTATION_TOLERANCE: f32 = 0.1;
+pub const ORIENTED_MISSION_RATIO: f64 = 0.3;
+pub const RETURN_MISSION_RATIO: f64 = 0.3;
+pub const LEGEND_LINE_HEIGHT: f32 = 30.0;
+pub const LEADERBOARD_SIZE: usize = 5;
+pub const LEADERBOARD_WIDTH: f32 = 250.0;
+pub const FRAME_STATS_PERIOD_S: f32 = 10.0;
+pub const TRAIL_LENGTH: usize = 200;
+pub const ROAD_NODE_SIZE: f32 = CELL_SIZE / 4.0;
+
+pub const CAPACITY_START_AGENTS: usize = 4;
+pub const CAPACITY_MAX_AGENTS: usize = 4096;
+pub const CAPACITY_WARMUP_S: f32 = 1.0;
+pub const CAPACITY_ROUND_S: f32 = 5.0;
+pub const CAPACITY_MIN_TICK_RATIO: f32 = 0.9;
+pub const CLUSTERING_ITERATIONS: usize = 10;
+pub const BENCH_MISSION_COUNTS: [usize; 4] = [100, 1_000, 10_000, 100_000];
+pub const BENCH_AGENTS: usize = 64;
+pub const BENCH_ITERATIONS: usize = 100;
+pub const BENCH_STREAM: u128 = 4000;
+
+pub const MAX_ACCELERATION: MetersPerSecondSquared = MetersPerSecondSquared(100.0);
+// Share of their velocity agents keep after coasting for a second
+pub const VELOCITY_RETAINED_PER_S: f32 = 0.8;
+pub const MAX_COLLISION_PUSHES: usize = 4;
+pub const WEAR_PER_DISTANCE: f32 = 2e-5;
+pub const MAX_WEAR: f32 = 0.5;
+pub const HANDOFF_WEAR: f32 = 0.45;
+pub const MAINTENANCE_WEAR_THRESHOLD: f32 = 0.3;
+pub const MAINTENANCE_RECOVERY_PER_S: f32 = 0.1;
+pub const MAINTENANCE_STATIONS: [(f32, f32); 2] = [(-150.0, -150.0), (150.0, 150.0)];
+pub const ARRIVALS_STREAM: u128 = 1000;
+pub const MAX_TARGET_SAMPLES: usize = 100;
+pub const SPAWN_SPACING: f32 = 3.0 * AGENT_RADIUS;
+pub const PRIORITY_LEVELS: u32 = 4;
+pub const PREEMPTED_PRIORITY: u32 = 2;
+pub const MIN_REWARD_SHARE: f32 = 0.05;
+
+pub const PEDESTRIAN_RADIUS: f32 = CELL_SIZE / 2.0;
+pub const PEDESTRIAN_SPEED: f32 = 20.0;
+pub const PEDESTRIAN_RELAXATION_S: f32 = 0.5;
+pub const SOCIAL_FORCE_STRENGTH: f32 = 50.0;
+pub const SOCIAL_FORCE_RANGE: f32 = 5.0;
+pub const PEDESTRIAN_AVOID_RADIUS: f32 = 3.0 * AGENT_RADIUS;
+pub const MAX_PEDESTRIAN_STEP_S: f32 = 0.1;
+pub const PEDESTRIANS_STREAM: u128 = 2000;
+
+pub const PLANNER_STREAM: u128 = 3000;
+pub const PLANNING_SLACK: f32 = 0.1;
+pub const RRT_ITERATIONS: usize = 1500;
+pub const RRT_STEP: f32 = 4.0 * CELL_SIZE;
+pub const RRT_NEIGHBOR_RADIUS: f32 = 2.0 * RRT_STEP;
+pub const RRT_GOAL_BIAS: f64 = 0.1;
+pub const ASTAR_COST_WEIGHT: f32 = 1.0;
+pub const MAX_TRACKING_ERROR: f32 = AGENT_RADIUS;
+pub const TRACKING_GAIN: f32 = 10.0;
+pub const APPROACH_GAIN: f32 = 5.0;
+pub const BRAKING_SHARE: f32 = 0.8;
+pub const TRAJECTORY_MARGIN: f32 = 0.8;
+pub const PLAN_SAMPLES: usize = 10;
+pub const PLAN_SAMPLE_PERIOD_S: f32 = 0.2;
+pub const INTENT_WAYPOINTS: usize = 5;
+pub const INTENT_CLEARANCE: f32 = 2.0 * AGENT_RADIUS;
+pub const ROUTED_PAST_PENALTY: f32 = 2.0;
+pub const SPEED_SCALES: [f32; 5] = [1.0, 0.75, 0.5, 0.25, 0.0];
+pub const CONFLICT_MARGIN: f32 = AGENT_RADIUS / 2.0;
+pub const MIN_CROSSING_SPEED: f32 = 5.0;
+pub const SAFETY_MARGIN: f32 = CELL_SIZE / 2.0;
+pub const SAFETY_ACTIVATION: f32 = 0.5;
+
+pub const CONGESTION_REGION_SIZE: f32 = 10.0 * CELL_SIZE;
+pub const CONGESTION_THRESHOLD: usize = 3;
+pub const CONGESTION_SLOWDOWN: f32 = 0.2;
+pub const CONGESTION_COST_WEIGHT: f32 = 0.5;
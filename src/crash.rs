@@ -0,0 +1,128 @@
+use crate::agent::AgentMessage;
+use crate::consts::{CRASH_DIR, CRASH_HISTORY_SIZE};
+use crate::error::Result;
+use log::*;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+enum Entry {
+    State(Box<AgentMessage>),
+    Note(String),
+}
+
+struct CrashState {
+    config: String,
+    start: Instant,
+    agents: HashMap<usize, AgentMessage>,
+    history: VecDeque<(f32, Entry)>,
+}
+
+impl CrashState {
+    fn history_text(&self) -> String {
+        let mut history = String::new();
+        for (at, entry) in &self.history {
+            let _ = match entry {
+                Entry::State(state) => writeln!(history, "{:.3}\t{:?}", at, state),
+                Entry::Note(note) => writeln!(history, "{:.3}\t{}", at, note),
+            };
+        }
+        history
+    }
+}
+
+// What the hub last knew of the world, kept around to be dumped if anything panics
+#[derive(Clone)]
+pub struct CrashContext {
+    state: Arc<Mutex<CrashState>>,
+}
+
+impl CrashContext {
+    pub fn new(config: String) -> Self {
+        CrashContext {
+            state: Arc::new(Mutex::new(CrashState {
+                config,
+                start: Instant::now(),
+                agents: HashMap::new(),
+                history: VecDeque::with_capacity(CRASH_HISTORY_SIZE),
+            })),
+        }
+    }
+
+    fn push(&self, entry: Entry) {
+        let mut state = self.state.lock().unwrap();
+        if state.history.len() == CRASH_HISTORY_SIZE {
+            state.history.pop_front();
+        }
+        let at = state.start.elapsed().as_secs_f32();
+        state.history.push_back((at, entry));
+    }
+
+    pub fn agent_state(&self, agent_message: &AgentMessage) {
+        self.state
+            .lock()
+            .unwrap()
+            .agents
+            .insert(agent_message.id, agent_message.clone());
+        self.push(Entry::State(Box::new(agent_message.clone())));
+    }
+
+    pub fn note(&self, note: String) {
+        self.push(Entry::Note(note));
+    }
+
+    // Writes the message history without stopping anything, for debugging a live run
+    pub fn dump_trace(&self, path: &Path) -> Result<()> {
+        let history = self.state.lock().unwrap().history_text();
+        std::fs::write(path, history)?;
+        Ok(())
+    }
+
+    // Chains with the default hook, which still prints the panic message
+    pub fn install(&self) {
+        let context = self.clone();
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            default_hook(info);
+            let thread = std::thread::current();
+            let reason = format!("{} panicked: {}", thread.name().unwrap_or("unnamed"), info);
+            match context.dump(&reason) {
+                Ok(Some(dir)) => error!("Wrote crash dump to {}", dir.display()),
+                Ok(None) => error!("World state is locked, no crash dump written"),
+                Err(err) => error!("Could not write crash dump: {}", err),
+            }
+        }));
+    }
+
+    fn dump(&self, reason: &str) -> Result<Option<PathBuf>> {
+        // The panicking thread may be the one holding the lock
+        let state = match self.state.try_lock() {
+            Ok(state) => state,
+            Err(_) => return Ok(None),
+        };
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let dir = Path::new(CRASH_DIR).join(format!("crash-{}", stamp));
+        std::fs::create_dir_all(&dir)?;
+
+        let mut world = format!(
+            "{}\nat t={}s\n\n",
+            reason,
+            state.start.elapsed().as_secs_f32()
+        );
+        let mut ids: Vec<_> = state.agents.keys().collect();
+        ids.sort();
+        for id in ids {
+            let _ = writeln!(world, "{:#?}", state.agents[id]);
+        }
+        std::fs::write(dir.join("world.txt"), world)?;
+
+        std::fs::write(dir.join("messages.txt"), state.history_text())?;
+        std::fs::write(dir.join("config.txt"), &state.config)?;
+        Ok(Some(dir))
+    }
+}
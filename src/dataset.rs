@@ -0,0 +1,71 @@
+use crate::error::Result;
+use crate::observation::{Observation, ObservationConfig};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// What the agent did with an observation: its acceleration command
+pub struct Action {
+    pub ax: f32,
+    pub ay: f32,
+    pub alpha: f32,
+}
+
+// Where the step left the agent
+pub struct Outcome {
+    pub mission: Option<usize>,
+    pub completed: usize,
+    pub distance_to_goal: Option<f32>,
+}
+
+// Shared CSV sink for per-tick (observation, action, outcome) rows of every agent
+#[derive(Clone)]
+pub struct DatasetRecorder {
+    out: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl DatasetRecorder {
+    pub fn create(path: &Path, config: &ObservationConfig) -> Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "agent,tick")?;
+        for name in config.layout() {
+            write!(out, ",{}", name)?;
+        }
+        writeln!(
+            out,
+            ",action.ax,action.ay,action.alpha,outcome.mission,outcome.completed,outcome.distance_to_goal"
+        )?;
+        Ok(DatasetRecorder {
+            out: Arc::new(Mutex::new(out)),
+        })
+    }
+
+    pub fn record(
+        &self,
+        observation: &Observation,
+        action: &Action,
+        outcome: &Outcome,
+    ) -> Result<()> {
+        let mut out = self.out.lock().unwrap();
+        write!(out, "{},{}", observation.agent, observation.tick)?;
+        for feature in &observation.features {
+            write!(out, ",{}", feature)?;
+        }
+        write!(out, ",{},{},{}", action.ax, action.ay, action.alpha)?;
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        writeln!(
+            out,
+            ",{},{},{}",
+            optional(outcome.mission.map(|m| m.to_string())),
+            outcome.completed,
+            optional(outcome.distance_to_goal.map(|d| d.to_string()))
+        )?;
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.out.lock().unwrap().flush()?;
+        Ok(())
+    }
+}
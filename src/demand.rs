@@ -0,0 +1,62 @@
+use crate::consts::{DEMAND_DECAY, DEMAND_SPLIT, GRID_HALF_SIZE, GRID_SIZE};
+use nalgebra::Vector2;
+
+// Exponentially weighted histogram of where missions recently appeared
+pub struct DemandMap {
+    cells: Vec<f32>,
+}
+
+impl DemandMap {
+    pub fn new() -> Self {
+        DemandMap {
+            cells: vec![0.0; DEMAND_SPLIT * DEMAND_SPLIT],
+        }
+    }
+
+    fn cell_size() -> f32 {
+        GRID_SIZE / DEMAND_SPLIT as f32
+    }
+
+    fn cell_of(p: &Vector2<f32>) -> usize {
+        let index = |c: f32| {
+            (((c + GRID_HALF_SIZE) / DemandMap::cell_size()).max(0.0) as usize)
+                .min(DEMAND_SPLIT - 1)
+        };
+        index(p.y) * DEMAND_SPLIT + index(p.x)
+    }
+
+    fn center_of(cell: usize) -> Vector2<f32> {
+        let size = DemandMap::cell_size();
+        Vector2::new(
+            ((cell % DEMAND_SPLIT) as f32 + 0.5) * size - GRID_HALF_SIZE,
+            ((cell / DEMAND_SPLIT) as f32 + 0.5) * size - GRID_HALF_SIZE,
+        )
+    }
+
+    pub fn record(&mut self, target: &Vector2<f32>) {
+        for demand in &mut self.cells {
+            *demand *= DEMAND_DECAY;
+        }
+        self.cells[DemandMap::cell_of(target)] += 1.0;
+    }
+
+    // Center of the cell with the most demand per agent already around, so that idle agents
+    // spread over the busy areas instead of piling up in the busiest one
+    pub fn best_spot(&self, others: impl Iterator<Item = Vector2<f32>>) -> Option<Vector2<f32>> {
+        let mut crowd = vec![0.0; self.cells.len()];
+        for p in others {
+            crowd[DemandMap::cell_of(&p)] += 1.0;
+        }
+        self.cells
+            .iter()
+            .zip(&crowd)
+            .enumerate()
+            .filter(|(_, (&demand, _))| demand > 0.0)
+            .max_by(|(_, (da, ca)), (_, (db, cb))| {
+                (*da / (1.0 + *ca))
+                    .partial_cmp(&(*db / (1.0 + *cb)))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .map(|(cell, _)| DemandMap::center_of(cell))
+    }
+}
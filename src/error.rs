@@ -0,0 +1,44 @@
+use std::fmt;
+use std::sync::mpsc::SendError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Config(String),
+    Io(std::io::Error),
+    Transport(String),
+    Planning(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(msg) => write!(f, "invalid configuration: {}", msg),
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Transport(msg) => write!(f, "transport error: {}", msg),
+            Error::Planning(msg) => write!(f, "planning error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl<T> From<SendError<T>> for Error {
+    fn from(_: SendError<T>) -> Self {
+        Error::Transport("receiving end of the channel was dropped".to_owned())
+    }
+}
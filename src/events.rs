@@ -0,0 +1,176 @@
+use crate::agent::AgentMessage;
+use crate::consts::CONTROL_RATE_HZ;
+use crate::missions::Mission;
+use nalgebra::Vector2;
+use std::collections::HashMap;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug)]
+pub enum EventKind {
+    Created,
+    Assigned { agent: usize },
+    Abandoned { agent: usize },
+    Completed { agent: usize },
+    // The service window closed before anyone completed it
+    Expired,
+}
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub time: f32,
+    pub mission: usize,
+    pub kind: EventKind,
+}
+
+pub struct EventLog {
+    start: Instant,
+    events: Vec<Event>,
+    assignments: HashMap<usize, usize>,
+    positions: HashMap<usize, Vector2<f32>>,
+    distances: HashMap<usize, f32>,
+    created: HashMap<usize, f32>,
+    windows: HashMap<usize, (f32, f32)>,
+    rewards: HashMap<usize, f32>,
+    response_times: (f64, usize),
+    strategy_switches: Vec<(f32, &'static str)>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        EventLog {
+            start: Instant::now(),
+            events: Vec::new(),
+            assignments: HashMap::new(),
+            positions: HashMap::new(),
+            distances: HashMap::new(),
+            created: HashMap::new(),
+            windows: HashMap::new(),
+            rewards: HashMap::new(),
+            response_times: (0.0, 0),
+            strategy_switches: Vec::new(),
+        }
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn strategy_switches(&self) -> &[(f32, &'static str)] {
+        &self.strategy_switches
+    }
+
+    pub fn strategy_switched(&mut self, strategy: &'static str) {
+        self.strategy_switches.push((self.elapsed(), strategy));
+    }
+
+    // Service windows of the missions that have one, in seconds since the start of the run
+    pub fn windows(&self) -> &HashMap<usize, (f32, f32)> {
+        &self.windows
+    }
+
+    // Reward earned by the completed missions that had one
+    pub fn rewards(&self) -> &HashMap<usize, f32> {
+        &self.rewards
+    }
+
+    pub fn distances(&self) -> &HashMap<usize, f32> {
+        &self.distances
+    }
+
+    pub fn elapsed(&self) -> f32 {
+        self.start.elapsed().as_secs_f32()
+    }
+
+    fn push(&mut self, mission: usize, kind: EventKind) {
+        self.events.push(Event {
+            time: self.elapsed(),
+            mission,
+            kind,
+        });
+    }
+
+    // Mean time from creation to completion of the completed missions
+    pub fn mean_response_time(&self) -> f64 {
+        let (total, count) = self.response_times;
+        if count == 0 {
+            0.0
+        } else {
+            total / count as f64
+        }
+    }
+
+    // Mean reward earned by the completed missions that had one
+    pub fn mean_reward(&self) -> f64 {
+        if self.rewards.is_empty() {
+            0.0
+        } else {
+            self.rewards.values().map(|&r| r as f64).sum::<f64>() / self.rewards.len() as f64
+        }
+    }
+
+    pub fn mission_rewarded(&mut self, mission: usize, reward: f32) {
+        self.rewards.insert(mission, reward);
+    }
+
+    pub fn mission_created(&mut self, mission: &Mission) {
+        self.created.insert(mission.id, self.elapsed());
+        if let Some((earliest, latest)) = mission.window {
+            self.windows.insert(
+                mission.id,
+                (
+                    earliest as f32 / CONTROL_RATE_HZ,
+                    latest as f32 / CONTROL_RATE_HZ,
+                ),
+            );
+        }
+        self.push(mission.id, EventKind::Created);
+    }
+
+    pub fn mission_expired(&mut self, mission: usize) {
+        self.created.remove(&mission);
+        self.push(mission, EventKind::Expired);
+    }
+
+    pub fn agent_state(&mut self, agent_message: &AgentMessage) {
+        let agent = agent_message.id;
+        let p = agent_message.kinematics.p;
+        if let Some(previous) = self.positions.insert(agent, p) {
+            *self.distances.entry(agent).or_insert(0.0) += (p - previous).norm();
+        }
+
+        let current = agent_message.mission.as_ref().map(|m| m.id);
+        let previous = self.assignments.get(&agent).copied();
+        if previous == current {
+            return;
+        }
+
+        // Missions moved to the cargo are still being worked on, until dropped off
+        if let Some(previous) = previous {
+            self.assignments.remove(&agent);
+            if !agent_message.cargo.iter().any(|m| m.id == previous) {
+                self.push(previous, EventKind::Abandoned { agent });
+            }
+        }
+        if let Some(current) = current {
+            self.assignments.insert(agent, current);
+            self.push(current, EventKind::Assigned { agent });
+        }
+    }
+
+    // Returns the time the mission took from its creation, if it was logged
+    pub fn mission_completed(&mut self, mission: usize, agent: usize) -> Option<f32> {
+        if self.assignments.get(&agent) == Some(&mission) {
+            self.assignments.remove(&agent);
+        }
+        let response_time = self
+            .created
+            .remove(&mission)
+            .map(|created| self.elapsed() - created);
+        if let Some(response_time) = response_time {
+            self.response_times.0 += response_time as f64;
+            self.response_times.1 += 1;
+        }
+        self.push(mission, EventKind::Completed { agent });
+        response_time
+    }
+}
@@ -0,0 +1,181 @@
+use crate::events::{EventKind, EventLog};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::path::Path;
+
+const ROW_HEIGHT: f32 = 24.0;
+const LABEL_WIDTH: f32 = 80.0;
+const PIXELS_PER_SECOND: f32 = 20.0;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Ending {
+    Unfinished,
+    Completed,
+    Expired,
+}
+
+struct Bar {
+    mission: usize,
+    start: f32,
+    end: f32,
+    ending: Ending,
+}
+
+pub fn write_gantt(log: &EventLog, path: &Path) -> std::io::Result<()> {
+    std::fs::write(path, render_gantt(log))
+}
+
+pub fn render_gantt(log: &EventLog) -> String {
+    let end_time = log.elapsed();
+    let mut rows: BTreeMap<usize, Vec<Bar>> = BTreeMap::new();
+    let mut open: HashMap<usize, (usize, f32)> = HashMap::new();
+    for event in log.events() {
+        match event.kind {
+            EventKind::Created => {}
+            EventKind::Assigned { agent } => {
+                open.insert(agent, (event.mission, event.time));
+                rows.entry(agent).or_default();
+            }
+            EventKind::Abandoned { agent } | EventKind::Completed { agent } => {
+                if let Some((mission, start)) = open.remove(&agent) {
+                    let completed = matches!(event.kind, EventKind::Completed { .. })
+                        && mission == event.mission;
+                    rows.entry(agent).or_default().push(Bar {
+                        mission,
+                        start,
+                        end: event.time,
+                        ending: if completed {
+                            Ending::Completed
+                        } else {
+                            Ending::Unfinished
+                        },
+                    });
+                }
+            }
+            EventKind::Expired => {
+                let holders: Vec<usize> = open
+                    .iter()
+                    .filter(|(_, (mission, _))| *mission == event.mission)
+                    .map(|(&agent, _)| agent)
+                    .collect();
+                for agent in holders {
+                    if let Some((mission, start)) = open.remove(&agent) {
+                        rows.entry(agent).or_default().push(Bar {
+                            mission,
+                            start,
+                            end: event.time,
+                            ending: Ending::Expired,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    for (agent, (mission, start)) in open {
+        rows.entry(agent).or_default().push(Bar {
+            mission,
+            start,
+            end: end_time,
+            ending: Ending::Unfinished,
+        });
+    }
+
+    // Window adherence: missions with a service window completed within it, and expired ones
+    let windows = log.windows();
+    let completed_in_window = log
+        .events()
+        .iter()
+        .filter(|e| matches!(e.kind, EventKind::Completed { .. }))
+        .filter(|e| {
+            windows
+                .get(&e.mission)
+                .is_some_and(|&(earliest, latest)| e.time >= earliest && e.time <= latest)
+        })
+        .count();
+    let expired = log
+        .events()
+        .iter()
+        .filter(|e| matches!(e.kind, EventKind::Expired))
+        .count();
+
+    let width = LABEL_WIDTH + end_time * PIXELS_PER_SECOND + 10.0;
+    let height = (rows.len() as f32 + 1.0) * ROW_HEIGHT;
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="10">"#,
+        width, height
+    );
+    for second in (0..=end_time as usize).step_by(10) {
+        let x = LABEL_WIDTH + second as f32 * PIXELS_PER_SECOND;
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{x}" y1="0" x2="{x}" y2="{}" stroke="lightgray"/><text x="{x}" y="{}">{}s</text>"#,
+            height - ROW_HEIGHT,
+            height - 8.0,
+            second,
+            x = x
+        );
+    }
+    if !windows.is_empty() {
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}">{} windowed missions completed in their window, {} expired</text>"#,
+            LABEL_WIDTH + 40.0,
+            height - 8.0,
+            completed_in_window,
+            expired
+        );
+    }
+    for (row, (agent, bars)) in rows.iter().enumerate() {
+        let y = row as f32 * ROW_HEIGHT;
+        let _ = writeln!(
+            svg,
+            r#"<text x="4" y="{}">Agent {}</text>"#,
+            y + ROW_HEIGHT * 0.65,
+            agent
+        );
+        // Service windows behind the bars working on them
+        for bar in bars {
+            if let Some(&(earliest, latest)) = windows.get(&bar.mission) {
+                let _ = writeln!(
+                    svg,
+                    r#"<rect x="{}" y="{}" width="{}" height="{}" fill="lightblue" opacity="0.6"><title>window of mission {} ({:.1}s - {:.1}s)</title></rect>"#,
+                    LABEL_WIDTH + earliest * PIXELS_PER_SECOND,
+                    y,
+                    (latest - earliest) * PIXELS_PER_SECOND,
+                    ROW_HEIGHT,
+                    bar.mission,
+                    earliest,
+                    latest
+                );
+            }
+        }
+        for bar in bars {
+            let x = LABEL_WIDTH + bar.start * PIXELS_PER_SECOND;
+            let w = ((bar.end - bar.start) * PIXELS_PER_SECOND).max(1.0);
+            let color = match bar.ending {
+                Ending::Completed => "seagreen",
+                Ending::Expired => "firebrick",
+                Ending::Unfinished => "darkgray",
+            };
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}" stroke="black"><title>mission {} ({:.1}s - {:.1}s)</title></rect><text x="{}" y="{}" fill="white">{}</text>"#,
+                x,
+                y + 2.0,
+                w,
+                ROW_HEIGHT - 4.0,
+                color,
+                bar.mission,
+                bar.start,
+                bar.end,
+                x + 2.0,
+                y + ROW_HEIGHT * 0.65,
+                bar.mission
+            );
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -0,0 +1,522 @@
+use crate::agent::{AgentMessage, Grid, Message};
+use crate::consts::{
+    CLOCK_SKEW_SLACK_MS, COMM_DROPOFF_START, COMM_RANGE, GOSSIP_FANOUT, GOSSIP_PERIOD_MS,
+    LEADER_TIMEOUT_MS, MAX_CLOCK_DRIFT, MAX_CLOCK_OFFSET_MS,
+};
+use crate::missions::{Mission, MissionMessage};
+use crate::render_sink::{RenderFrame, RenderSink};
+use crate::system::{ConnectionHandle, SimClock};
+use log::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rand_pcg::Pcg64;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, Default)]
+pub struct GossipView {
+    pub states: HashMap<usize, (u64, AgentMessage)>,
+    pub missions: HashMap<usize, Mission>,
+    pub finished: HashMap<usize, usize>,
+    // Arbitration of contested missions by the leader: mission -> (Lamport stamp, agent)
+    pub awards: HashMap<usize, (u64, usize)>,
+}
+
+#[derive(Debug, Default)]
+pub struct GossipDelta {
+    pub states: Vec<AgentMessage>,
+    pub missions: Vec<Mission>,
+    pub finished: Vec<(usize, usize)>,
+    pub awards: Vec<(usize, usize)>,
+}
+
+impl GossipView {
+    pub fn merge(&mut self, other: GossipView) -> GossipDelta {
+        let mut delta = GossipDelta::default();
+        for (id, agent) in other.finished {
+            if let Entry::Vacant(entry) = self.finished.entry(id) {
+                entry.insert(agent);
+                self.missions.remove(&id);
+                delta.finished.push((id, agent));
+            }
+        }
+        for (id, mission) in other.missions {
+            if !self.finished.contains_key(&id) && !self.missions.contains_key(&id) {
+                self.missions.insert(id, mission.clone());
+                delta.missions.push(mission);
+            }
+        }
+        for (id, (version, state)) in other.states {
+            let newer = match self.states.get(&id) {
+                Some((known, _)) => version > *known,
+                None => true,
+            };
+            if newer {
+                self.states.insert(id, (version, state.clone()));
+                delta.states.push(state);
+            }
+        }
+        for (id, award) in other.awards {
+            if self.awards.get(&id).is_none_or(|known| award > *known) {
+                self.awards.insert(id, award);
+                delta.awards.push((id, award.1));
+            }
+        }
+        delta
+    }
+
+    // Drops states stamped further ahead than any node's clock can be: merged, they would shadow
+    // the genuine states of their agent until its clock caught up
+    pub fn discard_skewed(&mut self, clock: &SkewedClock) {
+        self.states.retain(|id, (version, _)| {
+            let plausible = clock.within_skew(*version);
+            if !plausible {
+                warn!("Discarding state of agent {} stamped {}ms", id, version);
+            }
+            plausible
+        });
+    }
+
+    fn latest_award_stamp(&self) -> u64 {
+        self.awards
+            .values()
+            .map(|(stamp, _)| *stamp)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn finish(&mut self, mission_id: usize, agent: usize) -> bool {
+        self.missions.remove(&mission_id);
+        match self.finished.entry(mission_id) {
+            Entry::Vacant(entry) => {
+                entry.insert(agent);
+                true
+            }
+            Entry::Occupied(_) => false,
+        }
+    }
+}
+
+// Chance that a message makes it over a link of the given length: certain up to
+// COMM_DROPOFF_START, then fading linearly down to nothing at COMM_RANGE
+pub fn delivery_probability(distance: f32) -> f32 {
+    if distance <= COMM_DROPOFF_START {
+        1.0
+    } else if distance >= COMM_RANGE {
+        0.0
+    } else {
+        (COMM_RANGE - distance) / (COMM_RANGE - COMM_DROPOFF_START)
+    }
+}
+
+// Another node we can gossip with; the system manager has no id and is always in range
+#[derive(Clone)]
+pub struct Peer {
+    pub id: Option<usize>,
+    pub inbox: Sender<GossipView>,
+}
+
+// A node's local clock, off by a fixed offset and running slightly fast or slow. Timestamps from
+// different nodes are never compared with each other, only with earlier ones from the same node
+// and with the bounds any clock stays within.
+#[derive(Debug)]
+pub struct SkewedClock {
+    start: Instant,
+    offset_ms: u64,
+    drift: f64,
+}
+
+impl SkewedClock {
+    pub fn random<R: Rng>(rng: &mut R) -> Self {
+        SkewedClock {
+            start: Instant::now(),
+            offset_ms: rng.gen_range(0..=MAX_CLOCK_OFFSET_MS),
+            drift: rng.gen_range(-MAX_CLOCK_DRIFT..=MAX_CLOCK_DRIFT),
+        }
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.offset_ms + (self.start.elapsed().as_secs_f64() * 1000.0 * (1.0 + self.drift)) as u64
+    }
+
+    // Whether a timestamp of another node could come from a clock within the skew bounds, with
+    // some slack for the nodes not being started at the same instant
+    pub fn within_skew(&self, stamp_ms: u64) -> bool {
+        let elapsed_ms = self.start.elapsed().as_secs_f64() * 1000.0;
+        let latest = (MAX_CLOCK_OFFSET_MS + CLOCK_SKEW_SLACK_MS) as f64
+            + elapsed_ms * (1.0 + MAX_CLOCK_DRIFT);
+        stamp_ms as f64 <= latest
+    }
+}
+
+pub struct GossipNode {
+    id: usize,
+    view: GossipView,
+    version: u64,
+    local_clock: SkewedClock,
+    clock: SimClock,
+    last_heard: HashMap<usize, Instant>,
+    leader: usize,
+    agent_rx: Receiver<Message>,
+    agent_tx: Sender<Message>,
+    inbox: Receiver<GossipView>,
+    inbox_tx: Sender<GossipView>,
+    render_sink: Arc<dyn RenderSink>,
+    grid: Arc<Grid>,
+    rng: Pcg64,
+}
+
+impl GossipNode {
+    pub fn new(
+        id: usize,
+        render_sink: Arc<dyn RenderSink>,
+        grid: Arc<Grid>,
+        clock: SimClock,
+        seed: u128,
+    ) -> (Self, ConnectionHandle) {
+        let (agent_tx, rx) = channel();
+        let (tx, agent_rx) = channel();
+        let (inbox_tx, inbox) = channel();
+        let mut rng = Pcg64::new(seed, 3 + 2 * id as u128);
+        let local_clock = SkewedClock::random(&mut rng);
+        debug!("Gossip node {} clock: {:?}", id, local_clock);
+        let node = GossipNode {
+            id,
+            view: GossipView::default(),
+            version: 0,
+            local_clock,
+            clock,
+            last_heard: HashMap::new(),
+            leader: id,
+            agent_rx,
+            agent_tx,
+            inbox,
+            inbox_tx,
+            render_sink,
+            grid,
+            rng,
+        };
+        (node, ConnectionHandle { tx, rx })
+    }
+
+    pub fn peer(&self) -> Peer {
+        Peer {
+            id: Some(self.id),
+            inbox: self.inbox_tx.clone(),
+        }
+    }
+
+    pub fn run(mut self, mut peers: Vec<Peer>) {
+        info!("Starting gossip node {}", self.id);
+        let period = Duration::from_millis(GOSSIP_PERIOD_MS);
+        let mut last_push = Instant::now();
+        loop {
+            loop {
+                match self.agent_rx.try_recv() {
+                    Ok(Message::Agent(state)) => {
+                        if !self.update_own_state(state) {
+                            return;
+                        }
+                    }
+                    Ok(_) => debug!("Gossip node {} ignores anything but agent states", self.id),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        info!("Agent {} is gone, stopping its gossip node", self.id);
+                        return;
+                    }
+                }
+            }
+
+            match self.inbox.recv_timeout(Duration::from_millis(10)) {
+                Ok(mut view) => {
+                    view.discard_skewed(&self.local_clock);
+                    let delta = self.view.merge(view);
+                    let now = Instant::now();
+                    for state in &delta.states {
+                        self.last_heard.insert(state.id, now);
+                    }
+                    if !self.deliver(delta) {
+                        return;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    info!("Gossip node {} lost its inbox, stopping", self.id);
+                    return;
+                }
+            }
+
+            self.elect();
+            if self.leader == self.id {
+                let awards = self.arbitrate();
+                if !self.deliver(GossipDelta {
+                    awards,
+                    ..GossipDelta::default()
+                }) {
+                    return;
+                }
+            }
+
+            if last_push.elapsed() >= period {
+                last_push = Instant::now();
+                self.push(&mut peers);
+            }
+        }
+    }
+
+    fn is_alive(&self, id: usize) -> bool {
+        id == self.id
+            || self
+                .last_heard
+                .get(&id)
+                .is_some_and(|t| t.elapsed() < Duration::from_millis(LEADER_TIMEOUT_MS))
+    }
+
+    // Bully election: the live node with the highest id we know of leads. A leader that stops
+    // being heard from times out, and the next one in line takes over on its own.
+    fn elect(&mut self) {
+        let leader = self
+            .last_heard
+            .keys()
+            .copied()
+            .filter(|&id| self.is_alive(id))
+            .chain(std::iter::once(self.id))
+            .max()
+            .unwrap_or(self.id);
+        if leader != self.leader {
+            info!("Gossip node {} now follows leader {}", self.id, leader);
+            self.leader = leader;
+        }
+    }
+
+    // As leader, settles missions claimed by several live agents in favour of the cheapest one.
+    // Awarded missions nobody else will pick up anymore, so those whose winner went silent or
+    // moved on are handed again to the cheapest live agent.
+    fn arbitrate(&mut self) -> Vec<(usize, usize)> {
+        let mut out = Vec::new();
+        for (&mission_id, mission) in &self.view.missions {
+            let live: Vec<&AgentMessage> = self
+                .view
+                .states
+                .values()
+                .map(|(_, state)| state)
+                .filter(|state| self.is_alive(state.id))
+                .collect();
+            let claimants: Vec<&AgentMessage> = live
+                .iter()
+                .copied()
+                .filter(|state| state.mission.as_ref().map(|m| m.id) == Some(mission_id))
+                .collect();
+            let award = self.view.awards.get(&mission_id).map(|(_, agent)| *agent);
+            let candidates = match award {
+                Some(agent) if claimants.iter().any(|c| c.id == agent) => continue,
+                Some(_) => live,
+                None if claimants.len() > 1 => claimants,
+                None => continue,
+            };
+            let winner = candidates.iter().min_by(|a, b| {
+                a.cost_to(&mission.target)
+                    .partial_cmp(&b.cost_to(&mission.target))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            if let Some(winner) = winner.filter(|w| Some(w.id) != award) {
+                info!(
+                    "Leader {} awards mission {} to agent {}",
+                    self.id, mission_id, winner.id
+                );
+                out.push((mission_id, winner.id));
+            }
+        }
+        for &(mission_id, agent) in &out {
+            let stamp = self.view.latest_award_stamp() + 1;
+            self.view.awards.insert(mission_id, (stamp, agent));
+        }
+        out
+    }
+
+    fn update_own_state(&mut self, state: AgentMessage) -> bool {
+        let mut finished = None;
+        if let Some(mission) = &state.mission {
+            if mission.is_completed_by(&self.grid, &state.kinematics)
+                && self.view.finish(mission.id, self.id)
+            {
+                info!("Agent {} finished mission {}", self.id, mission.id);
+                finished = Some(mission.id);
+            }
+        }
+        // Versions are our own timestamps, kept strictly increasing even if two states are
+        // stamped within the same millisecond
+        self.version = self.local_clock.now_ms().max(self.version + 1);
+        self.view
+            .states
+            .insert(self.id, (self.version, state.clone()));
+        self.render_sink.submit_frame(&RenderFrame {
+            tick: self.clock.now(),
+            agents: vec![state],
+        });
+        match finished {
+            Some(mission_id) => self
+                .agent_tx
+                .send(Message::MissionCompleted {
+                    mission: mission_id,
+                    by_agent: self.id,
+                })
+                .is_ok(),
+            None => true,
+        }
+    }
+
+    fn deliver(&mut self, delta: GossipDelta) -> bool {
+        let mut messages = Vec::new();
+        if !delta.missions.is_empty() {
+            messages.push(Message::Mission(MissionMessage {
+                tick: self.clock.now(),
+                missions: delta.missions,
+            }));
+        }
+        for state in delta.states {
+            if state.id != self.id {
+                messages.push(Message::Agent(state));
+            }
+        }
+        for (mission_id, agent) in delta.finished {
+            messages.push(Message::MissionCompleted {
+                mission: mission_id,
+                by_agent: agent,
+            });
+        }
+        for (mission_id, agent) in delta.awards {
+            if let Some(mission) = self.view.missions.get(&mission_id) {
+                messages.push(Message::Awarded {
+                    mission: mission.clone(),
+                    agent,
+                });
+            }
+        }
+        messages
+            .into_iter()
+            .all(|message| self.agent_tx.send(message).is_ok())
+    }
+
+    // Peers whose position we do not know yet are assumed to be in range, so that nodes can find
+    // each other in the first place. Walls in between cut the link.
+    fn link_quality(&self, peer: &Peer) -> f32 {
+        let peer_id = match peer.id {
+            Some(id) => id,
+            None => return 1.0,
+        };
+        let position = |id| self.view.states.get(&id).map(|(_, s)| s.kinematics.p);
+        match (position(self.id), position(peer_id)) {
+            (Some(own), Some(theirs)) if !self.grid.line_of_sight(&own, &theirs) => 0.0,
+            (Some(own), Some(theirs)) => delivery_probability((own - theirs).norm()),
+            _ => 1.0,
+        }
+    }
+
+    // Views only travel between nodes within communication range, and spread further by being
+    // merged and pushed again
+    fn push(&mut self, peers: &mut Vec<Peer>) {
+        let qualities: Vec<f32> = peers.iter().map(|p| self.link_quality(p)).collect();
+        let targets: Vec<usize> = (0..peers.len()).filter(|&i| qualities[i] > 0.0).collect();
+        let mut dead = Vec::new();
+        for &i in targets.choose_multiple(&mut self.rng, GOSSIP_FANOUT) {
+            if !self.rng.gen_bool(qualities[i] as f64) {
+                debug!("Gossip node {} lost a message to a distant peer", self.id);
+                continue;
+            }
+            if peers[i].inbox.send(self.view.clone()).is_err() {
+                dead.push(i);
+            }
+        }
+        dead.sort_unstable();
+        for i in dead.into_iter().rev() {
+            debug!("Gossip node {} dropping an unreachable peer", self.id);
+            peers.remove(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::Kinematics;
+    use crate::consts::{AGENT_RADIUS, SEED};
+    use nalgebra::Vector2;
+
+    fn state(id: usize) -> AgentMessage {
+        AgentMessage {
+            id,
+            seq: 1,
+            tick: 0,
+            kinematics: Kinematics {
+                p: Vector2::zeros(),
+                v: Vector2::zeros(),
+                a: Vector2::zeros(),
+                theta: 0.0,
+                omega: 0.0,
+                alpha: 0.0,
+                radius: AGENT_RADIUS,
+            },
+            mission: None,
+            wear: 0.0,
+            completed: 0,
+            plan: Vec::new(),
+            waypoints: Vec::new(),
+            cargo: Vec::new(),
+        }
+    }
+
+    fn clock(offset_ms: u64, drift: f64) -> SkewedClock {
+        SkewedClock {
+            start: Instant::now(),
+            offset_ms,
+            drift,
+        }
+    }
+
+    #[test]
+    fn stamps_of_any_skewed_clock_are_accepted() {
+        let mut rng = Pcg64::new(SEED, 0);
+        let receiver = clock(0, -MAX_CLOCK_DRIFT);
+        for _ in 0..100 {
+            let sender = SkewedClock::random(&mut rng);
+            assert!(receiver.within_skew(sender.now_ms()));
+        }
+        let fastest = clock(MAX_CLOCK_OFFSET_MS, MAX_CLOCK_DRIFT);
+        assert!(receiver.within_skew(fastest.now_ms()));
+        assert!(receiver.within_skew(MAX_CLOCK_OFFSET_MS + CLOCK_SKEW_SLACK_MS));
+    }
+
+    #[test]
+    fn stamps_beyond_the_skew_bounds_are_rejected() {
+        let receiver = clock(0, 0.0);
+        assert!(!receiver.within_skew(MAX_CLOCK_OFFSET_MS + CLOCK_SKEW_SLACK_MS + 60_000));
+        assert!(!receiver.within_skew(u64::MAX));
+    }
+
+    // A state stamped in the far future is dropped before the merge, so that the genuine states
+    // of its agent, stamped by a clock far behind the other nodes, still get through
+    #[test]
+    fn skewed_states_are_discarded_before_merging() {
+        let receiver = clock(MAX_CLOCK_OFFSET_MS, MAX_CLOCK_DRIFT);
+        let mut view = GossipView::default();
+        view.states.insert(0, (5, state(0)));
+
+        let mut forged = GossipView::default();
+        forged.states.insert(0, (u64::MAX, state(0)));
+        forged.states.insert(1, (MAX_CLOCK_OFFSET_MS, state(1)));
+        forged.discard_skewed(&receiver);
+        let delta = view.merge(forged);
+        assert_eq!(delta.states.len(), 1);
+        assert_eq!(delta.states[0].id, 1);
+
+        let mut genuine = GossipView::default();
+        genuine.states.insert(0, (6, state(0)));
+        genuine.discard_skewed(&receiver);
+        assert_eq!(view.merge(genuine).states.len(), 1);
+        assert_eq!(view.states[&0].0, 6);
+    }
+}
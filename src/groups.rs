@@ -0,0 +1,93 @@
+use crate::metrics::Metrics;
+use crate::missions::Mission;
+use crate::scenario::AgentGroup;
+use std::collections::{BTreeMap, HashMap};
+
+// Which group each agent belongs to, deciding which scoped missions it is told about. Agents
+// outside of every group only see the missions open to all.
+pub struct Groups {
+    names: Vec<String>,
+    of_agent: HashMap<usize, usize>,
+    response_times: Vec<(f64, usize)>,
+    metrics: Metrics,
+}
+
+impl Groups {
+    pub fn new(groups: &[AgentGroup], metrics: Metrics) -> Self {
+        let mut of_agent = HashMap::new();
+        for (i, group) in groups.iter().enumerate() {
+            for &agent in &group.agents {
+                of_agent.insert(agent, i);
+            }
+        }
+        Groups {
+            names: groups.iter().map(|g| g.name.clone()).collect(),
+            of_agent,
+            response_times: vec![(0.0, 0); groups.len()],
+            metrics,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn sees(&self, agent: usize, mission: &Mission) -> bool {
+        mission
+            .group
+            .is_none_or(|group| self.of_agent.get(&agent) == Some(&group))
+    }
+
+    // Splits the missions by the agents allowed to see them
+    pub fn partition(
+        &self,
+        agents: &[usize],
+        missions: Vec<Mission>,
+    ) -> Vec<(Vec<usize>, Vec<Mission>)> {
+        if self.is_empty() {
+            return vec![(agents.to_vec(), missions)];
+        }
+        let mut by_group: BTreeMap<Option<usize>, Vec<Mission>> = BTreeMap::new();
+        for mission in missions {
+            by_group.entry(mission.group).or_default().push(mission);
+        }
+        by_group
+            .into_values()
+            .map(|missions| {
+                let recipients: Vec<usize> = agents
+                    .iter()
+                    .copied()
+                    .filter(|&agent| self.sees(agent, &missions[0]))
+                    .collect();
+                (recipients, missions)
+            })
+            .filter(|(recipients, _)| !recipients.is_empty())
+            .collect()
+    }
+
+    pub fn mission_created(&self, mission: &Mission) {
+        if let Some(group) = mission.group {
+            self.metrics
+                .increment(&format!("group.{}.missions_created", self.names[group]));
+        }
+    }
+
+    // Completions are credited to the group of the agent, whether the mission was scoped or not
+    pub fn mission_completed(&mut self, agent: usize, response_time: Option<f32>) {
+        let group = match self.of_agent.get(&agent) {
+            Some(&group) => group,
+            None => return,
+        };
+        let name = &self.names[group];
+        self.metrics.increment(&format!("group.{}.completed", name));
+        if let Some(response_time) = response_time {
+            let (total, count) = &mut self.response_times[group];
+            *total += response_time as f64;
+            *count += 1;
+            self.metrics.set(
+                &format!("group.{}.mean_response_time_s", name),
+                *total / *count as f64,
+            );
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use crate::agent::AgentMessage;
+use crate::consts::{
+    CLAIM_PROGRESS_TIMEOUT_S, INTENT_WAYPOINTS, MAX_MESSAGE_RATE, MAX_SPEED, QUARANTINE_STRIKES,
+    SPEED_SPREAD,
+};
+use crate::units::MetersPerSecond;
+use log::*;
+use nalgebra::Vector2;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+struct AgentRecord {
+    last_position: Vector2<f32>,
+    last_seen: Instant,
+    window_start: Instant,
+    window_messages: usize,
+    claim: Option<Claim>,
+    strikes: usize,
+}
+
+// Progress of an agent towards the current goal of the mission it claims
+struct Claim {
+    stage: (usize, bool),
+    // Waypoints the agent last reported
+    waypoints: Vec<Vector2<f32>>,
+    // Shortest distance left along the reported route since it was last rebased
+    best: f32,
+    since: Instant,
+}
+
+// Distance left to the goal along the waypoints, the goal being reached in a straight line from the
+// last of them
+fn distance_along(p: Vector2<f32>, waypoints: &[Vector2<f32>], goal: Vector2<f32>) -> f32 {
+    let mut from = p;
+    let mut distance = 0.0;
+    for waypoint in waypoints.iter().chain(std::iter::once(&goal)) {
+        distance += (waypoint - from).norm();
+        from = *waypoint;
+    }
+    distance
+}
+
+pub struct IntegrityMonitor {
+    records: HashMap<usize, AgentRecord>,
+    quarantined: HashSet<usize>,
+}
+
+impl IntegrityMonitor {
+    pub fn new() -> Self {
+        IntegrityMonitor {
+            records: HashMap::new(),
+            quarantined: HashSet::new(),
+        }
+    }
+
+    // Returns whether the message should be trusted. The messages of the agent the rate limiter
+    // dropped since the last one inspected count towards its message rate, so that spamming is
+    // still noticed behind the limiter.
+    pub fn inspect(&mut self, agent_message: &AgentMessage, dropped: usize) -> bool {
+        self.inspect_at(agent_message, dropped, Instant::now())
+    }
+
+    fn inspect_at(&mut self, agent_message: &AgentMessage, dropped: usize, now: Instant) -> bool {
+        if self.quarantined.contains(&agent_message.id) {
+            return false;
+        }
+
+        let p = agent_message.kinematics.p;
+        let record = self
+            .records
+            .entry(agent_message.id)
+            .or_insert_with(|| AgentRecord {
+                last_position: p,
+                last_seen: now,
+                window_start: now,
+                window_messages: 0,
+                claim: None,
+                strikes: 0,
+            });
+
+        let mut offences = Vec::new();
+
+        let dt = (now - record.last_seen).as_secs_f32().max(1e-3);
+        let speed = (p - record.last_position).norm() / dt;
+        if MetersPerSecond(speed) > 2.0 * MAX_SPEED * (1.0 + SPEED_SPREAD) {
+            offences.push(format!("moved at an impossible speed ({})", speed));
+        }
+        record.last_position = p;
+        record.last_seen = now;
+
+        record.window_messages += 1 + dropped;
+        let window = now - record.window_start;
+        if window >= Duration::from_secs(1) {
+            let rate = record.window_messages as f32 / window.as_secs_f32();
+            if rate > MAX_MESSAGE_RATE {
+                offences.push(format!("sent {} messages per second", rate));
+            }
+            record.window_start = now;
+            record.window_messages = 0;
+        }
+
+        // Progress is measured along the route the agent reported, so that detours around
+        // obstacles count. Only part of a long route is reported: once the agent moves past one of
+        // its waypoints, the next part of the route may be longer than the straight line it
+        // replaces, and the distance is measured anew from there.
+        match &agent_message.mission {
+            Some(mission) => {
+                let waypoints = &agent_message.waypoints;
+                let distance = distance_along(p, waypoints, mission.current_goal().0);
+                let stage = (mission.id, mission.visited);
+                match &mut record.claim {
+                    Some(claim) if claim.stage == stage => {
+                        let moved_on = waypoints.len() == INTENT_WAYPOINTS
+                            && waypoints.first().is_some_and(|next| {
+                                claim.waypoints.iter().skip(1).any(|w| w == next)
+                            });
+                        // Waiting for the service window to open is not stalling
+                        let waiting = !mission.is_open(agent_message.tick)
+                            && !mission.has_expired(agent_message.tick);
+                        if moved_on || waiting || distance < claim.best {
+                            claim.best = distance;
+                            claim.since = now;
+                        } else if (now - claim.since).as_secs_f32() > CLAIM_PROGRESS_TIMEOUT_S {
+                            offences.push(format!(
+                                "claims mission {} without getting closer to it",
+                                mission.id
+                            ));
+                            claim.since = now;
+                        }
+                        claim.waypoints = waypoints.clone();
+                    }
+                    _ => {
+                        record.claim = Some(Claim {
+                            stage,
+                            waypoints: waypoints.clone(),
+                            best: distance,
+                            since: now,
+                        })
+                    }
+                }
+            }
+            None => record.claim = None,
+        }
+
+        for offence in offences {
+            record.strikes += 1;
+            warn!(
+                "Agent {} {} (strike {}/{})",
+                agent_message.id, offence, record.strikes, QUARANTINE_STRIKES
+            );
+        }
+        if record.strikes >= QUARANTINE_STRIKES {
+            error!("Agent {} is quarantined", agent_message.id);
+            self.quarantined.insert(agent_message.id);
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::Kinematics;
+    use crate::consts::{AGENT_RADIUS, CELL_SIZE};
+    use crate::missions::Mission;
+    use crate::units::Seconds;
+
+    const RATE_HZ: f32 = 10.0;
+    // A worn agent, slow enough for a detour to take a while
+    const SPEED: f32 = 5.0;
+
+    fn mission(window: Option<(u64, u64)>) -> Mission {
+        Mission {
+            id: 0,
+            agent: Some(0),
+            target: Vector2::zeros(),
+            target_theta: None,
+            urgent: false,
+            priority: 0,
+            return_to: None,
+            visited: false,
+            group: None,
+            window,
+            camera: None,
+            created: 0,
+            reward: None,
+        }
+    }
+
+    fn state(
+        tick: u64,
+        p: Vector2<f32>,
+        waypoints: &[Vector2<f32>],
+        mission: &Mission,
+    ) -> AgentMessage {
+        AgentMessage {
+            id: 0,
+            seq: tick + 1,
+            tick,
+            kinematics: Kinematics {
+                p,
+                v: Vector2::zeros(),
+                a: Vector2::zeros(),
+                theta: 0.0,
+                omega: 0.0,
+                alpha: 0.0,
+                radius: AGENT_RADIUS,
+            },
+            mission: Some(mission.clone()),
+            wear: 0.0,
+            completed: 0,
+            plan: Vec::new(),
+            waypoints: waypoints.to_vec(),
+            cargo: Vec::new(),
+        }
+    }
+
+    // Points every CELL_SIZE along the corners, as a grid planner would lay them
+    fn path_through(corners: &[Vector2<f32>]) -> Vec<Vector2<f32>> {
+        let mut path = Vec::new();
+        for leg in corners.windows(2) {
+            let steps = ((leg[1] - leg[0]).norm() / CELL_SIZE).ceil() as usize;
+            path.extend((1..=steps).map(|k| leg[0].lerp(&leg[1], k as f32 / steps as f32)));
+        }
+        path
+    }
+
+    // Sends a state every 1/RATE_HZ seconds for the given duration, with the agent wherever
+    // `at` puts it, until one is not trusted
+    fn trusted_for(
+        monitor: &mut IntegrityMonitor,
+        duration_s: f32,
+        mission: &Mission,
+        mut at: impl FnMut(f32) -> (Vector2<f32>, Vec<Vector2<f32>>),
+    ) -> bool {
+        let start = Instant::now();
+        (0..(duration_s * RATE_HZ) as usize).all(|k| {
+            let t = k as f32 / RATE_HZ;
+            let (p, waypoints) = at(t);
+            let message = state(Seconds(t).ticks(), p, &waypoints, mission);
+            monitor.inspect_at(&message, 0, start + Duration::from_secs_f32(t))
+        })
+    }
+
+    // Around a wall lying between the agent and its goal, the agent first moves away from the
+    // goal, and only gets closer to it than where it started 48s later
+    #[test]
+    fn honest_detours_are_not_penalised() {
+        let start = Vector2::new(0.0, 100.0);
+        let path = path_through(&[
+            start,
+            Vector2::new(-120.0, 100.0),
+            Vector2::new(-120.0, 0.0),
+            Vector2::zeros(),
+        ]);
+        let mission = mission(None);
+        let mut monitor = IntegrityMonitor::new();
+        let duration_s = path.len() as f32 * CELL_SIZE / SPEED;
+        assert!(trusted_for(&mut monitor, duration_s, &mission, |t| {
+            let travelled = t * SPEED / CELL_SIZE;
+            let (reached, along) = (travelled as usize, travelled.fract());
+            let from = if reached == 0 {
+                start
+            } else {
+                path[reached - 1]
+            };
+            let p = from.lerp(&path[reached], along);
+            let waypoints = path.iter().skip(reached).take(INTENT_WAYPOINTS);
+            (p, waypoints.copied().collect())
+        }));
+    }
+
+    #[test]
+    fn stalled_claims_are_quarantined() {
+        let p = Vector2::new(0.0, 100.0);
+        let waypoints = path_through(&[p, Vector2::new(-120.0, 100.0)]);
+        let waypoints: Vec<_> = waypoints.into_iter().take(INTENT_WAYPOINTS).collect();
+        let mission = mission(None);
+        let mut monitor = IntegrityMonitor::new();
+        let timeouts = QUARANTINE_STRIKES as f32 + 1.0;
+        assert!(!trusted_for(
+            &mut monitor,
+            timeouts * CLAIM_PROGRESS_TIMEOUT_S,
+            &mission,
+            |_| (p, waypoints.clone())
+        ));
+    }
+
+    #[test]
+    fn waiting_for_a_window_to_open_is_not_stalling() {
+        let timeouts = QUARANTINE_STRIKES as f32 + 1.0;
+        let opens_s = timeouts * CLAIM_PROGRESS_TIMEOUT_S;
+        let mission = mission(Some((
+            Seconds(opens_s).ticks(),
+            Seconds(2.0 * opens_s).ticks(),
+        )));
+        let at_target = |_| (Vector2::zeros(), vec![Vector2::zeros()]);
+        assert!(trusted_for(
+            &mut IntegrityMonitor::new(),
+            opens_s,
+            &mission,
+            at_target
+        ));
+        assert!(!trusted_for(
+            &mut IntegrityMonitor::new(),
+            2.0 * opens_s,
+            &mission,
+            at_target
+        ));
+    }
+}
@@ -0,0 +1,77 @@
+use crate::agent::AgentMessage;
+use crate::consts::GRID_HALF_SIZE;
+use crate::error::Result;
+use crate::missions::Mission;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::path::Path;
+
+pub fn violations(agents: &HashMap<usize, AgentMessage>, missions: &[&Mission]) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut ids = HashSet::new();
+    for mission in missions {
+        if !ids.insert(mission.id) {
+            out.push(format!("mission id {} is used more than once", mission.id));
+        }
+    }
+
+    let by_id: HashMap<usize, &Mission> = missions.iter().map(|m| (m.id, *m)).collect();
+    for agent in agents.values() {
+        let k = &agent.kinematics;
+        if !k.is_finite() {
+            out.push(format!("agent {} has non-finite kinematics", agent.id));
+        } else if k.p.x.abs() > GRID_HALF_SIZE || k.p.y.abs() > GRID_HALF_SIZE {
+            out.push(format!("agent {} is outside the grid at {}", agent.id, k.p));
+        }
+
+        let claim = match &agent.mission {
+            Some(claim) => claim,
+            None => continue,
+        };
+        if let Some(assignee) = claim.agent.filter(|&assignee| assignee != agent.id) {
+            out.push(format!(
+                "agent {} claims mission {} assigned to agent {}",
+                agent.id, claim.id, assignee
+            ));
+        }
+        if let Some(pooled) = by_id.get(&claim.id) {
+            if pooled.target != claim.target {
+                out.push(format!(
+                    "agent {} claims mission {} with target {}, the pool has {}",
+                    agent.id, claim.id, claim.target, pooled.target
+                ));
+            }
+        }
+    }
+    out
+}
+
+pub fn write_dump(
+    path: &Path,
+    violations: &[String],
+    agents: &HashMap<usize, AgentMessage>,
+    missions: &[&Mission],
+    metrics: &BTreeMap<String, f64>,
+) -> Result<()> {
+    let mut dump = String::new();
+    let _ = writeln!(dump, "Invariant violations:");
+    for violation in violations {
+        let _ = writeln!(dump, "  {}", violation);
+    }
+    let _ = writeln!(dump, "\nAgents:");
+    let mut ids: Vec<_> = agents.keys().collect();
+    ids.sort();
+    for id in ids {
+        let _ = writeln!(dump, "{:#?}", agents[id]);
+    }
+    let _ = writeln!(dump, "\nPending missions:");
+    for mission in missions {
+        let _ = writeln!(dump, "{:?}", mission);
+    }
+    let _ = writeln!(dump, "\nMetrics:");
+    for (name, value) in metrics {
+        let _ = writeln!(dump, "  {}: {}", name, value);
+    }
+    std::fs::write(path, dump)?;
+    Ok(())
+}
@@ -0,0 +1,141 @@
+pub mod agent;
+pub mod analyze;
+mod approach;
+pub mod capacity;
+mod claims;
+mod clustering;
+mod congestion;
+pub mod consts;
+pub mod crash;
+pub mod dataset;
+mod demand;
+pub mod error;
+mod events;
+mod gantt;
+mod gossip;
+mod groups;
+mod integrity;
+mod invariants;
+mod metrics;
+pub mod microbench;
+mod missions;
+mod observation;
+mod obstacles;
+mod pedestrians;
+mod planning;
+pub mod realtime;
+mod rect;
+mod regions;
+pub mod render_sink;
+pub mod renderer;
+mod replenishment;
+pub mod roads;
+mod safety;
+pub mod scenario;
+mod scene_svg;
+mod scheduling;
+mod sequence;
+mod summary;
+pub mod system;
+mod trajectory;
+mod units;
+
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use agent::{Cell, Grid, Kinematics};
+use consts::*;
+use error::Result;
+use nalgebra::Vector2;
+use rect::Rect;
+use scenario::Scenario;
+use system::SystemManager;
+
+pub fn init_grid() -> Grid {
+    let height = GRID_SPLIT as usize;
+    let width = GRID_SPLIT as usize;
+    let mut cells = Vec::with_capacity(height * width);
+    for i in 0..height {
+        for j in 0..width {
+            if i == 0 || j == 0 || i == height - 1 || j == height - 1 {
+                cells.push(Cell::Uncrossable);
+            } else if j > (0.4 * width as f32) as usize && j < (0.6 * width as f32) as usize {
+                cells.push(Cell::Crossable(MAX_COST * i as f32 / height as f32));
+            } else {
+                cells.push(Cell::Crossable(MAX_COST / 2.0));
+            }
+        }
+    }
+    Grid {
+        cells,
+        width,
+        obstacles: Vec::new(),
+        roads: None,
+        labels: HashMap::new(),
+    }
+}
+
+// The default layout, with the terrain, obstacles and labeled regions of the scenario on top
+pub fn init_scenario_grid(scenario: &Scenario) -> Grid {
+    let mut grid = init_grid();
+    for patch in &scenario.terrain {
+        grid.paint(Rect::new(patch.min, patch.max), patch.cost);
+    }
+    grid.add_obstacles(scenario.obstacles.clone());
+    for region in &scenario.labeled_regions {
+        grid.label_region(region.name.clone(), Rect::new(region.min, region.max));
+    }
+    grid
+}
+
+pub fn init_agent_kinematics(n: usize) -> Vec<Kinematics> {
+    let side = (n as f32).sqrt().ceil() as usize;
+    let spacing = GRID_SIZE / (side + 1) as f32;
+    let mut out = Vec::new();
+    for i in 0..side {
+        for j in 0..side {
+            if out.len() == n {
+                return out;
+            }
+            let kinematics = Kinematics {
+                v: Vector2::zeros(),
+                a: Vector2::zeros(),
+                p: Vector2::new(
+                    (j + 1) as f32 * spacing - GRID_HALF_SIZE,
+                    (i + 1) as f32 * spacing - GRID_HALF_SIZE,
+                ),
+                theta: j as f32 * std::f32::consts::PI,
+                omega: 0.0,
+                alpha: 0.0,
+                radius: AGENT_RADIUS,
+            };
+            out.push(kinematics)
+        }
+    }
+    out
+}
+
+pub fn start_simulation(
+    mut system: SystemManager,
+    grid: &Arc<Grid>,
+    agent_kinematics: Vec<Kinematics>,
+) -> Result<(Arc<AtomicBool>, JoinHandle<Result<()>>)> {
+    let mut agents = Vec::new();
+    let mut connection_handlers = Vec::new();
+    agent_kinematics.into_iter().for_each(|agent_kinematic| {
+        let (a, ch) = system.add_agent(agent_kinematic);
+        agents.push(a);
+        connection_handlers.push(ch);
+    });
+
+    let shutdown = system.shutdown_handle();
+    let system_thread = std::thread::Builder::new()
+        .name("SystemManager".to_owned())
+        .spawn(move || system.run())?;
+    for (a, ch) in agents.into_iter().zip(connection_handlers) {
+        a.spawn(ch, grid.clone())?;
+    }
+    Ok((shutdown, system_thread))
+}
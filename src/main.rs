@@ -1,86 +1,201 @@
-mod agent;
-mod consts;
-mod missions;
-mod renderer;
-mod system;
-
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
-use agent::{Cell, Grid, Kinematics};
-use consts::*;
+use allez_ropi_romi::agent::{AllocationStrategy, IdlePolicy, Kinematics};
+use allez_ropi_romi::analyze::run_analysis;
+use allez_ropi_romi::capacity::run_capacity_benchmark;
+use allez_ropi_romi::consts::*;
+use allez_ropi_romi::crash::CrashContext;
+use allez_ropi_romi::dataset::DatasetRecorder;
+use allez_ropi_romi::error::{Error, Result};
+use allez_ropi_romi::microbench::run_mission_benchmarks;
+use allez_ropi_romi::realtime::LoadShedding;
+use allez_ropi_romi::render_sink::{FrameRecorder, RenderSink};
+use allez_ropi_romi::renderer::{LatestStates, Renderer};
+use allez_ropi_romi::roads::RoadNetwork;
+use allez_ropi_romi::scenario::Scenario;
+use allez_ropi_romi::system::{CoordinationMode, SystemManager};
+use allez_ropi_romi::{init_agent_kinematics, init_grid, init_scenario_grid, start_simulation};
 use nalgebra::Vector2;
-use renderer::Renderer;
-use std::sync::mpsc::channel;
-use system::SystemManager;
+use std::path::Path;
 
-fn init_grid() -> Grid {
-    let height = GRID_SPLIT as usize;
-    let width = GRID_SPLIT as usize;
-    let mut cells = Vec::with_capacity(height * width);
-    for i in 0..height {
-        for j in 0..width {
-            if i == 0 || j == 0 || i == height - 1 || j == height - 1 {
-                cells.push(Cell::Uncrossable);
-            } else if j > (0.4 * width as f32) as usize && j < (0.6 * width as f32) as usize {
-                cells.push(Cell::Crossable(MAX_COST * i as f32 / height as f32));
-            } else {
-                cells.push(Cell::Crossable(MAX_COST / 2.0));
-            }
-        }
-    }
-    Grid { cells, width }
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != name);
+    args.next()?;
+    args.next()
 }
 
-fn init_agent_kinematics() -> Vec<Kinematics> {
-    let mut out = Vec::new();
-    for i in 0..2 {
-        for j in 0..2 {
-            let kinematics = Kinematics {
-                v: Vector2::zeros(),
-                a: Vector2::zeros(),
-                p: Vector2::new(
-                    (j + 1) as f32 * GRID_SIZE / 3.0 - GRID_HALF_SIZE,
-                    (i + 1) as f32 * GRID_SIZE / 3.0 - GRID_HALF_SIZE,
-                ),
-                theta: j as f32 * std::f32::consts::PI,
-                radius: 10.0,
-            };
-            out.push(kinematics)
-        }
+fn main() -> Result<()> {
+    let subcommand = std::env::args().nth(1);
+    if subcommand.as_deref() == Some("analyze") {
+        let plot = arg_value("--plot");
+        let summaries: Vec<String> = std::env::args()
+            .skip(2)
+            .filter(|arg| arg != "--plot" && Some(arg) != plot.as_ref())
+            .collect();
+        return run_analysis(&summaries, plot.as_deref().map(Path::new));
     }
-    out
-}
-
-fn main() {
+    let capacity = subcommand.as_deref() == Some("capacity");
+    let bench = subcommand.as_deref() == Some("bench-missions");
     tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
+        .with_max_level(if capacity || bench {
+            tracing::Level::INFO
+        } else {
+            tracing::Level::DEBUG
+        })
         .with_thread_ids(true)
         .with_thread_names(true)
         .init();
-    let grid = Arc::new(init_grid());
-    let agent_kinematics = init_agent_kinematics();
+    if capacity {
+        return run_capacity_benchmark(&Arc::new(init_grid()));
+    }
+    if bench {
+        return run_mission_benchmarks(&Arc::new(init_grid()));
+    }
 
-    let (renderer_tx, rendered_rx) = channel();
-    let renderer = Renderer::new(&grid, rendered_rx);
-    let mut system = SystemManager::new(renderer_tx);
-    let mut agents = Vec::new();
-    let mut connection_handlers = Vec::new();
-    agent_kinematics.into_iter().for_each(|agent_kinematic| {
-        let (a, ch) = system.add_agent(agent_kinematic);
-        agents.push(a);
-        connection_handlers.push(ch);
-    });
+    let (mut scenario, scenario_source) = match arg_value("--scenario") {
+        Some(path) => Scenario::load(Path::new(&path))?,
+        None => (Scenario::default(), String::new()),
+    };
+    if let Some(seed) = arg_value("--seed") {
+        scenario.seed = seed
+            .parse()
+            .map_err(|_| Error::Config(format!("invalid seed: {}", seed)))?;
+    }
+    let mut grid = init_scenario_grid(&scenario);
+    if let Some(path) = arg_value("--roads") {
+        grid.roads = Some(Arc::new(RoadNetwork::load(Path::new(&path))?));
+    }
+    let grid = Arc::new(grid);
 
-    let _system_thread = std::thread::Builder::new()
-        .name("SystemManager".to_owned())
-        .spawn(move || system.run())
-        .unwrap();
-    for (i, (mut a, mut ch)) in agents.into_iter().zip(connection_handlers).enumerate() {
-        let grid = grid.clone();
-        std::thread::Builder::new()
-            .name(format!("Agent {}", i))
-            .spawn(move || a.run(&mut ch, &grid))
-            .unwrap();
+    let render_states = LatestStates::new();
+    let mode = if std::env::args().any(|arg| arg == "--gossip") {
+        CoordinationMode::Gossip
+    } else {
+        CoordinationMode::Centralized
+    };
+    if let CoordinationMode::Gossip = mode {
+        let mut unsupported = scenario.centralized_only();
+        for flag in [
+            "--byzantine",
+            "--check-invariants",
+            "--congestion",
+            "--soft-realtime",
+        ] {
+            if std::env::args().any(|arg| arg == flag) {
+                unsupported.push(flag);
+            }
+        }
+        if !unsupported.is_empty() {
+            return Err(Error::Config(format!(
+                "gossip mode does not support: {}",
+                unsupported.join(", ")
+            )));
+        }
+    }
+    let mut render_sinks: Vec<Arc<dyn RenderSink>> = vec![Arc::new(render_states.clone())];
+    if let Some(path) = arg_value("--record-frames") {
+        render_sinks.push(Arc::new(FrameRecorder::create(Path::new(&path))?));
     }
+    let mut system = SystemManager::new(grid.clone(), Arc::new(render_sinks), mode);
+    if let Some(n) = arg_value("--byzantine") {
+        system
+            .set_byzantine_agents(n.parse().map_err(|_| {
+                Error::Config(format!("invalid number of byzantine agents: {}", n))
+            })?);
+    }
+    match arg_value("--allocation").as_deref() {
+        None | Some("nearest") => {}
+        Some("clustered") => system.set_allocation_strategy(AllocationStrategy::Clustered),
+        Some(other) => {
+            return Err(Error::Config(format!(
+                "unknown allocation strategy: {}",
+                other
+            )))
+        }
+    }
+
+    match arg_value("--idle").as_deref() {
+        None | Some("drift") => {}
+        Some("hold") => system.set_idle_policy(IdlePolicy::Hold),
+        Some("home") => system.set_idle_policy(IdlePolicy::ReturnHome),
+        Some("spread") => system.set_idle_policy(IdlePolicy::SpreadOut),
+        Some("anticipate") => system.set_idle_policy(IdlePolicy::Anticipate),
+        Some(other) => return Err(Error::Config(format!("unknown idle policy: {}", other))),
+    }
+    if std::env::args().any(|arg| arg == "--keep-connected") {
+        system.keep_agents_connected();
+    }
+    if std::env::args().any(|arg| arg == "--safety-filter") {
+        system.enable_safety_filter();
+    }
+    if std::env::args().any(|arg| arg == "--congestion") {
+        system.enable_congestion();
+    }
+    let load_shedding = std::env::args()
+        .any(|arg| arg == "--soft-realtime")
+        .then(LoadShedding::new);
+    if let Some(shedding) = &load_shedding {
+        system.set_load_shedding(shedding.clone());
+    }
+    if std::env::args().any(|arg| arg == "--check-invariants") {
+        system.check_invariants();
+    }
+
+    let crash = CrashContext::new(format!(
+        "arguments: {:?}\nscenario: {:#?}\n",
+        std::env::args().collect::<Vec<_>>(),
+        scenario
+    ));
+    crash.install();
+    system.record_crashes(crash);
+
+    let mut renderer = Renderer::new(&grid, render_states, system.operator_handle())?;
+    renderer.set_camera_tour(scenario.camera.clone());
+    renderer.set_pedestrians(system.pedestrians());
+    if let Some(congestion) = system.congestion() {
+        renderer.set_congestion(congestion);
+    }
+    if let Some(shedding) = load_shedding {
+        renderer.set_load_shedding(shedding);
+    }
+    if let Some(base) = scenario.base {
+        renderer.add_base(Vector2::new(base[0], base[1]));
+    }
+    let agent_kinematics = if scenario.agents.is_empty() {
+        init_agent_kinematics(4)
+    } else {
+        scenario
+            .agents
+            .iter()
+            .map(|p| Kinematics {
+                v: Vector2::zeros(),
+                a: Vector2::zeros(),
+                p: Vector2::new(p[0], p[1]),
+                theta: 0.0,
+                omega: 0.0,
+                alpha: 0.0,
+                radius: AGENT_RADIUS,
+            })
+            .collect()
+    };
+    system.set_scenario(scenario);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    system.set_config_source(format!("{}\n{}", args.join(" "), scenario_source));
+    if let Some(path) = arg_value("--dataset") {
+        system.record_dataset(DatasetRecorder::create(
+            Path::new(&path),
+            system.observation_config(),
+        )?);
+    }
+    let (shutdown, system_thread) = start_simulation(system, &grid, agent_kinematics)?;
     renderer.run();
+    shutdown.store(true, Ordering::Relaxed);
+    match system_thread.join() {
+        Ok(result) => result,
+        Err(_) => {
+            log::error!("System manager panicked");
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,41 @@
+use log::*;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Default)]
+pub struct Metrics {
+    values: Arc<Mutex<BTreeMap<String, f64>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn add(&self, name: &str, amount: f64) {
+        *self
+            .values
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_insert(0.0) += amount;
+    }
+
+    pub fn set(&self, name: &str, value: f64) {
+        self.values.lock().unwrap().insert(name.to_owned(), value);
+    }
+
+    pub fn increment(&self, name: &str) {
+        self.add(name, 1.0);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, f64> {
+        self.values.lock().unwrap().clone()
+    }
+
+    pub fn log_report(&self) {
+        for (name, value) in self.snapshot() {
+            info!("{}: {}", name, value);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::agent::{AgentMessage, Grid};
+use crate::consts::*;
+use crate::error::Result;
+use crate::init_agent_kinematics;
+use crate::missions::{Mission, MissionPool};
+use crate::render_sink::NullSink;
+use crate::system::{CoordinationMode, SystemManager};
+use nalgebra::Vector2;
+use rand::distributions::{Distribution, Uniform};
+use rand_pcg::Pcg64;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn time_per_call(mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..BENCH_ITERATIONS {
+        f();
+    }
+    start.elapsed() / BENCH_ITERATIONS as u32
+}
+
+// Times mission selection and conflict checks of a single agent, with the rest of the fleet
+// claiming its mission so that the conflict check always has to look for another one
+pub fn run_mission_benchmarks(grid: &Arc<Grid>) -> Result<()> {
+    let mut rng = Pcg64::new(SEED, BENCH_STREAM);
+    let coordinate = Uniform::new(-GRID_HALF_SIZE, GRID_HALF_SIZE);
+    println!("missions | get_new_mission | check_missions");
+    for &count in &BENCH_MISSION_COUNTS {
+        let mut pool = MissionPool::default();
+        for id in 0..count {
+            pool.insert(Mission {
+                id,
+                agent: None,
+                target: Vector2::new(coordinate.sample(&mut rng), coordinate.sample(&mut rng)),
+                target_theta: None,
+                urgent: false,
+                priority: 0,
+                return_to: None,
+                visited: false,
+                group: None,
+                window: None,
+                camera: None,
+                created: 0,
+                reward: None,
+            });
+        }
+
+        let mut system = SystemManager::new(
+            grid.clone(),
+            Arc::new(NullSink),
+            CoordinationMode::Centralized,
+        );
+        system.disable_reports();
+        let mut fleet: Vec<_> = init_agent_kinematics(BENCH_AGENTS)
+            .into_iter()
+            .map(|kinematics| system.add_agent(kinematics))
+            .collect();
+        let (mut agent, _connection_handle) = fleet.remove(0);
+        let contested = pool.get(0).cloned();
+        let agents: HashMap<usize, AgentMessage> = fleet
+            .iter()
+            .map(|(other, _)| {
+                let mut kinematics = other.kinematics.clone();
+                kinematics.p = contested.as_ref().map_or(kinematics.p, |m| m.target);
+                let state = AgentMessage {
+                    id: other.id,
+                    seq: 0,
+                    tick: 0,
+                    kinematics,
+                    mission: contested.clone(),
+                    wear: 0.0,
+                    completed: 0,
+                    plan: Vec::new(),
+                    waypoints: Vec::new(),
+                    cargo: Vec::new(),
+                };
+                (other.id, state)
+            })
+            .collect();
+
+        let selection = time_per_call(|| {
+            agent.mission = None;
+            agent.get_new_mission(grid, &pool, &agents);
+        });
+        let check = time_per_call(|| {
+            agent.mission = contested.clone();
+            agent.check_missions(&pool, &agents);
+        });
+        println!("{:>8} | {:>15?} | {:>14?}", count, selection, check);
+    }
+    Ok(())
+}
@@ -1,41 +1,351 @@
-use crate::agent::AgentMessage;
-use crate::consts::{AGENT_RADIUS, CELL_SIZE, DISTANCE_TO_TARGET, GRID_HALF_SIZE};
+use crate::agent::{AgentMessage, Cell, Grid, Kinematics};
+use crate::consts::{
+    AGENT_RADIUS, CAMERA_STANDOFF, CELL_SIZE, CONTROL_RATE_HZ, DISTANCE_TO_TARGET, GRID_HALF_SIZE,
+    MAX_TARGET_SAMPLES, ORIENTATION_TOLERANCE, ORIENTED_MISSION_RATIO, PREEMPTED_PRIORITY,
+    PRIORITY_LEVELS, RETURN_MISSION_RATIO, VIEWPOINT_DIRECTIONS,
+};
+use crate::obstacles::Obstacle;
+use crate::rect::Rect;
+use crate::roads::RoadNetwork;
+use crate::scenario::{MissionTarget, PendingMission};
+use crate::units::Seconds;
 use log::*;
 use nalgebra::Vector2;
 use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
 use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::sync::Arc;
 use std::{collections::HashMap, fmt};
 
+// Camera a mission has to observe its target with, instead of driving onto it: the target must lie
+// within range and within the field of view, in radians, centered on the heading of the agent
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct CameraSensor {
+    pub fov: f32,
+    pub range: f32,
+}
+
+impl CameraSensor {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.fov.is_finite() || self.fov <= 0.0 || self.fov > 2.0 * PI {
+            return Err(format!(
+                "camera field of view must be within (0, 2pi], got {}",
+                self.fov
+            ));
+        }
+        if !self.range.is_finite() || self.range <= 0.0 {
+            return Err(format!("camera range must be positive, got {}", self.range));
+        }
+        Ok(())
+    }
+
+    pub fn sees(&self, grid: &Grid, kinematics: &Kinematics, target: &Vector2<f32>) -> bool {
+        let d = target - kinematics.p;
+        d.norm() <= self.range
+            && angle_difference(kinematics.theta, d.y.atan2(d.x)).abs() <= self.fov / 2.0
+            && grid.line_of_sight(&kinematics.p, target)
+    }
+
+    // Where to observe the target from, coming from p, and the heading facing it from there. The
+    // side of p is preferred, then directions further and further around the target, until one
+    // gives a free spot with a clear view.
+    pub fn viewpoint(
+        &self,
+        grid: &Grid,
+        p: &Vector2<f32>,
+        target: &Vector2<f32>,
+    ) -> (Vector2<f32>, f32) {
+        let d = p - target;
+        let towards_p = if d.norm() > 0.0 { d.y.atan2(d.x) } else { 0.0 };
+        let at = |angle: f32| {
+            target + CAMERA_STANDOFF * self.range * Vector2::new(angle.cos(), angle.sin())
+        };
+        let angle = (0..VIEWPOINT_DIRECTIONS)
+            .map(|k| {
+                let turn = k.div_ceil(2) as f32 * 2.0 * PI / VIEWPOINT_DIRECTIONS as f32;
+                if k % 2 == 0 {
+                    towards_p + turn
+                } else {
+                    towards_p - turn
+                }
+            })
+            .find(|&angle| {
+                let viewpoint = at(angle);
+                matches!(grid.cell_at(&viewpoint), Some(Cell::Crossable(_)))
+                    && grid.line_of_sight(&viewpoint, target)
+            })
+            .unwrap_or(towards_p);
+        (at(angle), angle_difference(0.0, angle + PI))
+    }
+}
+
+// Share of the missions given a service window, opening up to max_delay_s after their creation
+// and staying open for length_s
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TimeWindowConfig {
+    pub ratio: f64,
+    pub max_delay_s: f32,
+    pub length_s: f32,
+}
+
+impl TimeWindowConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.ratio) {
+            return Err(format!("ratio must be between 0 and 1, got {}", self.ratio));
+        }
+        if !self.max_delay_s.is_finite() || self.max_delay_s < 0.0 {
+            return Err(format!(
+                "maximum delay must not be negative, got {}s",
+                self.max_delay_s
+            ));
+        }
+        if !self.length_s.is_finite() || self.length_s <= 0.0 {
+            return Err(format!("length must be positive, got {}s", self.length_s));
+        }
+        Ok(())
+    }
+}
+
+// How the reward of a mission falls while it waits: by per_s every second, or by half every
+// half_life_s
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "decay")]
+pub enum RewardDecay {
+    Linear { per_s: f32 },
+    Exponential { half_life_s: f32 },
+}
+
+// Reward for completing a mission as soon as it is created
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RewardConfig {
+    pub value: f32,
+    #[serde(flatten)]
+    pub decay: RewardDecay,
+}
+
+impl RewardConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.value.is_finite() || self.value <= 0.0 {
+            return Err(format!("reward must be positive, got {}", self.value));
+        }
+        match self.decay {
+            RewardDecay::Linear { per_s } if !per_s.is_finite() || per_s < 0.0 => Err(format!(
+                "linear decay must not be negative, got {}/s",
+                per_s
+            )),
+            RewardDecay::Exponential { half_life_s }
+                if !half_life_s.is_finite() || half_life_s <= 0.0 =>
+            {
+                Err(format!("half life must be positive, got {}s", half_life_s))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Reward left once the mission waited for elapsed
+    pub fn after(&self, elapsed: Seconds) -> f32 {
+        match self.decay {
+            RewardDecay::Linear { per_s } => (self.value - per_s * elapsed.0).max(0.0),
+            RewardDecay::Exponential { half_life_s } => {
+                self.value * 0.5f32.powf(elapsed / Seconds(half_life_s))
+            }
+        }
+    }
+}
+
 pub struct MissionManager {
     missions: HashMap<usize, Mission>,
+    waiting_since: HashMap<usize, u64>,
     id_counter: usize,
+    id_step: usize,
     rng: Pcg64,
-    between: Uniform<f32>,
+    between_x: Uniform<f32>,
+    between_y: Uniform<f32>,
+    closed_zones: Vec<(Vector2<f32>, Vector2<f32>)>,
+    base: Option<Vector2<f32>>,
+    obstacles: Vec<Obstacle>,
+    group_shares: Vec<f64>,
+    roads: Option<Arc<RoadNetwork>>,
+    time_windows: Option<TimeWindowConfig>,
+    reward: Option<RewardConfig>,
 }
 
 impl MissionManager {
-    pub fn new() -> Self {
+    pub fn for_region(
+        index: usize,
+        count: usize,
+        min: Vector2<f32>,
+        max: Vector2<f32>,
+        seed: u128,
+    ) -> Self {
+        let margin = CELL_SIZE + AGENT_RADIUS;
+        let low = Vector2::repeat(-GRID_HALF_SIZE + margin).sup(&min);
+        let high = Vector2::repeat(GRID_HALF_SIZE - margin).inf(&max);
         MissionManager {
             missions: HashMap::new(),
-            id_counter: 0,
-            between: Uniform::new(
-                CELL_SIZE + AGENT_RADIUS - GRID_HALF_SIZE,
-                GRID_HALF_SIZE - CELL_SIZE - AGENT_RADIUS,
-            ),
-            rng: rand_pcg::Pcg64::new(0, 0),
+            waiting_since: HashMap::new(),
+            id_counter: index,
+            id_step: count,
+            between_x: Uniform::new(low.x, high.x),
+            between_y: Uniform::new(low.y, high.y),
+            rng: rand_pcg::Pcg64::new(seed, 2 * index as u128),
+            closed_zones: Vec::new(),
+            base: None,
+            obstacles: Vec::new(),
+            group_shares: Vec::new(),
+            roads: None,
+            time_windows: None,
+            reward: None,
+        }
+    }
+
+    // Missions are numbered by region, but may have been moved to another one since
+    pub fn owns(&self, mission_id: usize) -> bool {
+        self.missions.contains_key(&mission_id)
+    }
+
+    // Hands a pending mission over to another manager, with the tick it has been waiting since
+    pub fn take(&mut self, id: usize) -> Option<(Mission, Option<u64>)> {
+        let mission = self.missions.remove(&id)?;
+        Some((mission, self.waiting_since.remove(&id)))
+    }
+
+    pub fn adopt(&mut self, mission: Mission, waiting_since: Option<u64>) {
+        if let Some(since) = waiting_since {
+            self.waiting_since.insert(mission.id, since);
         }
+        self.missions.insert(mission.id, mission);
+    }
+
+    pub fn pending_missions(&self) -> Vec<Mission> {
+        self.missions.values().cloned().collect()
     }
 
-    pub fn create_new_missions(&mut self, n: usize) -> Vec<Mission> {
+    pub fn missions(&self) -> impl Iterator<Item = &Mission> {
+        self.missions.values()
+    }
+
+    pub fn set_base(&mut self, base: Vector2<f32>) {
+        self.base = Some(base);
+    }
+
+    pub fn set_time_windows(&mut self, config: TimeWindowConfig) {
+        self.time_windows = Some(config);
+    }
+
+    pub fn set_reward(&mut self, config: RewardConfig) {
+        self.reward = Some(config);
+    }
+
+    fn sample_window(&mut self, now: u64) -> Option<(u64, u64)> {
+        let config = self.time_windows.as_ref()?;
+        if !self.rng.gen_bool(config.ratio) {
+            return None;
+        }
+        let (max_delay_s, length_s) = (config.max_delay_s, config.length_s);
+        let earliest = now + Seconds(self.rng.gen_range(0.0..=max_delay_s)).ticks();
+        Some((earliest, earliest + Seconds(length_s).ticks()))
+    }
+
+    // Targets are then moved to the nearest road node
+    pub fn set_roads(&mut self, roads: Arc<RoadNetwork>) {
+        self.roads = Some(roads);
+    }
+
+    pub fn set_obstacles(&mut self, obstacles: Vec<Obstacle>) {
+        self.obstacles = obstacles;
+    }
+
+    // Fraction of the new missions scoped to each group, by group index
+    pub fn set_group_shares(&mut self, shares: Vec<f64>) {
+        self.group_shares = shares;
+    }
+
+    fn sample_group(&mut self) -> Option<usize> {
+        if self.group_shares.is_empty() {
+            return None;
+        }
+        let mut draw = self.rng.gen::<f64>();
+        for (i, share) in self.group_shares.iter().enumerate() {
+            if draw < *share {
+                return Some(i);
+            }
+            draw -= share;
+        }
+        None
+    }
+
+    fn is_blocked(&self, p: &Vector2<f32>) -> bool {
+        self.obstacles
+            .iter()
+            .any(|o| o.signed_distance(p) < AGENT_RADIUS)
+    }
+
+    fn is_closed(&self, p: &Vector2<f32>) -> bool {
+        self.closed_zones
+            .iter()
+            .any(|(min, max)| p.x >= min.x && p.y >= min.y && p.x <= max.x && p.y <= max.y)
+    }
+
+    // Stops creating missions in the zone, and cancels the pending ones it contains
+    pub fn close_zone(&mut self, min: Vector2<f32>, max: Vector2<f32>) -> Vec<usize> {
+        self.closed_zones.push((min, max));
+        let cancelled: Vec<usize> = self
+            .missions
+            .values()
+            .filter(|m| self.is_closed(&m.target))
+            .map(|m| m.id)
+            .collect();
+        for id in &cancelled {
+            self.missions.remove(id);
+            self.waiting_since.remove(id);
+        }
+        cancelled
+    }
+
+    fn sample_target(&mut self) -> Vector2<f32> {
+        let mut target = Vector2::zeros();
+        for _ in 0..MAX_TARGET_SAMPLES {
+            target = Vector2::new(
+                self.between_x.sample(&mut self.rng),
+                self.between_y.sample(&mut self.rng),
+            );
+            if let Some(roads) = &self.roads {
+                target = roads.nearest_node(&target);
+            }
+            if !self.is_closed(&target) && !self.is_blocked(&target) {
+                break;
+            }
+        }
+        target
+    }
+
+    pub fn create_new_missions(&mut self, n: usize, now: u64) -> Vec<Mission> {
         let mut out = Vec::new();
         for _i in 0..n {
             let mission = Mission {
                 id: self.id_counter,
                 agent: None,
-                target: Vector2::new(
-                    self.between.sample(&mut self.rng),
-                    self.between.sample(&mut self.rng),
-                ),
+                target: self.sample_target(),
+                target_theta: if self.rng.gen_bool(ORIENTED_MISSION_RATIO) {
+                    Some(self.rng.gen_range(-PI..PI))
+                } else {
+                    None
+                },
+                urgent: false,
+                priority: 0,
+                return_to: match self.base {
+                    Some(base) if self.rng.gen_bool(RETURN_MISSION_RATIO) => Some(base),
+                    _ => None,
+                },
+                visited: false,
+                group: self.sample_group(),
+                window: self.sample_window(now),
+                camera: None,
+                created: now,
+                reward: self.reward,
             };
             info!(
                 "Mission {} created with target: {}",
@@ -43,51 +353,476 @@ impl MissionManager {
             );
             self.missions.insert(self.id_counter, mission.clone());
             out.push(mission);
-            self.id_counter += 1;
+            self.id_counter += self.id_step;
         }
         out
     }
 
+    // Free point of the box, if one is found
+    fn sample_inside(&mut self, rect: &Rect) -> Vector2<f32> {
+        let mut target = rect.center();
+        for _ in 0..MAX_TARGET_SAMPLES {
+            target = rect.sample(&mut self.rng);
+            if !self.is_blocked(&target) {
+                break;
+            }
+        }
+        target
+    }
+
+    // Missions given by a scenario rather than sampled, to a point or to anywhere in a labeled
+    // region. None if the region is unknown.
+    pub fn add_mission(
+        &mut self,
+        pending: &PendingMission,
+        grid: &Grid,
+        now: u64,
+    ) -> Option<Mission> {
+        let point = |p: [f32; 2]| Vector2::new(p[0], p[1]);
+        let target = match &pending.target {
+            MissionTarget::Point(p) => point(*p),
+            MissionTarget::Region(name) => self.sample_inside(&grid.region(name)?),
+        };
+        let mission = Mission {
+            id: self.id_counter,
+            agent: None,
+            target,
+            target_theta: pending.target_theta,
+            urgent: false,
+            priority: 0,
+            return_to: pending.return_to.map(point),
+            visited: false,
+            group: pending.group,
+            window: pending.window_s.map(|[earliest, latest]| {
+                (
+                    now + Seconds(earliest).ticks(),
+                    now + Seconds(latest).ticks(),
+                )
+            }),
+            camera: pending.camera,
+            created: now,
+            reward: pending.reward.or(self.reward),
+        };
+        info!(
+            "Mission {} added with target: {}",
+            self.id_counter, mission.target
+        );
+        self.missions.insert(self.id_counter, mission.clone());
+        self.id_counter += self.id_step;
+        Some(mission)
+    }
+
     pub fn finish_mission(&mut self, id: usize) {
         self.missions.remove(&id);
+        self.waiting_since.remove(&id);
+    }
+
+    // Raises the priority of pending missions with the time they have been waiting, reaching the
+    // highest level at max_wait ticks. Returns the missions whose priority changed, and those that
+    // waited past max_wait, which are made urgent.
+    pub fn age(&mut self, now: u64, max_wait: u64) -> (Vec<Mission>, Vec<Mission>) {
+        let mut raised = Vec::new();
+        let mut overdue = Vec::new();
+        for mission in self.missions.values_mut() {
+            let since = *self.waiting_since.entry(mission.id).or_insert(now);
+            let waited = now.saturating_sub(since);
+            if waited >= max_wait && !mission.urgent {
+                mission.urgent = true;
+                overdue.push(mission.clone());
+                continue;
+            }
+            let level = ((waited * PRIORITY_LEVELS as u64) / max_wait.max(1)) as u32;
+            if level > mission.priority {
+                mission.priority = level.min(PRIORITY_LEVELS);
+                raised.push(mission.clone());
+            }
+        }
+        (raised, overdue)
+    }
+
+    // Removes the missions whose window closed before anyone completed them
+    pub fn expire(&mut self, now: u64) -> Vec<Mission> {
+        let expired: Vec<usize> = self
+            .missions
+            .values()
+            .filter(|m| m.has_expired(now))
+            .map(|m| m.id)
+            .collect();
+        expired
+            .iter()
+            .filter_map(|id| {
+                self.waiting_since.remove(id);
+                self.missions.remove(id)
+            })
+            .collect()
+    }
+
+    // Missions abandoned for an urgent one inherit an elevated priority so they are not starved
+    pub fn inherit_priority(&mut self, id: usize) -> Option<Mission> {
+        let mission = self.missions.get_mut(&id)?;
+        if mission.priority >= PREEMPTED_PRIORITY {
+            return None;
+        }
+        mission.priority = PREEMPTED_PRIORITY;
+        Some(mission.clone())
+    }
+
+    // Missions handed off keep their priority if it is higher than that of an abandoned one
+    pub fn hand_off(&mut self, id: usize) -> Option<Mission> {
+        let mission = self.missions.get_mut(&id)?;
+        mission.priority = mission.priority.max(PREEMPTED_PRIORITY);
+        Some(mission.clone())
+    }
+
+    pub fn make_urgent(&mut self, id: usize) -> Option<Mission> {
+        let mission = self.missions.get_mut(&id)?;
+        mission.urgent = true;
+        Some(mission.clone())
     }
 
     pub fn number_missions_left(&self) -> usize {
         self.missions.len()
     }
 
-    pub fn mission_to_finish(&mut self, agent_message: &AgentMessage) -> Option<usize> {
-        let mission = agent_message.mission.as_ref()?;
-        if (agent_message.kinematics.p - mission.target).norm() < DISTANCE_TO_TARGET {
-            self.finish_mission(mission.id);
-            Some(mission.id)
-        } else {
-            None
+    // Only the first agent to complete a mission gets it: later completions of a mission that is
+    // no longer pending are ignored
+    pub fn mission_to_finish(
+        &mut self,
+        grid: &Grid,
+        agent_message: &AgentMessage,
+        mission: &Mission,
+    ) -> Option<Mission> {
+        if !mission.is_completed_by(grid, &agent_message.kinematics)
+            || !mission.is_open(agent_message.tick)
+        {
+            return None;
+        }
+        match self.missions.remove(&mission.id) {
+            Some(pending) => Some(pending),
+            None => {
+                debug!(
+                    "Agent {} completed mission {} after it was already finished",
+                    agent_message.id, mission.id
+                );
+                None
+            }
         }
     }
 }
 
 #[derive(Debug)]
-pub struct MissionMessage(pub Vec<Mission>);
+pub struct MissionMessage {
+    pub tick: u64,
+    pub missions: Vec<Mission>,
+}
+
+#[derive(Debug)]
+pub struct WorldSync {
+    pub tick: u64,
+    pub missions: Vec<Mission>,
+    pub agents: Vec<AgentMessage>,
+}
 
 #[derive(Clone, Debug)]
 pub struct Mission {
     pub id: usize,
     pub agent: Option<usize>,
     pub target: Vector2<f32>,
+    pub target_theta: Option<f32>,
+    pub urgent: bool,
+    // Raised while the mission waits and when it is abandoned for an urgent one; agents favour
+    // missions with a higher priority
+    pub priority: u32,
+    // Missions bringing something back have to return to this point once the target is visited
+    pub return_to: Option<Vector2<f32>>,
+    pub visited: bool,
+    // Only agents of this group, by index in the scenario groups, are told about the mission
+    pub group: Option<usize>,
+    // Earliest and latest tick it may be completed at; it expires once the window closes
+    pub window: Option<(u64, u64)>,
+    // Observation missions are completed from a distance, looking at their target
+    pub camera: Option<CameraSensor>,
+    // Tick it was created at, from which its reward decays
+    pub created: u64,
+    pub reward: Option<RewardConfig>,
+}
+
+pub fn angle_difference(from: f32, to: f32) -> f32 {
+    (to - from + PI).rem_euclid(2.0 * PI) - PI
+}
+
+impl Mission {
+    // Squared distance from p to the target, shrunk for missions with a higher priority
+    pub fn selection_cost(&self, p: &Vector2<f32>) -> f32 {
+        (self.target - p).norm_squared() / (1.0 + self.priority as f32).powi(2)
+    }
+
+    // Reward earned by completing the mission at the given tick, if it has one
+    pub fn reward_at(&self, tick: u64) -> Option<f32> {
+        let waited = Seconds(tick.saturating_sub(self.created) as f32 / CONTROL_RATE_HZ);
+        self.reward.map(|reward| reward.after(waited))
+    }
+
+    pub fn is_open(&self, tick: u64) -> bool {
+        self.window
+            .is_none_or(|(earliest, latest)| tick >= earliest && tick <= latest)
+    }
+
+    pub fn has_expired(&self, tick: u64) -> bool {
+        self.window.is_some_and(|(_, latest)| tick > latest)
+    }
+
+    pub fn current_goal(&self) -> (Vector2<f32>, Option<f32>) {
+        match self.return_to {
+            Some(base) if self.visited => (base, None),
+            _ => (self.target, self.target_theta),
+        }
+    }
+
+    // Where an agent at p heads for: the current goal, or a viewpoint of the target for observation
+    // missions, which then override the heading of the mission
+    pub fn goal_from(&self, grid: &Grid, p: &Vector2<f32>) -> (Vector2<f32>, Option<f32>) {
+        match self.camera {
+            Some(camera) if !self.visited => {
+                let (viewpoint, theta) = camera.viewpoint(grid, p, &self.target);
+                (viewpoint, Some(theta))
+            }
+            _ => self.current_goal(),
+        }
+    }
+
+    // Whether the agent stands on its current goal, with the right heading if one is required, or
+    // sees the target of an observation mission
+    pub fn goal_reached_by(&self, grid: &Grid, kinematics: &Kinematics) -> bool {
+        if let Some(camera) = self.camera.filter(|_| !self.visited) {
+            return camera.sees(grid, kinematics, &self.target);
+        }
+        let (goal, goal_theta) = self.current_goal();
+        (kinematics.p - goal).norm() < DISTANCE_TO_TARGET
+            && goal_theta.is_none_or(|goal_theta| {
+                angle_difference(kinematics.theta, goal_theta).abs() < ORIENTATION_TOLERANCE
+            })
+    }
+
+    pub fn is_completed_by(&self, grid: &Grid, kinematics: &Kinematics) -> bool {
+        (self.return_to.is_none() || self.visited) && self.goal_reached_by(grid, kinematics)
+    }
 }
 
 impl fmt::Display for Mission {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "[id: {}, agent: {}, target: {}]",
+            "[id: {}, agent: {}, target: {}, heading: {}]",
             self.id,
             match self.agent {
                 Some(id) => id.to_string(),
                 None => "None".to_owned(),
             },
-            self.target
+            self.target,
+            match self.target_theta {
+                Some(theta) => theta.to_string(),
+                None => "any".to_owned(),
+            }
+        )
+    }
+}
+
+// The missions an agent knows about, stored contiguously so that selection scans a slice
+#[derive(Default)]
+pub struct MissionPool {
+    missions: Vec<Mission>,
+    slots: HashMap<usize, usize>,
+}
+
+impl MissionPool {
+    pub fn insert(&mut self, mission: Mission) {
+        match self.slots.get(&mission.id) {
+            Some(&slot) => self.missions[slot] = mission,
+            None => {
+                self.slots.insert(mission.id, self.missions.len());
+                self.missions.push(mission);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Mission> {
+        let slot = self.slots.remove(&id)?;
+        let removed = self.missions.swap_remove(slot);
+        if let Some(moved) = self.missions.get(slot) {
+            self.slots.insert(moved.id, slot);
+        }
+        Some(removed)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Mission> {
+        self.slots.get(&id).map(|&slot| &self.missions[slot])
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Mission> {
+        self.missions.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::SEED;
+
+    fn grid() -> Grid {
+        Grid {
+            cells: Vec::new(),
+            width: 0,
+            obstacles: Vec::new(),
+            roads: None,
+            labels: HashMap::new(),
+        }
+    }
+
+    fn manager() -> MissionManager {
+        MissionManager::for_region(
+            0,
+            1,
+            Vector2::repeat(-GRID_HALF_SIZE),
+            Vector2::repeat(GRID_HALF_SIZE),
+            SEED,
         )
     }
+
+    fn add_mission_at(manager: &mut MissionManager, x: f32, y: f32) -> Mission {
+        let pending = PendingMission {
+            target: MissionTarget::Point([x, y]),
+            target_theta: None,
+            return_to: None,
+            group: None,
+            window_s: None,
+            camera: None,
+            reward: None,
+        };
+        manager.add_mission(&pending, &grid(), 0).unwrap()
+    }
+
+    fn state_at(id: usize, p: Vector2<f32>, mission: &Mission) -> AgentMessage {
+        AgentMessage {
+            id,
+            seq: 1,
+            tick: 0,
+            kinematics: Kinematics {
+                p,
+                v: Vector2::zeros(),
+                a: Vector2::zeros(),
+                theta: 0.0,
+                omega: 0.0,
+                alpha: 0.0,
+                radius: AGENT_RADIUS,
+            },
+            mission: Some(mission.clone()),
+            wear: 0.0,
+            completed: 0,
+            plan: Vec::new(),
+            waypoints: Vec::new(),
+            cargo: Vec::new(),
+        }
+    }
+
+    fn mission(id: usize, target: Vector2<f32>) -> Mission {
+        Mission {
+            id,
+            agent: None,
+            target,
+            target_theta: None,
+            urgent: false,
+            priority: 0,
+            return_to: None,
+            visited: false,
+            group: None,
+            window: None,
+            camera: None,
+            created: 0,
+            reward: None,
+        }
+    }
+
+    // Every slot of the pool holds the mission it is indexed under, whatever the order of inserts,
+    // replacements and removals
+    #[test]
+    fn mission_pool_slots_stay_consistent() {
+        let mut rng = Pcg64::new(SEED, 0);
+        let mut pool = MissionPool::default();
+        let mut expected: HashMap<usize, f32> = HashMap::new();
+        for step in 0..2000 {
+            let id = rng.gen_range(0..32);
+            if rng.gen_bool(0.4) {
+                assert_eq!(
+                    pool.remove(id).map(|m| m.target.x),
+                    expected.remove(&id),
+                    "step {}",
+                    step
+                );
+            } else {
+                let x = step as f32;
+                pool.insert(mission(id, Vector2::new(x, 0.0)));
+                expected.insert(id, x);
+            }
+
+            assert_eq!(pool.iter().count(), expected.len(), "step {}", step);
+            for m in pool.iter() {
+                assert_eq!(pool.get(m.id).map(|g| g.target.x), Some(m.target.x));
+            }
+            for (&id, &x) in &expected {
+                assert_eq!(pool.get(id).map(|m| (m.id, m.target.x)), Some((id, x)));
+            }
+        }
+    }
+
+    // Two agents standing on the same target both report it completed: only the first gets it
+    #[test]
+    fn co_located_agents_finish_a_mission_once() {
+        let mut manager = manager();
+        let target = add_mission_at(&mut manager, 10.0, 10.0);
+        add_mission_at(&mut manager, -10.0, -10.0);
+        assert_eq!(manager.number_missions_left(), 2);
+
+        let first = state_at(0, target.target, &target);
+        let second = state_at(1, target.target, &target);
+        assert_eq!(
+            manager
+                .mission_to_finish(&grid(), &first, &target)
+                .map(|m| m.id),
+            Some(target.id)
+        );
+        assert!(manager
+            .mission_to_finish(&grid(), &second, &target)
+            .is_none());
+        assert_eq!(manager.number_missions_left(), 1);
+    }
+
+    // Aged every tick, each mission becomes urgent once it has waited max_wait ticks, and only
+    // then, whether it was preempted or added while others were already waiting
+    #[test]
+    fn no_mission_waits_past_max_wait_without_becoming_urgent() {
+        let max_wait = 100;
+        let mut manager = manager();
+        let mut added = HashMap::new();
+        for now in 0..=3 * max_wait {
+            if now % 40 == 0 {
+                let mission = add_mission_at(&mut manager, now as f32 / 10.0, 0.0);
+                added.insert(mission.id, now);
+            }
+            if now == 30 {
+                let preempted = *added.keys().min().unwrap();
+                assert!(manager.inherit_priority(preempted).is_some());
+            }
+
+            let (_, overdue) = manager.age(now, max_wait);
+            for mission in &overdue {
+                assert_eq!(now - added[&mission.id], max_wait);
+            }
+            for mission in manager.missions() {
+                let waited = now - added[&mission.id];
+                assert_eq!(mission.urgent, waited >= max_wait, "tick {}", now);
+                assert!(mission.priority <= PRIORITY_LEVELS);
+            }
+        }
+    }
 }
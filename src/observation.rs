@@ -0,0 +1,162 @@
+use crate::agent::{AgentMessage, Cell, Grid, Kinematics};
+use crate::consts::{CELL_SIZE, GRID_HALF_SIZE, MAX_ANGULAR_ACCELERATION, MAX_COST, MAX_SPEED};
+use crate::missions::{Mission, MissionPool};
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Which features make up an observation; the layout only depends on these counts so that
+// every agent of a run produces vectors of the same length and meaning
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ObservationConfig {
+    pub own_kinematics: bool,
+    pub nearest_agents: usize,
+    pub nearest_missions: usize,
+    // In cells around the agent; 0 disables the grid patch
+    pub grid_patch_radius: usize,
+}
+
+impl Default for ObservationConfig {
+    fn default() -> Self {
+        ObservationConfig {
+            own_kinematics: true,
+            nearest_agents: 3,
+            nearest_missions: 3,
+            grid_patch_radius: 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Observation {
+    pub agent: usize,
+    pub tick: u64,
+    pub features: Vec<f32>,
+}
+
+impl ObservationConfig {
+    pub fn layout(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        if self.own_kinematics {
+            for name in &[
+                "x",
+                "y",
+                "vx",
+                "vy",
+                "cos_theta",
+                "sin_theta",
+                "omega",
+                "wear",
+            ] {
+                names.push(format!("own.{}", name));
+            }
+        }
+        for i in 0..self.nearest_agents {
+            for name in &["present", "dx", "dy", "dvx", "dvy", "busy"] {
+                names.push(format!("agent{}.{}", i, name));
+            }
+        }
+        for i in 0..self.nearest_missions {
+            for name in &["present", "dx", "dy", "urgent", "claimed"] {
+                names.push(format!("mission{}.{}", i, name));
+            }
+        }
+        let side = 2 * self.grid_patch_radius as i64 + 1;
+        if self.grid_patch_radius > 0 {
+            for row in 0..side {
+                for col in 0..side {
+                    names.push(format!("cell{}_{}.cost", row, col));
+                }
+            }
+        }
+        names
+    }
+
+    // Positions are normalized by the half size of the grid, speeds by MAX_SPEED and cell costs
+    // by MAX_COST; missing neighbours are all zeros, including their presence flag
+    pub fn build(
+        &self,
+        own: &AgentMessage,
+        agents: &HashMap<usize, AgentMessage>,
+        missions: &MissionPool,
+        grid: &Grid,
+    ) -> Observation {
+        let mut features = Vec::with_capacity(self.layout().len());
+        let k = &own.kinematics;
+        let relative = |p: &Vector2<f32>| (p - k.p) / GRID_HALF_SIZE;
+        if self.own_kinematics {
+            features.extend_from_slice(&[
+                k.p.x / GRID_HALF_SIZE,
+                k.p.y / GRID_HALF_SIZE,
+                k.v.x / MAX_SPEED.0,
+                k.v.y / MAX_SPEED.0,
+                k.theta.cos(),
+                k.theta.sin(),
+                k.omega / MAX_ANGULAR_ACCELERATION,
+                own.wear,
+            ]);
+        }
+
+        let mut others: Vec<&AgentMessage> = agents.values().filter(|a| a.id != own.id).collect();
+        others.sort_by(|a, b| distance_order(k, &a.kinematics.p, &b.kinematics.p));
+        for i in 0..self.nearest_agents {
+            match others.get(i) {
+                Some(other) => {
+                    let d = relative(&other.kinematics.p);
+                    let dv = (other.kinematics.v - k.v) / MAX_SPEED.0;
+                    let busy = if other.mission.is_some() { 1.0 } else { 0.0 };
+                    features.extend_from_slice(&[1.0, d.x, d.y, dv.x, dv.y, busy]);
+                }
+                None => features.extend_from_slice(&[0.0; 6]),
+            }
+        }
+
+        let mut pending: Vec<&Mission> = missions.iter().collect();
+        pending.sort_by(|a, b| distance_order(k, &a.current_goal().0, &b.current_goal().0));
+        for i in 0..self.nearest_missions {
+            match pending.get(i) {
+                Some(mission) => {
+                    let d = relative(&mission.current_goal().0);
+                    let urgent = if mission.urgent { 1.0 } else { 0.0 };
+                    let claimed = if agents
+                        .values()
+                        .any(|a| a.mission.as_ref().map(|m| m.id) == Some(mission.id))
+                    {
+                        1.0
+                    } else {
+                        0.0
+                    };
+                    features.extend_from_slice(&[1.0, d.x, d.y, urgent, claimed]);
+                }
+                None => features.extend_from_slice(&[0.0; 5]),
+            }
+        }
+
+        let radius = self.grid_patch_radius as i64;
+        if radius > 0 {
+            for row in -radius..=radius {
+                for col in -radius..=radius {
+                    let p = k.p + Vector2::new(col as f32, row as f32) * CELL_SIZE;
+                    features.push(match grid.cell_at(&p) {
+                        Some(Cell::Crossable(cost)) => cost / MAX_COST,
+                        Some(Cell::Uncrossable) | None => 1.0,
+                    });
+                }
+            }
+        }
+
+        Observation {
+            agent: own.id,
+            tick: own.tick,
+            features,
+        }
+    }
+}
+
+fn distance_order(k: &Kinematics, a: &Vector2<f32>, b: &Vector2<f32>) -> std::cmp::Ordering {
+    (a - k.p)
+        .norm_squared()
+        .partial_cmp(&(b - k.p).norm_squared())
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
@@ -0,0 +1,156 @@
+use nalgebra::{Rotation2, Vector2};
+use serde::{Deserialize, Serialize};
+
+// Continuous obstacles, independent of the cell raster. Collisions are checked against their
+// exact shape; the grid only gets a rasterized copy for whatever works on cells.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum Obstacle {
+    Circle {
+        center: [f32; 2],
+        radius: f32,
+    },
+    // Rotated counter-clockwise by angle (radians) around its center
+    Rectangle {
+        center: [f32; 2],
+        size: [f32; 2],
+        #[serde(default)]
+        angle: f32,
+    },
+    Polygon {
+        vertices: Vec<[f32; 2]>,
+    },
+}
+
+fn point(p: &[f32; 2]) -> Vector2<f32> {
+    Vector2::new(p[0], p[1])
+}
+
+pub fn distance_to_segment(p: &Vector2<f32>, a: &Vector2<f32>, b: &Vector2<f32>) -> f32 {
+    let ab = b - a;
+    if ab.norm_squared() == 0.0 {
+        return (p - a).norm();
+    }
+    let t = ((p - a).dot(&ab) / ab.norm_squared()).clamp(0.0, 1.0);
+    (p - (a + t * ab)).norm()
+}
+
+impl Obstacle {
+    pub fn validate(&self) -> Result<(), String> {
+        let finite = |values: &[f32]| values.iter().all(|v| v.is_finite());
+        match self {
+            Obstacle::Circle { center, radius } => {
+                if !finite(center) || !radius.is_finite() || *radius <= 0.0 {
+                    return Err(format!(
+                        "circle at {:?} needs a positive radius, got {}",
+                        center, radius
+                    ));
+                }
+            }
+            Obstacle::Rectangle {
+                center,
+                size,
+                angle,
+            } => {
+                if !finite(center)
+                    || !angle.is_finite()
+                    || size.iter().any(|s| !s.is_finite() || *s <= 0.0)
+                {
+                    return Err(format!(
+                        "rectangle at {:?} needs a positive size, got {:?}",
+                        center, size
+                    ));
+                }
+            }
+            Obstacle::Polygon { vertices } => {
+                if vertices.len() < 3 || vertices.iter().any(|v| !finite(v)) {
+                    return Err(format!(
+                        "polygon needs at least 3 finite vertices, got {:?}",
+                        vertices
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Distance from p to the obstacle's boundary, negative inside
+    pub fn signed_distance(&self, p: &Vector2<f32>) -> f32 {
+        match self {
+            Obstacle::Circle { center, radius } => (p - point(center)).norm() - radius,
+            Obstacle::Rectangle {
+                center,
+                size,
+                angle,
+            } => {
+                let local = Rotation2::new(-angle) * (p - point(center));
+                let d = local.abs() - point(size) / 2.0;
+                d.sup(&Vector2::zeros()).norm() + d.x.max(d.y).min(0.0)
+            }
+            Obstacle::Polygon { vertices } => {
+                let mut distance = f32::INFINITY;
+                let mut inside = false;
+                for (i, a) in vertices.iter().enumerate() {
+                    let (a, b) = (point(a), point(&vertices[(i + 1) % vertices.len()]));
+                    distance = distance.min(distance_to_segment(p, &a, &b));
+                    if (a.y > p.y) != (b.y > p.y)
+                        && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x)
+                    {
+                        inside = !inside;
+                    }
+                }
+                if inside {
+                    -distance
+                } else {
+                    distance
+                }
+            }
+        }
+    }
+
+    // Direction in which the signed distance grows fastest, i.e. out of the obstacle
+    pub fn outward(&self, p: &Vector2<f32>) -> Vector2<f32> {
+        let eps = 1e-2;
+        let gradient = Vector2::new(
+            self.signed_distance(&(p + Vector2::new(eps, 0.0)))
+                - self.signed_distance(&(p - Vector2::new(eps, 0.0))),
+            self.signed_distance(&(p + Vector2::new(0.0, eps)))
+                - self.signed_distance(&(p - Vector2::new(0.0, eps))),
+        );
+        if gradient.norm() > 0.0 {
+            gradient.normalize()
+        } else {
+            Vector2::x()
+        }
+    }
+
+    // Closed outline, for drawing
+    pub fn outline(&self) -> Vec<Vector2<f32>> {
+        match self {
+            Obstacle::Circle { center, radius } => (0..32)
+                .map(|i| {
+                    let theta = i as f32 / 32.0 * 2.0 * std::f32::consts::PI;
+                    point(center) + *radius * Vector2::new(theta.cos(), theta.sin())
+                })
+                .collect(),
+            Obstacle::Rectangle {
+                center,
+                size,
+                angle,
+            } => {
+                let half = point(size) / 2.0;
+                let rotation = Rotation2::new(*angle);
+                vec![
+                    Vector2::new(-half.x, -half.y),
+                    Vector2::new(half.x, -half.y),
+                    Vector2::new(half.x, half.y),
+                    Vector2::new(-half.x, half.y),
+                ]
+                .into_iter()
+                .map(|corner| point(center) + rotation * corner)
+                .collect()
+            }
+            Obstacle::Polygon { vertices } => vertices.iter().map(point).collect(),
+        }
+    }
+}
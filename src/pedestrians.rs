@@ -0,0 +1,117 @@
+use crate::consts::{
+    AGENT_RADIUS, CELL_SIZE, DISTANCE_TO_TARGET, GRID_HALF_SIZE, PEDESTRIANS_STREAM,
+    PEDESTRIAN_RADIUS, PEDESTRIAN_RELAXATION_S, PEDESTRIAN_SPEED, SOCIAL_FORCE_RANGE,
+    SOCIAL_FORCE_STRENGTH,
+};
+use nalgebra::Vector2;
+use rand::distributions::{Distribution, Uniform};
+use rand_pcg::Pcg64;
+use std::sync::{Arc, Mutex};
+
+// Latest pedestrian positions, published by the system manager for agents to avoid and for the
+// renderer to draw
+#[derive(Clone, Default)]
+pub struct Pedestrians {
+    positions: Arc<Mutex<Vec<Vector2<f32>>>>,
+}
+
+impl Pedestrians {
+    pub fn new() -> Self {
+        Pedestrians::default()
+    }
+
+    pub fn publish(&self, positions: Vec<Vector2<f32>>) {
+        *self.positions.lock().unwrap() = positions;
+    }
+
+    pub fn positions(&self) -> Vec<Vector2<f32>> {
+        self.positions.lock().unwrap().clone()
+    }
+}
+
+struct Pedestrian {
+    p: Vector2<f32>,
+    v: Vector2<f32>,
+    goal: Vector2<f32>,
+}
+
+// Pedestrians walking between random goals, driven by a social-force model: a pull toward their
+// goal at PEDESTRIAN_SPEED, and an exponential push away from every other pedestrian and agent
+pub struct Crowd {
+    pedestrians: Vec<Pedestrian>,
+    rng: Pcg64,
+    between: Uniform<f32>,
+}
+
+impl Crowd {
+    pub fn new(count: usize, seed: u128) -> Self {
+        let margin = CELL_SIZE + PEDESTRIAN_RADIUS;
+        let mut crowd = Crowd {
+            pedestrians: Vec::with_capacity(count),
+            rng: Pcg64::new(seed, PEDESTRIANS_STREAM),
+            between: Uniform::new(-GRID_HALF_SIZE + margin, GRID_HALF_SIZE - margin),
+        };
+        for _ in 0..count {
+            let p = crowd.sample();
+            let goal = crowd.sample();
+            crowd.pedestrians.push(Pedestrian {
+                p,
+                v: Vector2::zeros(),
+                goal,
+            });
+        }
+        crowd
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pedestrians.is_empty()
+    }
+
+    fn sample(&mut self) -> Vector2<f32> {
+        Vector2::new(
+            self.between.sample(&mut self.rng),
+            self.between.sample(&mut self.rng),
+        )
+    }
+
+    pub fn positions(&self) -> Vec<Vector2<f32>> {
+        self.pedestrians.iter().map(|p| p.p).collect()
+    }
+
+    pub fn step(&mut self, dt: f32, agents: &[Vector2<f32>]) {
+        let positions = self.positions();
+        let repulsion = |p: &Vector2<f32>, other: &Vector2<f32>, reach: f32| {
+            let d = p - other;
+            let distance = d.norm();
+            if distance == 0.0 {
+                return Vector2::zeros();
+            }
+            d / distance * SOCIAL_FORCE_STRENGTH * ((reach - distance) / SOCIAL_FORCE_RANGE).exp()
+        };
+        for i in 0..self.pedestrians.len() {
+            if (self.pedestrians[i].goal - self.pedestrians[i].p).norm() < DISTANCE_TO_TARGET {
+                self.pedestrians[i].goal = self.sample();
+            }
+            let pedestrian = &mut self.pedestrians[i];
+            let desired = (pedestrian.goal - pedestrian.p).normalize() * PEDESTRIAN_SPEED;
+            let mut force = (desired - pedestrian.v) / PEDESTRIAN_RELAXATION_S;
+            for (j, other) in positions.iter().enumerate() {
+                if j != i {
+                    force += repulsion(&pedestrian.p, other, 2.0 * PEDESTRIAN_RADIUS);
+                }
+            }
+            for agent in agents {
+                force += repulsion(&pedestrian.p, agent, PEDESTRIAN_RADIUS + AGENT_RADIUS);
+            }
+            pedestrian.v += dt * force;
+            if pedestrian.v.norm() > 2.0 * PEDESTRIAN_SPEED {
+                pedestrian.v *= 2.0 * PEDESTRIAN_SPEED / pedestrian.v.norm();
+            }
+            pedestrian.p += dt * pedestrian.v;
+            pedestrian.p = pedestrian
+                .p
+                .sup(&Vector2::repeat(-GRID_HALF_SIZE))
+                .inf(&Vector2::repeat(GRID_HALF_SIZE));
+        }
+    }
+}
@@ -0,0 +1,590 @@
+use crate::agent::{AgentMessage, Cell, Grid, Kinematics};
+use crate::congestion::Congestion;
+use crate::consts::{
+    ASTAR_COST_WEIGHT, CELL_SIZE, DISTANCE_TO_TARGET, GRID_HALF_SIZE, INTENT_WAYPOINTS, MAX_COST,
+    MAX_TRACKING_ERROR, PLANNER_STREAM, PLANNING_SLACK, PLAN_SAMPLES, PLAN_SAMPLE_PERIOD_S,
+    RRT_GOAL_BIAS, RRT_ITERATIONS, RRT_NEIGHBOR_RADIUS, RRT_STEP,
+};
+use crate::error::{Error, Result};
+use crate::roads::{RoadNetwork, Visit};
+use crate::scheduling::speed_scale;
+use crate::trajectory::{Reference, Trajectory};
+use crate::units::{MetersPerSecond, MetersPerSecondSquared, Seconds};
+use log::*;
+use nalgebra::Vector2;
+use rand::Rng;
+use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+use std::collections::{BinaryHeap, HashMap};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlannerKind {
+    // Straight at the goal, obstacles or not
+    #[default]
+    Direct,
+    RrtStar,
+    // Along the crossable cells of the grid
+    AStar,
+    // Straight at the goal along a time-parameterized trajectory within the acceleration caps,
+    // tracked instead of chased
+    Kinodynamic,
+}
+
+// Whether a disk of the given radius can sweep from a to b without touching an obstacle or
+// leaving the grid. The start is not checked, so that an agent overlapping something can still
+// plan its way out, and agents pushed out of an obstacle end up at exactly their radius from it,
+// hence some slack.
+fn is_free(grid: &Grid, a: &Vector2<f32>, b: &Vector2<f32>, radius: f32) -> bool {
+    let steps = ((b - a).norm() / (CELL_SIZE / 2.0)).ceil().max(1.0) as usize;
+    (1..=steps).all(|i| {
+        let p = a + (b - a) * (i as f32 / steps as f32);
+        p.x.abs() <= GRID_HALF_SIZE - radius
+            && p.y.abs() <= GRID_HALF_SIZE - radius
+            && grid
+                .obstacles
+                .iter()
+                .all(|o| o.signed_distance(&p) >= radius - PLANNING_SLACK)
+    })
+}
+
+struct Node {
+    p: Vector2<f32>,
+    parent: usize,
+    cost: f32,
+}
+
+// RRT*: grows a tree of collision-free segments from the start, attaching every new node to the
+// neighbour giving it the cheapest path and rewiring the neighbours through it when shorter.
+// Segments through crowded regions cost more than their length.
+fn rrt_star(
+    grid: &Grid,
+    congestion: &Congestion,
+    start: Vector2<f32>,
+    goal: Vector2<f32>,
+    radius: f32,
+    rng: &mut Pcg64,
+) -> Result<Vec<Vector2<f32>>> {
+    let mut nodes = vec![Node {
+        p: start,
+        parent: 0,
+        cost: 0.0,
+    }];
+    let mut best: Option<(usize, f32)> = None;
+    let length = |a: &Vector2<f32>, b: &Vector2<f32>| (a - b).norm() * congestion.cost_factor(a, b);
+    let bound = GRID_HALF_SIZE - radius;
+    for _ in 0..RRT_ITERATIONS {
+        let sample = if rng.gen_bool(RRT_GOAL_BIAS) {
+            goal
+        } else {
+            Vector2::new(rng.gen_range(-bound..bound), rng.gen_range(-bound..bound))
+        };
+        let nearest = nodes
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                (a.p - sample)
+                    .norm_squared()
+                    .partial_cmp(&(b.p - sample).norm_squared())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+        let towards = sample - nodes[nearest].p;
+        let p = if towards.norm() > RRT_STEP {
+            nodes[nearest].p + towards.normalize() * RRT_STEP
+        } else {
+            sample
+        };
+        if !is_free(grid, &nodes[nearest].p, &p, radius) {
+            continue;
+        }
+
+        let neighbors: Vec<usize> = (0..nodes.len())
+            .filter(|&i| (nodes[i].p - p).norm() <= RRT_NEIGHBOR_RADIUS)
+            .collect();
+        let (parent, cost) = neighbors
+            .iter()
+            .map(|&i| (i, nodes[i].cost + length(&nodes[i].p, &p)))
+            .filter(|&(i, _)| i == nearest || is_free(grid, &nodes[i].p, &p, radius))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .unwrap_or((nearest, nodes[nearest].cost + length(&nodes[nearest].p, &p)));
+        let index = nodes.len();
+        nodes.push(Node { p, parent, cost });
+        for i in neighbors {
+            let through = cost + length(&p, &nodes[i].p);
+            if through < nodes[i].cost && is_free(grid, &p, &nodes[i].p, radius) {
+                nodes[i].parent = index;
+                nodes[i].cost = through;
+            }
+        }
+
+        if (p - goal).norm() <= DISTANCE_TO_TARGET {
+            let cost = nodes[index].cost;
+            if best.is_none_or(|(_, best_cost)| cost < best_cost) {
+                best = Some((index, cost));
+            }
+        }
+    }
+
+    // Costs of rewired subtrees are not propagated, so the path is rebuilt from the parents
+    let (mut i, _) = best.ok_or_else(|| {
+        Error::Planning(format!(
+            "no collision-free path from {} to {} within {} samples",
+            start, goal, RRT_ITERATIONS
+        ))
+    })?;
+    let mut path = vec![goal];
+    while i != 0 {
+        path.push(nodes[i].p);
+        i = nodes[i].parent;
+    }
+    path.reverse();
+    Ok(path)
+}
+
+// A* over the centers of the crossable cells, 8-connected without cutting corners. A step costs its
+// length, raised with the cost of the cell it enters, so that the straight distance to the goal
+// never overestimates what is left. The cells of the start and of the goal are entered whatever
+// they are, so that agents and targets stuck in a wall can still be planned for. The path ends on
+// the goal itself; an error if no crossable cells lead there.
+fn a_star(grid: &Grid, start: &Vector2<f32>, goal: &Vector2<f32>) -> Result<Vec<Vector2<f32>>> {
+    let width = grid.width as i64;
+    let height = (grid.cells.len() / grid.width) as i64;
+    let cell_of = |p: &Vector2<f32>| {
+        let index = |c: f32| ((c + GRID_HALF_SIZE) / CELL_SIZE).round() as i64;
+        (
+            index(p.x).clamp(0, width - 1),
+            index(p.y).clamp(0, height - 1),
+        )
+    };
+    let node = |(col, row): (i64, i64)| (row * width + col) as usize;
+    let center = |node: usize| {
+        Vector2::new(
+            (node % grid.width) as f32 * CELL_SIZE - GRID_HALF_SIZE,
+            (node / grid.width) as f32 * CELL_SIZE - GRID_HALF_SIZE,
+        )
+    };
+    let from = node(cell_of(start));
+    let to = node(cell_of(goal));
+    let cost_of = |(col, row): (i64, i64)| {
+        if col < 0 || row < 0 || col >= width || row >= height {
+            return None;
+        }
+        match grid.cells[node((col, row))] {
+            Cell::Crossable(cost) => Some(cost),
+            Cell::Uncrossable if node((col, row)) == to => Some(MAX_COST),
+            Cell::Uncrossable => None,
+        }
+    };
+
+    let mut costs = vec![f32::INFINITY; grid.cells.len()];
+    let mut previous = vec![None; grid.cells.len()];
+    let mut heap = BinaryHeap::new();
+    costs[from] = 0.0;
+    heap.push(Visit {
+        cost: (center(to) - center(from)).norm(),
+        node: from,
+    });
+    while let Some(Visit { node: current, .. }) = heap.pop() {
+        if current == to {
+            break;
+        }
+        let (col, row) = ((current % grid.width) as i64, (current / grid.width) as i64);
+        for (dc, dr) in [
+            (1, 0),
+            (-1, 0),
+            (0, 1),
+            (0, -1),
+            (1, 1),
+            (1, -1),
+            (-1, 1),
+            (-1, -1),
+        ] {
+            let next = (col + dc, row + dr);
+            let cell_cost = match cost_of(next) {
+                Some(cost) => cost,
+                None => continue,
+            };
+            if dc != 0
+                && dr != 0
+                && (cost_of((col + dc, row)).is_none() || cost_of((col, row + dr)).is_none())
+            {
+                continue;
+            }
+            let step = CELL_SIZE * ((dc * dc + dr * dr) as f32).sqrt();
+            let through = costs[current] + step * (1.0 + ASTAR_COST_WEIGHT * cell_cost / MAX_COST);
+            let next = node(next);
+            if through < costs[next] {
+                costs[next] = through;
+                previous[next] = Some(current);
+                heap.push(Visit {
+                    cost: through + (center(to) - center(next)).norm(),
+                    node: next,
+                });
+            }
+        }
+    }
+    if !costs[to].is_finite() {
+        return Err(Error::Planning(format!(
+            "no crossable cells lead from {} to {}",
+            start, goal
+        )));
+    }
+
+    let mut path = vec![*goal];
+    let mut cell = previous[to];
+    while let Some(c) = cell.filter(|&c| c != from) {
+        path.push(center(c));
+        cell = previous[c];
+    }
+    path.reverse();
+    Ok(path)
+}
+
+// Turns the goal of an agent into the next point it should steer to
+pub struct Planner {
+    kind: PlannerKind,
+    rng: Pcg64,
+    goal: Option<Vector2<f32>>,
+    path: Vec<Vector2<f32>>,
+    trajectory: Option<Trajectory>,
+    // Fraction of the planned speed the trajectory is followed at, to let others go first
+    scale: f32,
+}
+
+impl Planner {
+    pub fn new(kind: PlannerKind, id: usize, seed: u128) -> Self {
+        Planner {
+            kind,
+            rng: Pcg64::new(seed, PLANNER_STREAM + id as u128),
+            goal: None,
+            path: Vec::new(),
+            trajectory: None,
+            scale: 1.0,
+        }
+    }
+
+    // Slows down along the current trajectory, without changing its path, when keeping to it
+    // would run into an agent with priority
+    pub fn schedule(&mut self, own: &AgentMessage, agents: &HashMap<usize, AgentMessage>) {
+        self.scale = match &self.trajectory {
+            Some(trajectory) => speed_scale(own, |t| trajectory.position_ahead(Seconds(t)), agents),
+            None => 1.0,
+        };
+    }
+
+    // Where the agent expects to be every PLAN_SAMPLE_PERIOD_S over the planning horizon, for
+    // others to schedule around. Only trajectories have a timing to share.
+    pub fn intent(&self) -> Vec<Vector2<f32>> {
+        match &self.trajectory {
+            Some(trajectory) => (1..=PLAN_SAMPLES)
+                .map(|k| {
+                    trajectory.position_ahead(Seconds(self.scale * k as f32 * PLAN_SAMPLE_PERIOD_S))
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Next waypoints of the current path, up to the goal, for others to see where the agent is
+    // heading. Unlike the plan, they hold no timing.
+    pub fn waypoints(&self) -> Vec<Vector2<f32>> {
+        self.path.iter().take(INTENT_WAYPOINTS).copied().collect()
+    }
+
+    // Drops the path once the agent has nowhere to go
+    pub fn forget(&mut self) {
+        self.goal = None;
+        self.path.clear();
+    }
+
+    // Reference state to track for the kinodynamic planner, None for the others. The trajectory
+    // is replanned from the current state when the goal moves, or when the agent has been pushed
+    // too far from it.
+    pub fn track(
+        &mut self,
+        dt: Seconds,
+        k: &Kinematics,
+        goal: Vector2<f32>,
+        max_speed: MetersPerSecond,
+        max_acceleration: MetersPerSecondSquared,
+    ) -> Option<Reference> {
+        if self.kind != PlannerKind::Kinodynamic {
+            return None;
+        }
+        let mut trajectory = match self.trajectory.take() {
+            Some(trajectory) if (trajectory.goal - goal).norm() <= DISTANCE_TO_TARGET => trajectory,
+            _ => Planner::plan_trajectory(k, goal, max_speed, max_acceleration),
+        };
+        let mut reference = trajectory.advance(self.scale * dt);
+        if (reference.p - k.p).norm() > MAX_TRACKING_ERROR {
+            trajectory = Planner::plan_trajectory(k, goal, max_speed, max_acceleration);
+            reference = trajectory.advance(self.scale * dt);
+        }
+        self.trajectory = Some(trajectory);
+        // Time runs slower along the trajectory when scaled down
+        reference.v *= self.scale;
+        reference.a *= self.scale * self.scale;
+        Some(reference)
+    }
+
+    fn plan_trajectory(
+        k: &Kinematics,
+        goal: Vector2<f32>,
+        max_speed: MetersPerSecond,
+        max_acceleration: MetersPerSecondSquared,
+    ) -> Trajectory {
+        let trajectory = Trajectory::new(k.p, k.v, goal, max_speed, max_acceleration);
+        debug!(
+            "Planned a {:.2}s trajectory from {} to {}",
+            trajectory.duration().0,
+            k.p,
+            goal
+        );
+        trajectory
+    }
+
+    // A goal no path leads to is reported once, after which the planner heads straight for it
+    // until the goal moves
+    pub fn next_waypoint(
+        &mut self,
+        grid: &Grid,
+        congestion: &Congestion,
+        p: &Vector2<f32>,
+        goal: Vector2<f32>,
+        radius: f32,
+    ) -> Result<Vector2<f32>> {
+        if let Some(roads) = &grid.roads {
+            return self.next_road_waypoint(roads, congestion, p, goal);
+        }
+        // Straight lines are only taken when they neither hit anything, walls of the grid included,
+        // nor cross a crowd
+        let clear = |a: &Vector2<f32>, b: &Vector2<f32>| {
+            is_free(grid, a, b, radius)
+                && grid.line_of_sight(a, b)
+                && !congestion.is_congested_between(a, b)
+        };
+        if self.kind == PlannerKind::Direct || clear(p, &goal) {
+            self.goal = None;
+            self.path = vec![goal];
+            return Ok(goal);
+        }
+        let replan = self
+            .goal
+            .is_none_or(|planned| (planned - goal).norm() > DISTANCE_TO_TARGET);
+        if replan {
+            self.goal = Some(goal);
+            let path = match self.kind {
+                PlannerKind::AStar => a_star(grid, p, &goal),
+                _ => rrt_star(grid, congestion, *p, goal, radius, &mut self.rng),
+            };
+            // Left in place of the path if none is found
+            self.path = vec![goal];
+            self.path = path?;
+        }
+        // Skips the waypoints already reached, and the ones that can be cut to
+        while self.path.len() > 1
+            && ((self.path[0] - p).norm() < DISTANCE_TO_TARGET || clear(p, &self.path[1]))
+        {
+            self.path.remove(0);
+        }
+        Ok(self.path.first().copied().unwrap_or(goal))
+    }
+
+    // Along the shortest route on the roads, never cutting corners
+    fn next_road_waypoint(
+        &mut self,
+        roads: &RoadNetwork,
+        congestion: &Congestion,
+        p: &Vector2<f32>,
+        goal: Vector2<f32>,
+    ) -> Result<Vector2<f32>> {
+        let replan = self
+            .goal
+            .is_none_or(|planned| (planned - goal).norm() > DISTANCE_TO_TARGET);
+        if replan {
+            self.goal = Some(goal);
+            // Left in place of the route if none is found
+            self.path = vec![goal];
+            self.path = roads.route(p, &goal, congestion)?;
+        }
+        while self.path.len() > 1 && (self.path[0] - p).norm() < DISTANCE_TO_TARGET {
+            self.path.remove(0);
+        }
+        Ok(self.path.first().copied().unwrap_or(goal))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::{AGENT_RADIUS, SEED};
+    use crate::obstacles::Obstacle;
+
+    const WIDTH: usize = 20;
+
+    fn grid_with(cost: f32, blocked: &[(usize, usize)]) -> Grid {
+        let mut cells: Vec<Cell> = (0..WIDTH * WIDTH).map(|_| Cell::Crossable(cost)).collect();
+        for &(col, row) in blocked {
+            cells[row * WIDTH + col] = Cell::Uncrossable;
+        }
+        Grid {
+            cells,
+            width: WIDTH,
+            obstacles: Vec::new(),
+            roads: None,
+            labels: HashMap::new(),
+        }
+    }
+
+    fn center(col: usize, row: usize) -> Vector2<f32> {
+        Vector2::new(col as f32, row as f32) * CELL_SIZE - Vector2::repeat(GRID_HALF_SIZE)
+    }
+
+    fn cell_of(p: &Vector2<f32>) -> (usize, usize) {
+        let index = |c: f32| ((c + GRID_HALF_SIZE) / CELL_SIZE).round() as usize;
+        (index(p.x), index(p.y))
+    }
+
+    // Every step of the path goes to a neighbouring crossable cell, up to the goal, in sight of the
+    // previous one unless leaving a blocked start
+    fn assert_walkable(grid: &Grid, start: &Vector2<f32>, path: &[Vector2<f32>]) {
+        let mut previous = *start;
+        for p in &path[..path.len() - 1] {
+            assert!((p - previous).norm() <= CELL_SIZE * 1.5, "jump to {}", p);
+            assert!(
+                previous == *start || grid.line_of_sight(&previous, p),
+                "{} -> {}",
+                previous,
+                p
+            );
+            let (col, row) = cell_of(p);
+            assert!(matches!(grid.cells[row * WIDTH + col], Cell::Crossable(_)));
+            previous = *p;
+        }
+    }
+
+    #[test]
+    fn a_star_routes_around_a_wall() {
+        let wall: Vec<(usize, usize)> = (0..15).map(|row| (10, row)).collect();
+        let grid = grid_with(1.0, &wall);
+        let (start, goal) = (center(5, 5), center(15, 5));
+        let path = a_star(&grid, &start, &goal).unwrap();
+        assert_eq!(path.last(), Some(&goal));
+        assert_walkable(&grid, &start, &path);
+        assert!(path.iter().any(|p| cell_of(p).1 >= 15));
+    }
+
+    #[test]
+    fn a_star_prefers_cheaper_cells() {
+        let mut grid = grid_with(MAX_COST, &[]);
+        for col in 0..WIDTH {
+            grid.cells[9 * WIDTH + col] = Cell::Crossable(0.0);
+        }
+        let (start, goal) = (center(2, 6), center(17, 6));
+        let path = a_star(&grid, &start, &goal).unwrap();
+        assert_walkable(&grid, &start, &path);
+        assert!(path.iter().filter(|p| cell_of(p).1 == 9).count() >= 5);
+    }
+
+    #[test]
+    fn a_star_reports_unreachable_goals() {
+        let wall: Vec<(usize, usize)> = (0..WIDTH).map(|row| (10, row)).collect();
+        let grid = grid_with(1.0, &wall);
+        assert!(matches!(
+            a_star(&grid, &center(5, 5), &center(15, 5)),
+            Err(Error::Planning(_))
+        ));
+    }
+
+    // Agents and targets stuck in a wall are planned out of and into it
+    #[test]
+    fn a_star_plans_from_and_to_blocked_cells() {
+        let grid = grid_with(1.0, &[(5, 5), (15, 5)]);
+        let (start, goal) = (center(5, 5), center(15, 5));
+        let path = a_star(&grid, &start, &goal).unwrap();
+        assert_eq!(path.last(), Some(&goal));
+        assert_walkable(&grid, &start, &path);
+        let into_goal = path[path.len() - 2];
+        assert!((goal - into_goal).norm() <= CELL_SIZE * 1.5);
+    }
+
+    #[test]
+    fn rrt_star_keeps_the_radius_clear_of_obstacles() {
+        let mut grid = grid_with(1.0, &[]);
+        grid.obstacles = vec![Obstacle::Rectangle {
+            center: [0.0, 0.0],
+            size: [20.0, 300.0],
+            angle: 0.0,
+        }];
+        let (start, goal) = (Vector2::new(-100.0, 0.0), Vector2::new(100.0, 0.0));
+        let plan = |stream| {
+            let mut rng = Pcg64::new(SEED, stream);
+            rrt_star(
+                &grid,
+                &Congestion::new(),
+                start,
+                goal,
+                AGENT_RADIUS,
+                &mut rng,
+            )
+            .unwrap()
+        };
+        let path = plan(PLANNER_STREAM);
+        assert_eq!(path.last(), Some(&goal));
+        let mut previous = start;
+        for p in &path {
+            for i in 0..=20 {
+                let q = previous + (p - previous) * (i as f32 / 20.0);
+                let clearance = grid.obstacles[0].signed_distance(&q);
+                assert!(
+                    clearance >= AGENT_RADIUS - PLANNING_SLACK,
+                    "{} at {}",
+                    clearance,
+                    q
+                );
+            }
+            previous = *p;
+        }
+        assert_eq!(path, plan(PLANNER_STREAM));
+    }
+
+    #[test]
+    fn kinodynamic_trajectories_stay_within_their_caps() {
+        let (max_speed, max_acceleration) = (MetersPerSecond(40.0), MetersPerSecondSquared(20.0));
+        let dt = Seconds(0.02);
+        let starts = [
+            (Vector2::new(-200.0, -150.0), Vector2::zeros()),
+            (Vector2::new(0.0, 0.0), Vector2::new(-20.0, 25.0)),
+            (Vector2::new(100.0, 10.0), Vector2::new(0.0, -35.0)),
+        ];
+        for (p, v) in starts {
+            let mut planner = Planner::new(PlannerKind::Kinodynamic, 0, SEED);
+            let mut k = Kinematics {
+                p,
+                v,
+                a: Vector2::zeros(),
+                theta: 0.0,
+                omega: 0.0,
+                alpha: 0.0,
+                radius: AGENT_RADIUS,
+            };
+            let goal = Vector2::new(150.0, 120.0);
+            for _ in 0..2000 {
+                let reference = planner
+                    .track(dt, &k, goal, max_speed, max_acceleration)
+                    .unwrap();
+                assert!(reference.v.norm() <= max_speed.0 + 1e-3, "{}", reference.v);
+                assert!(
+                    reference.a.norm() <= max_acceleration.0 + 1e-3,
+                    "{}",
+                    reference.a
+                );
+                k.p = reference.p;
+                k.v = reference.v;
+            }
+            assert!((k.p - goal).norm() < DISTANCE_TO_TARGET, "from {}", p);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use crate::consts::*;
+use crate::metrics::Metrics;
+use log::*;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct SheddingState {
+    level: usize,
+    last_overrun: Instant,
+    last_change: Instant,
+}
+
+// How much optional work to skip, shared by every loop running in soft real-time mode. Any loop
+// missing its deadline raises it, and it is only lowered once none has for a while.
+#[derive(Clone)]
+pub struct LoadShedding {
+    state: Arc<Mutex<SheddingState>>,
+}
+
+impl Default for LoadShedding {
+    fn default() -> Self {
+        LoadShedding::new()
+    }
+}
+
+impl LoadShedding {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        LoadShedding {
+            state: Arc::new(Mutex::new(SheddingState {
+                level: 0,
+                last_overrun: now,
+                last_change: now,
+            })),
+        }
+    }
+
+    // Only one in this many optional actions (state broadcasts, rendered frames) is performed
+    pub fn divider(&self) -> usize {
+        1 << self.state.lock().unwrap().level
+    }
+
+    // Returns the new level if it was raised
+    fn overrun(&self) -> Option<usize> {
+        let now = Instant::now();
+        let mut s = self.state.lock().unwrap();
+        s.last_overrun = now;
+        let settled = (now - s.last_change).as_secs_f32() >= SHEDDING_RAISE_PERIOD_S;
+        if s.level < MAX_SHEDDING_LEVEL && (s.level == 0 || settled) {
+            s.level += 1;
+            s.last_change = now;
+            return Some(s.level);
+        }
+        None
+    }
+
+    // Returns the new level if it was lowered
+    fn on_time(&self) -> Option<usize> {
+        let now = Instant::now();
+        let mut s = self.state.lock().unwrap();
+        let calm = (now - s.last_overrun).as_secs_f32() >= SHEDDING_RECOVERY_S
+            && (now - s.last_change).as_secs_f32() >= SHEDDING_RECOVERY_S;
+        if s.level > 0 && calm {
+            s.level -= 1;
+            s.last_change = now;
+            return Some(s.level);
+        }
+        None
+    }
+}
+
+pub struct DeadlineMonitor {
+    name: String,
+    deadline: Duration,
+    shedding: LoadShedding,
+    metrics: Metrics,
+}
+
+impl DeadlineMonitor {
+    pub fn new(name: String, deadline: Duration, shedding: LoadShedding, metrics: Metrics) -> Self {
+        DeadlineMonitor {
+            name,
+            deadline,
+            shedding,
+            metrics,
+        }
+    }
+
+    // Records how long one iteration of the loop took, against its deadline
+    pub fn record(&mut self, elapsed: Duration) {
+        if elapsed <= self.deadline {
+            if let Some(level) = self.shedding.on_time() {
+                info!(
+                    "No deadline overrun for {}s, shedding level lowered to {}",
+                    SHEDDING_RECOVERY_S, level
+                );
+            }
+            return;
+        }
+
+        self.metrics
+            .increment(&format!("{}.deadline_overruns", self.name));
+        debug!(
+            "{} overran its {:?} deadline: {:?}",
+            self.name, self.deadline, elapsed
+        );
+        if let Some(level) = self.shedding.overrun() {
+            warn!(
+                "{} overran its {:?} deadline ({:?}), shedding level raised to {}",
+                self.name, self.deadline, elapsed, level
+            );
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use nalgebra::Vector2;
+use rand::Rng;
+
+// Axis-aligned box of the world, e.g. a labeled region of the grid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub min: Vector2<f32>,
+    pub max: Vector2<f32>,
+}
+
+impl Rect {
+    pub fn new(min: [f32; 2], max: [f32; 2]) -> Self {
+        Rect {
+            min: Vector2::new(min[0], min[1]),
+            max: Vector2::new(max[0], max[1]),
+        }
+    }
+
+    pub fn center(&self) -> Vector2<f32> {
+        (self.min + self.max) / 2.0
+    }
+
+    pub fn corners(&self) -> [Vector2<f32>; 4] {
+        [
+            self.min,
+            Vector2::new(self.max.x, self.min.y),
+            self.max,
+            Vector2::new(self.min.x, self.max.y),
+        ]
+    }
+
+    // Uniformly distributed point inside
+    pub fn sample(&self, rng: &mut impl Rng) -> Vector2<f32> {
+        Vector2::new(
+            rng.gen_range(self.min.x..=self.max.x),
+            rng.gen_range(self.min.y..=self.max.y),
+        )
+    }
+}
@@ -0,0 +1,437 @@
+use crate::agent::{AgentMessage, Grid};
+use crate::consts::{GRID_HALF_SIZE, GRID_SIZE, REGION_IMBALANCE};
+use crate::missions::{Mission, MissionManager, RewardConfig, TimeWindowConfig};
+use crate::obstacles::Obstacle;
+use crate::replenishment::{ReplenishmentConfig, ReplenishmentPolicy};
+use crate::roads::RoadNetwork;
+use crate::scenario::PendingMission;
+use log::*;
+use nalgebra::Vector2;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+pub struct Region {
+    center: Vector2<f32>,
+    mission_manager: MissionManager,
+    policy: Box<dyn ReplenishmentPolicy>,
+    agents: HashSet<usize>,
+}
+
+pub struct Coordinator {
+    split: usize,
+    seed: u128,
+    regions: Vec<Region>,
+    agent_regions: HashMap<usize, usize>,
+}
+
+impl Coordinator {
+    pub fn new(split: usize, seed: u128) -> Self {
+        let count = split * split;
+        let size = GRID_SIZE / split as f32;
+        let mut regions = Vec::with_capacity(count);
+        for i in 0..split {
+            for j in 0..split {
+                let min = Vector2::new(
+                    j as f32 * size - GRID_HALF_SIZE,
+                    i as f32 * size - GRID_HALF_SIZE,
+                );
+                let max = min + Vector2::repeat(size);
+                regions.push(Region {
+                    center: (min + max) / 2.0,
+                    mission_manager: MissionManager::for_region(
+                        regions.len(),
+                        count,
+                        min,
+                        max,
+                        seed,
+                    ),
+                    policy: ReplenishmentConfig::default().build(regions.len(), seed),
+                    agents: HashSet::new(),
+                });
+            }
+        }
+        Coordinator {
+            split,
+            seed,
+            regions,
+            agent_regions: HashMap::new(),
+        }
+    }
+
+    pub fn split(&self) -> usize {
+        self.split
+    }
+
+    pub fn seed(&self) -> u128 {
+        self.seed
+    }
+
+    fn region_of(&self, p: &Vector2<f32>) -> usize {
+        let size = GRID_SIZE / self.split as f32;
+        let index = |c: f32| (((c + GRID_HALF_SIZE) / size) as usize).min(self.split - 1);
+        index(p.y) * self.split + index(p.x)
+    }
+
+    pub fn number_missions_left(&self) -> usize {
+        self.regions
+            .iter()
+            .map(|r| r.mission_manager.number_missions_left())
+            .sum()
+    }
+
+    // Returns the pending missions of the agent's new region if it changed region
+    pub fn register_agent(&mut self, id: usize, p: &Vector2<f32>) -> Option<Vec<Mission>> {
+        let new_region = self.region_of(p);
+        let old_region = self.agent_regions.insert(id, new_region);
+        if old_region == Some(new_region) {
+            return None;
+        }
+
+        if let Some(old_region) = old_region {
+            self.regions[old_region].agents.remove(&id);
+            debug!(
+                "Agent {} moved from region {} to region {}",
+                id, old_region, new_region
+            );
+        }
+        self.regions[new_region].agents.insert(id);
+        Some(self.regions[new_region].mission_manager.pending_missions())
+    }
+
+    pub fn set_replenishment(&mut self, config: &ReplenishmentConfig) {
+        for (i, region) in self.regions.iter_mut().enumerate() {
+            region.policy = config.build(i, self.seed);
+        }
+    }
+
+    // Tops up every region as its replenishment policy says, and returns the new missions along
+    // with the agents that should be told about them. Regions without agents get no missions.
+    pub fn replenish(&mut self, now: u64) -> Vec<(Vec<usize>, Vec<Mission>)> {
+        let mut out = Vec::new();
+        for (i, region) in self.regions.iter_mut().enumerate() {
+            let n_agents = region.agents.len();
+            let missions_left = region.mission_manager.number_missions_left();
+            let n_missions = region.policy.missions_to_create(n_agents, missions_left);
+            if n_agents == 0 || n_missions == 0 {
+                continue;
+            }
+
+            info!(
+                "Creating new batch of {} missions in region {} ({} agents, {} missions left)",
+                n_missions, i, n_agents, missions_left
+            );
+            let new_missions = region.mission_manager.create_new_missions(n_missions, now);
+            out.push((region.agents.iter().copied().collect(), new_missions));
+        }
+        out
+    }
+
+    // Moves pending missions, one at a time, from the region with the most of them per agent to
+    // the one with the fewest, as long as the first keeps at least REGION_IMBALANCE times as many
+    // as the second. Regions without agents give away all they can. Only missions nobody is
+    // assigned to and not started yet move. Returns the missions moved with the agents of the
+    // region they moved to, which should be told about them.
+    pub fn rebalance(&mut self) -> Vec<(Vec<usize>, Vec<Mission>)> {
+        let mut moved: HashMap<usize, Vec<Mission>> = HashMap::new();
+        let movable = |m: &&Mission| !m.urgent && !m.visited && m.agent.is_none();
+        let load = |r: &Region, extra: f32| {
+            (r.mission_manager.number_missions_left() as f32 + extra) / r.agents.len() as f32
+        };
+        loop {
+            let lightest = (0..self.regions.len())
+                .filter(|&i| !self.regions[i].agents.is_empty())
+                .min_by(|&a, &b| {
+                    load(&self.regions[a], 0.0)
+                        .partial_cmp(&load(&self.regions[b], 0.0))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                });
+            let heaviest = (0..self.regions.len())
+                .filter(|&i| {
+                    self.regions[i]
+                        .mission_manager
+                        .missions()
+                        .any(|m| movable(&m))
+                })
+                .max_by(|&a, &b| {
+                    load(&self.regions[a], 0.0)
+                        .partial_cmp(&load(&self.regions[b], 0.0))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                });
+            let (from, to) = match (heaviest, lightest) {
+                (Some(from), Some(to)) if from != to => (from, to),
+                _ => break,
+            };
+            let (giver, taker) = (&self.regions[from], &self.regions[to]);
+            if !giver.agents.is_empty() && load(giver, -1.0) < REGION_IMBALANCE * load(taker, 1.0) {
+                break;
+            }
+            let center = taker.center;
+            let id = giver
+                .mission_manager
+                .missions()
+                .filter(movable)
+                .min_by(|a, b| {
+                    (a.target - center)
+                        .norm_squared()
+                        .partial_cmp(&(b.target - center).norm_squared())
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .map(|m| m.id);
+            let (mission, waiting_since) =
+                match id.and_then(|id| self.regions[from].mission_manager.take(id)) {
+                    Some(taken) => taken,
+                    None => break,
+                };
+            debug!(
+                "Moving mission {} from region {} to region {}",
+                mission.id, from, to
+            );
+            moved.entry(to).or_default().push(mission.clone());
+            self.regions[to]
+                .mission_manager
+                .adopt(mission, waiting_since);
+        }
+        moved
+            .into_iter()
+            .map(|(i, missions)| (self.regions[i].agents.iter().copied().collect(), missions))
+            .collect()
+    }
+
+    fn owner(&mut self, mission_id: usize) -> Option<&mut Region> {
+        self.regions
+            .iter_mut()
+            .find(|r| r.mission_manager.owns(mission_id))
+    }
+
+    // Ages the pending missions of every region. Returns, per region, its agents, the missions
+    // whose priority was raised and those that waited too long.
+    pub fn age(
+        &mut self,
+        now: u64,
+        max_wait: u64,
+    ) -> Vec<(Vec<usize>, Vec<Mission>, Vec<Mission>)> {
+        self.regions
+            .iter_mut()
+            .map(|region| {
+                let (raised, overdue) = region.mission_manager.age(now, max_wait);
+                (region.agents.iter().copied().collect(), raised, overdue)
+            })
+            .filter(|(_, raised, overdue)| !raised.is_empty() || !overdue.is_empty())
+            .collect()
+    }
+
+    // Returns the agents of the mission's region along with the updated mission
+    pub fn inherit_priority(&mut self, mission_id: usize) -> Option<(Vec<usize>, Mission)> {
+        let region = self.owner(mission_id)?;
+        let mission = region.mission_manager.inherit_priority(mission_id)?;
+        Some((region.agents.iter().copied().collect(), mission))
+    }
+
+    // Returns the agents of the mission's region along with the updated mission
+    pub fn hand_off(&mut self, mission_id: usize) -> Option<(Vec<usize>, Mission)> {
+        let region = self.owner(mission_id)?;
+        let mission = region.mission_manager.hand_off(mission_id)?;
+        Some((region.agents.iter().copied().collect(), mission))
+    }
+
+    pub fn make_urgent(&mut self, mission_id: usize) -> Option<Mission> {
+        self.owner(mission_id)?
+            .mission_manager
+            .make_urgent(mission_id)
+    }
+
+    pub fn finish_mission(&mut self, mission_id: usize) {
+        if let Some(region) = self
+            .regions
+            .iter_mut()
+            .find(|r| r.mission_manager.owns(mission_id))
+        {
+            region.mission_manager.finish_mission(mission_id);
+        }
+    }
+
+    pub fn set_obstacles(&mut self, obstacles: &[Obstacle]) {
+        for region in &mut self.regions {
+            region.mission_manager.set_obstacles(obstacles.to_vec());
+        }
+    }
+
+    pub fn set_time_windows(&mut self, config: &TimeWindowConfig) {
+        for region in &mut self.regions {
+            region.mission_manager.set_time_windows(config.clone());
+        }
+    }
+
+    pub fn set_reward(&mut self, config: &RewardConfig) {
+        for region in &mut self.regions {
+            region.mission_manager.set_reward(*config);
+        }
+    }
+
+    pub fn expire(&mut self, now: u64) -> Vec<Mission> {
+        self.regions
+            .iter_mut()
+            .flat_map(|r| r.mission_manager.expire(now))
+            .collect()
+    }
+
+    pub fn set_roads(&mut self, roads: &Arc<RoadNetwork>) {
+        for region in &mut self.regions {
+            region.mission_manager.set_roads(roads.clone());
+        }
+    }
+
+    pub fn set_group_shares(&mut self, shares: &[f64]) {
+        for region in &mut self.regions {
+            region.mission_manager.set_group_shares(shares.to_vec());
+        }
+    }
+
+    pub fn set_base(&mut self, base: Vector2<f32>) {
+        for region in &mut self.regions {
+            region.mission_manager.set_base(base);
+        }
+    }
+
+    pub fn close_zone(&mut self, min: Vector2<f32>, max: Vector2<f32>) -> Vec<usize> {
+        self.regions
+            .iter_mut()
+            .flat_map(|r| r.mission_manager.close_zone(min, max))
+            .collect()
+    }
+
+    // Handled by the region of its target, or of the center of the labeled region it targets.
+    // Returns the mission with the agents of that region, which should be told about it.
+    pub fn add_mission(
+        &mut self,
+        pending: &PendingMission,
+        grid: &Grid,
+        now: u64,
+    ) -> Option<(Vec<usize>, Mission)> {
+        let index = self.region_of(&pending.target.anchor(grid)?);
+        let region = &mut self.regions[index];
+        let mission = region.mission_manager.add_mission(pending, grid, now)?;
+        Some((region.agents.iter().copied().collect(), mission))
+    }
+
+    pub fn missions(&self) -> impl Iterator<Item = &Mission> {
+        self.regions
+            .iter()
+            .flat_map(|r| r.mission_manager.missions())
+    }
+
+    pub fn mission(&self, mission_id: usize) -> Option<&Mission> {
+        self.missions().find(|m| m.id == mission_id)
+    }
+
+    pub fn nearest_pending_mission(&self, p: &Vector2<f32>) -> Option<Mission> {
+        self.regions
+            .iter()
+            .flat_map(|r| r.mission_manager.missions())
+            .min_by(|a, b| {
+                (a.target - p)
+                    .norm_squared()
+                    .partial_cmp(&(b.target - p).norm_squared())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .cloned()
+    }
+
+    // The missions the agent completes with this state: the one it works on, and the cargo it
+    // drops off
+    pub fn missions_to_finish(
+        &mut self,
+        grid: &Grid,
+        agent_message: &AgentMessage,
+    ) -> Vec<Mission> {
+        let mut finished = Vec::new();
+        for mission in agent_message.mission.iter().chain(&agent_message.cargo) {
+            finished.extend(
+                self.regions
+                    .iter_mut()
+                    .find(|r| r.mission_manager.owns(mission.id))
+                    .and_then(|r| {
+                        r.mission_manager
+                            .mission_to_finish(grid, agent_message, mission)
+                    }),
+            );
+        }
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::SEED;
+    use crate::scenario::MissionTarget;
+
+    fn grid() -> Grid {
+        Grid {
+            cells: Vec::new(),
+            width: 0,
+            obstacles: Vec::new(),
+            roads: None,
+            labels: HashMap::new(),
+        }
+    }
+
+    fn add_mission_at(coordinator: &mut Coordinator, x: f32, y: f32) -> Mission {
+        let pending = PendingMission {
+            target: MissionTarget::Point([x, y]),
+            target_theta: None,
+            return_to: None,
+            group: None,
+            window_s: None,
+            camera: None,
+            reward: None,
+        };
+        coordinator.add_mission(&pending, &grid(), 0).unwrap().1
+    }
+
+    #[test]
+    fn missions_of_a_region_without_agents_move_to_a_staffed_one() {
+        let mut coordinator = Coordinator::new(2, SEED);
+        coordinator.register_agent(0, &Vector2::new(-100.0, -100.0));
+        let missions: Vec<Mission> = (0..3)
+            .map(|i| add_mission_at(&mut coordinator, 100.0 + 10.0 * i as f32, 100.0))
+            .collect();
+
+        let moved = coordinator.rebalance();
+        assert_eq!(moved.len(), 1);
+        assert_eq!(moved[0].0, vec![0]);
+        assert_eq!(moved[0].1.len(), 3);
+        assert!(coordinator.rebalance().is_empty());
+
+        // The region they moved to is in charge of them from now on
+        coordinator.finish_mission(missions[0].id);
+        assert_eq!(coordinator.number_missions_left(), 2);
+        let joined = coordinator.register_agent(1, &Vector2::new(-100.0, -50.0));
+        assert_eq!(joined.map(|pending| pending.len()), Some(2));
+    }
+
+    #[test]
+    fn staffed_regions_only_give_missions_away_past_the_imbalance() {
+        let mut coordinator = Coordinator::new(2, SEED);
+        coordinator.register_agent(0, &Vector2::new(-100.0, -100.0));
+        coordinator.register_agent(1, &Vector2::new(100.0, -100.0));
+        for i in 0..5 {
+            add_mission_at(&mut coordinator, 100.0 + 10.0 * i as f32, -100.0);
+        }
+
+        let moved = coordinator.rebalance();
+        assert_eq!(moved.len(), 1);
+        assert_eq!(moved[0].0, vec![0]);
+        assert_eq!(moved[0].1.len(), 1);
+        assert!(coordinator.rebalance().is_empty());
+        assert_eq!(coordinator.number_missions_left(), 5);
+    }
+
+    #[test]
+    fn a_single_region_never_rebalances() {
+        let mut coordinator = Coordinator::new(1, SEED);
+        coordinator.register_agent(0, &Vector2::new(0.0, 0.0));
+        add_mission_at(&mut coordinator, 100.0, 100.0);
+        assert!(coordinator.rebalance().is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::agent::AgentMessage;
+use crate::error::Result;
+use log::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// Agent states that changed since the previous frame
+pub struct RenderFrame {
+    pub tick: u64,
+    pub agents: Vec<AgentMessage>,
+}
+
+// Where the simulation sends what there is to draw. Frames are submitted from the hub and gossip
+// threads, so sinks have to hand them over to whatever does the drawing.
+pub trait RenderSink: Send + Sync {
+    fn submit_frame(&self, frame: &RenderFrame);
+}
+
+// Every frame goes to each of the sinks
+impl RenderSink for Vec<Arc<dyn RenderSink>> {
+    fn submit_frame(&self, frame: &RenderFrame) {
+        for sink in self {
+            sink.submit_frame(frame);
+        }
+    }
+}
+
+// Drops every frame, for headless runs
+pub struct NullSink;
+
+impl RenderSink for NullSink {
+    fn submit_frame(&self, _frame: &RenderFrame) {}
+}
+
+// Writes every agent state of every frame as a CSV row, to be plotted or replayed offline
+pub struct FrameRecorder {
+    out: Mutex<BufWriter<File>>,
+}
+
+impl FrameRecorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "tick,agent,x,y,theta,vx,vy,mission,completed")?;
+        Ok(FrameRecorder {
+            out: Mutex::new(out),
+        })
+    }
+
+    fn write_frame(&self, frame: &RenderFrame) -> std::io::Result<()> {
+        let mut out = self.out.lock().unwrap();
+        for state in &frame.agents {
+            let k = &state.kinematics;
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{},{}",
+                frame.tick,
+                state.id,
+                k.p.x,
+                k.p.y,
+                k.theta,
+                k.v.x,
+                k.v.y,
+                state
+                    .mission
+                    .as_ref()
+                    .map(|m| m.id.to_string())
+                    .unwrap_or_default(),
+                state.completed
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl RenderSink for FrameRecorder {
+    fn submit_frame(&self, frame: &RenderFrame) {
+        if let Err(err) = self.write_frame(frame) {
+            warn!("Could not record frame of tick {}: {}", frame.tick, err);
+        }
+    }
+}
@@ -1,17 +1,31 @@
 use crate::agent::{AgentMessage, Cell, Grid, Kinematics};
+use crate::congestion::Congestion;
 use crate::consts::*;
-use crate::missions::Mission;
+use crate::error::{Error, Result};
+use crate::missions::{CameraSensor, Mission};
+use crate::obstacles::Obstacle;
+use crate::pedestrians::Pedestrians;
+use crate::realtime::LoadShedding;
+use crate::rect::Rect;
+use crate::render_sink::{RenderFrame, RenderSink};
+use crate::roads::RoadNetwork;
+use crate::scenario::{CameraKeyframe, CameraTour};
+use crate::scene_svg::{write_scene, Scene, SceneAgent};
+use crate::system::OperatorCommand;
 use kiss3d::event::Action;
+use kiss3d::planar_camera::{PlanarCamera, Sidescroll};
 use kiss3d::text::Font;
 use kiss3d::{scene::PlanarSceneNode, window::Window};
+use log::*;
 use nalgebra::{Matrix2x1, Point2, Point3, Translation2, UnitComplex, Vector2};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::f32::consts::FRAC_1_SQRT_2;
 use std::f32::consts::FRAC_PI_2;
+use std::path::Path;
 use std::rc::Rc;
-use std::sync::mpsc::Receiver;
-use std::sync::Mutex;
-use std::time::Duration;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 struct TargetNode {
     target_cross: PlanarSceneNode,
@@ -33,6 +47,16 @@ impl TargetNode {
 }
 
 pub struct AgentNode {
+    position: Vector2<f32>,
+    theta: f32,
+    radius: f32,
+    goal: Option<Vector2<f32>>,
+    // Camera of the observation mission the agent works on
+    camera: Option<CameraSensor>,
+    trail: VecDeque<Vector2<f32>>,
+    // Next waypoints the agent shared
+    waypoints: Vec<Vector2<f32>>,
+    completed: usize,
     main: PlanarSceneNode,
     velocity: PlanarSceneNode,
     accel: PlanarSceneNode,
@@ -41,119 +65,677 @@ pub struct AgentNode {
 
 struct RendererConfig {
     with_target: bool,
+    with_costs: bool,
+    with_congestion: bool,
+    with_intents: bool,
+    shown_targets: Option<HashSet<usize>>,
+}
+
+impl RendererConfig {
+    fn shows_target_of(&self, id: usize) -> bool {
+        self.with_target
+            && self
+                .shown_targets
+                .as_ref()
+                .is_none_or(|shown| shown.contains(&id))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct LatestStates {
+    states: Arc<Mutex<HashMap<usize, AgentMessage>>>,
+}
+
+// Hands the states over to the kiss3d renderer, which only draws the latest one of each agent
+impl RenderSink for LatestStates {
+    fn submit_frame(&self, frame: &RenderFrame) {
+        let mut states = self.states.lock().unwrap();
+        for state in &frame.agents {
+            states.insert(state.id, state.clone());
+        }
+    }
+}
+
+impl LatestStates {
+    pub fn new() -> Self {
+        LatestStates::default()
+    }
+
+    pub fn take(&self) -> Vec<AgentMessage> {
+        self.states
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, state)| state)
+            .collect()
+    }
+}
+
+struct FrameStats {
+    last_frame: Instant,
+    last_report: Instant,
+    frames: usize,
+    total: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl FrameStats {
+    fn new() -> Self {
+        let now = Instant::now();
+        FrameStats {
+            last_frame: now,
+            last_report: now,
+            frames: 0,
+            total: Duration::from_secs(0),
+            min: Duration::from_secs(u64::MAX),
+            max: Duration::from_secs(0),
+        }
+    }
+
+    fn record(&mut self) {
+        let now = Instant::now();
+        let frame_time = now - self.last_frame;
+        self.last_frame = now;
+        self.frames += 1;
+        self.total += frame_time;
+        self.min = self.min.min(frame_time);
+        self.max = self.max.max(frame_time);
+
+        if (now - self.last_report).as_secs_f32() >= FRAME_STATS_PERIOD_S {
+            info!(
+                "Rendered {} frames: mean {:?}, min {:?}, max {:?}",
+                self.frames,
+                self.total / self.frames as u32,
+                self.min,
+                self.max
+            );
+            *self = FrameStats {
+                last_frame: now,
+                ..FrameStats::new()
+            };
+        }
+    }
 }
 
 pub struct Renderer {
     window: Window,
+    camera: Sidescroll,
+    camera_tour: Option<CameraTour>,
+    pedestrians: Pedestrians,
+    congestion: Option<Congestion>,
+    obstacles: Vec<Obstacle>,
+    roads: Option<Arc<RoadNetwork>>,
+    labels: Vec<(String, Rect)>,
+    start: Instant,
+    frame_stats: FrameStats,
+    frames: usize,
+    load_shedding: Option<LoadShedding>,
+    cell_nodes: Vec<(PlanarSceneNode, Point3<f32>)>,
+    grid_width: usize,
     agent_nodes: HashMap<usize, AgentNode>,
     config: Mutex<RendererConfig>,
     font: Rc<kiss3d::text::Font>,
-    rx: Receiver<AgentMessage>,
+    states: LatestStates,
+    operator: Sender<OperatorCommand>,
 }
 
 impl Renderer {
-    pub fn new(grid: &Grid, rx: Receiver<AgentMessage>) -> Self {
-        let mut window = Window::new("Allez Opi, Omi !");
+    pub fn new(
+        grid: &Grid,
+        states: LatestStates,
+        operator: Sender<OperatorCommand>,
+    ) -> Result<Self> {
+        Renderer::from_window(Window::new("Allez Opi, Omi !"), grid, states, operator)
+    }
+
+    // Draws into a window owned by a larger kiss3d application, which then calls render_one from
+    // its own main loop instead of run
+    pub fn from_window(
+        mut window: Window,
+        grid: &Grid,
+        states: LatestStates,
+        operator: Sender<OperatorCommand>,
+    ) -> Result<Self> {
+        if grid.width == 0 || !grid.cells.len().is_multiple_of(grid.width) {
+            return Err(Error::Config(format!(
+                "{} cells cannot be laid out in rows of {}",
+                grid.cells.len(),
+                grid.width
+            )));
+        }
+
+        let mut cell_nodes = Vec::with_capacity(grid.cells.len());
         for (k, cell) in grid.cells.iter().enumerate() {
             let col = k % grid.width;
             let row = k / grid.width;
             let mut rect = window.add_rectangle(CELL_SIZE, CELL_SIZE);
-            match &cell {
-                Cell::Uncrossable => rect.set_color(0.686, 0.2, 0.0),
-                Cell::Crossable(cost) => {
-                    let cost = *cost;
-                    let reduced = (cost - HALF_COST) / HALF_COST;
-                    let (r, g, b) = if cost > HALF_COST {
-                        (1.0, 1.0 - reduced, 1.0 - reduced)
-                    } else {
-                        (1.0 + reduced, 1.0, 1.0 + reduced)
-                    };
-                    rect.set_color(r, g, b);
-                }
-            }
+            let color = Renderer::cell_color(cell);
+            rect.set_color(color.x, color.y, color.z);
             rect.append_translation(&Translation2::new(
                 col as f32 * CELL_SIZE - GRID_HALF_SIZE,
                 row as f32 * CELL_SIZE - GRID_HALF_SIZE,
             ));
+            cell_nodes.push((rect, color));
+        }
+        for &(x, y) in &MAINTENANCE_STATIONS {
+            let mut station = window.add_rectangle(2.0 * AGENT_RADIUS, 2.0 * AGENT_RADIUS);
+            station.set_color(0.2, 0.4, 0.8);
+            station.append_translation(&Translation2::new(x, y));
         }
 
-        let config = Mutex::new(RendererConfig { with_target: true });
+        let config = Mutex::new(RendererConfig {
+            with_target: true,
+            with_costs: true,
+            with_congestion: true,
+            with_intents: false,
+            shown_targets: None,
+        });
 
-        Renderer {
+        Ok(Renderer {
             window,
+            camera: Sidescroll::new(),
+            camera_tour: None,
+            pedestrians: Pedestrians::new(),
+            congestion: None,
+            obstacles: grid.obstacles.clone(),
+            roads: grid.roads.clone(),
+            labels: {
+                let mut labels: Vec<(String, Rect)> = grid
+                    .labels
+                    .iter()
+                    .map(|(name, rect)| (name.clone(), *rect))
+                    .collect();
+                labels.sort_by(|a, b| a.0.cmp(&b.0));
+                labels
+            },
+            start: Instant::now(),
+            frame_stats: FrameStats::new(),
+            frames: 0,
+            load_shedding: None,
+            cell_nodes,
+            grid_width: grid.width,
             config,
             agent_nodes: HashMap::new(),
             font: Font::default(),
-            rx,
+            states,
+            operator,
+        })
+    }
+
+    fn cell_color(cell: &Cell) -> Point3<f32> {
+        match cell {
+            Cell::Uncrossable => Point3::new(0.686, 0.2, 0.0),
+            Cell::Crossable(cost) => Renderer::cost_color(*cost),
+        }
+    }
+
+    fn cost_color(cost: f32) -> Point3<f32> {
+        let reduced = (cost - HALF_COST) / HALF_COST;
+        if cost > HALF_COST {
+            Point3::new(1.0, 1.0 - reduced, 1.0 - reduced)
+        } else {
+            Point3::new(1.0 + reduced, 1.0, 1.0 + reduced)
+        }
+    }
+
+    pub fn toggle_costs(&mut self) {
+        let c = self.config.get_mut().unwrap();
+        c.with_costs = !c.with_costs;
+        for (node, color) in &mut self.cell_nodes {
+            if c.with_costs {
+                node.set_color(color.x, color.y, color.z);
+            } else {
+                node.set_color(0.6, 0.6, 0.6);
+            }
+        }
+    }
+
+    fn draw_legend(&mut self) {
+        let mut entries: Vec<(String, Point3<f32>)> = (0..=4)
+            .map(|i| {
+                let cost = i as f32 * MAX_COST / 4.0;
+                (format!("cost {}", cost), Renderer::cost_color(cost))
+            })
+            .collect();
+        entries.push((
+            "uncrossable".to_owned(),
+            Renderer::cell_color(&Cell::Uncrossable),
+        ));
+        for (i, (label, color)) in entries.iter().enumerate() {
+            self.window.draw_text(
+                label,
+                &Point2::new(10.0, 10.0 + i as f32 * LEGEND_LINE_HEIGHT),
+                LEGEND_LINE_HEIGHT,
+                &self.font,
+                color,
+            );
+        }
+    }
+
+    // Agents credited with the most completed missions, in the top right corner
+    fn draw_leaderboard(&mut self) {
+        let mut scores: Vec<(usize, usize)> = self
+            .agent_nodes
+            .iter()
+            .map(|(&id, node)| (id, node.completed))
+            .collect();
+        scores.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let x = self.window.width() as f32 - LEADERBOARD_WIDTH;
+        for (i, (id, completed)) in scores.iter().take(LEADERBOARD_SIZE).enumerate() {
+            self.window.draw_text(
+                &format!("agent {}: {}", id, completed),
+                &Point2::new(x, 10.0 + i as f32 * LEGEND_LINE_HEIGHT),
+                LEGEND_LINE_HEIGHT,
+                &self.font,
+                &Point3::new(0.0, 0.0, 0.0),
+            );
         }
     }
 
     pub fn toggle_target(&mut self) {
-        let mut c = self.config.get_mut().unwrap();
+        let c = self.config.get_mut().unwrap();
         c.with_target = !c.with_target;
     }
 
+    pub fn toggle_agent_target(&mut self, id: usize) {
+        let shown = self
+            .config
+            .get_mut()
+            .unwrap()
+            .shown_targets
+            .get_or_insert_with(HashSet::new);
+        if !shown.remove(&id) {
+            shown.insert(id);
+        }
+    }
+
+    pub fn show_all_targets(&mut self) {
+        self.config.get_mut().unwrap().shown_targets = None;
+    }
+
+    pub fn add_base(&mut self, base: Vector2<f32>) {
+        let mut node = self.window.add_circle(3.0 * AGENT_RADIUS);
+        node.set_color(0.1, 0.6, 0.2);
+        node.append_translation(&Translation2::new(base.x, base.y));
+    }
+
+    pub fn set_camera_tour(&mut self, keyframes: Vec<CameraKeyframe>) {
+        self.camera_tour = CameraTour::new(keyframes);
+    }
+
+    pub fn set_load_shedding(&mut self, shedding: LoadShedding) {
+        self.load_shedding = Some(shedding);
+    }
+
+    pub fn set_pedestrians(&mut self, pedestrians: Pedestrians) {
+        self.pedestrians = pedestrians;
+    }
+
+    pub fn set_congestion(&mut self, congestion: Congestion) {
+        self.congestion = Some(congestion);
+    }
+
+    pub fn toggle_congestion(&mut self) {
+        let c = self.config.get_mut().unwrap();
+        c.with_congestion = !c.with_congestion;
+    }
+
+    // Outlines regions holding more than one agent, from yellow to red as they fill up
+    fn draw_congestion(&mut self) {
+        let congestion = match &self.congestion {
+            Some(congestion) if self.config.get_mut().unwrap().with_congestion => congestion,
+            _ => return,
+        };
+        for (min, count) in congestion.regions() {
+            if count < 2 {
+                continue;
+            }
+            let load = ((count - 1) as f32 / (CONGESTION_THRESHOLD - 1) as f32).min(1.0);
+            let color = Point3::new(1.0, 1.0 - load, 0.0);
+            let corners = [
+                min,
+                min + Vector2::new(CONGESTION_REGION_SIZE, 0.0),
+                min + Vector2::repeat(CONGESTION_REGION_SIZE),
+                min + Vector2::new(0.0, CONGESTION_REGION_SIZE),
+            ];
+            for i in 0..corners.len() {
+                self.window.draw_planar_line(
+                    &Point2::from(corners[i]),
+                    &Point2::from(corners[(i + 1) % corners.len()]),
+                    &color,
+                );
+            }
+        }
+    }
+
+    pub fn toggle_intents(&mut self) {
+        let c = self.config.get_mut().unwrap();
+        c.with_intents = !c.with_intents;
+    }
+
+    // Routes the agents shared with each other, from where they are through their next waypoints
+    fn draw_intents(&mut self) {
+        if !self.config.get_mut().unwrap().with_intents {
+            return;
+        }
+        let color = Point3::new(0.8, 0.4, 0.8);
+        for node in self.agent_nodes.values() {
+            let mut from = node.position;
+            for waypoint in &node.waypoints {
+                self.window
+                    .draw_planar_line(&Point2::from(from), &Point2::from(*waypoint), &color);
+                from = *waypoint;
+            }
+        }
+    }
+
+    // Field of view of the agents on observation missions, as a cone out to the camera range
+    fn draw_cameras(&mut self) {
+        let color = Point3::new(0.2, 0.8, 0.2);
+        for node in self.agent_nodes.values() {
+            let camera = match node.camera {
+                Some(camera) => camera,
+                None => continue,
+            };
+            let outline: Vec<Point2<f32>> = (0..=FOV_ARC_SEGMENTS)
+                .map(|i| {
+                    let angle = node.theta - camera.fov / 2.0
+                        + camera.fov * i as f32 / FOV_ARC_SEGMENTS as f32;
+                    Point2::from(
+                        node.position + camera.range * Vector2::new(angle.cos(), angle.sin()),
+                    )
+                })
+                .collect();
+            let apex = Point2::from(node.position);
+            self.window.draw_planar_line(&apex, &outline[0], &color);
+            self.window
+                .draw_planar_line(&apex, &outline[FOV_ARC_SEGMENTS], &color);
+            for pair in outline.windows(2) {
+                self.window.draw_planar_line(&pair[0], &pair[1], &color);
+            }
+        }
+    }
+
+    // The exact shapes, on top of their rasterized cells
+    fn draw_obstacles(&mut self) {
+        let color = Point3::new(1.0, 1.0, 1.0);
+        for obstacle in &self.obstacles {
+            let outline = obstacle.outline();
+            for (i, a) in outline.iter().enumerate() {
+                let b = outline[(i + 1) % outline.len()];
+                self.window
+                    .draw_planar_line(&Point2::from(*a), &Point2::from(b), &color);
+            }
+        }
+    }
+
+    // Edges as lines, nodes as small crosses
+    // Window coordinates of a world position. The camera only unprojects, but does so with an
+    // affine map per axis, which two points are enough to invert.
+    fn project(&self, p: &Vector2<f32>) -> Point2<f32> {
+        let size = self.window.size();
+        let size = Vector2::new(size.x as f32, size.y as f32);
+        let origin = self.camera.unproject(&Point2::origin(), &size);
+        let unit = self.camera.unproject(&Point2::new(1.0, 1.0), &size) - origin;
+        Point2::new((p.x - origin.x) / unit.x, (p.y - origin.y) / unit.y)
+    }
+
+    // Outlines of the labeled regions, with their name in the top left corner
+    fn draw_labels(&mut self) {
+        let color = Point3::new(0.5, 0.2, 0.6);
+        for (name, rect) in &self.labels {
+            let corners = rect.corners();
+            for i in 0..corners.len() {
+                self.window.draw_planar_line(
+                    &Point2::from(corners[i]),
+                    &Point2::from(corners[(i + 1) % corners.len()]),
+                    &color,
+                );
+            }
+            let anchor = self.project(&Vector2::new(rect.min.x, rect.max.y));
+            self.window
+                .draw_text(name, &anchor, LEGEND_LINE_HEIGHT, &self.font, &color);
+        }
+    }
+
+    fn draw_roads(&mut self) {
+        let roads = match &self.roads {
+            Some(roads) => roads,
+            None => return,
+        };
+        let color = Point3::new(0.6, 0.6, 0.6);
+        let arm = Vector2::new(ROAD_NODE_SIZE, ROAD_NODE_SIZE);
+        let flipped = Vector2::new(ROAD_NODE_SIZE, -ROAD_NODE_SIZE);
+        for (a, b) in roads.edges() {
+            self.window
+                .draw_planar_line(&Point2::from(a), &Point2::from(b), &color);
+            for node in [a, b] {
+                self.window.draw_planar_line(
+                    &Point2::from(node - arm),
+                    &Point2::from(node + arm),
+                    &color,
+                );
+                self.window.draw_planar_line(
+                    &Point2::from(node - flipped),
+                    &Point2::from(node + flipped),
+                    &color,
+                );
+            }
+        }
+    }
+
+    fn draw_pedestrians(&mut self) {
+        let color = Point3::new(0.9, 0.9, 0.2);
+        for p in self.pedestrians.positions() {
+            let p = Point2::from(p);
+            let dx = Vector2::new(PEDESTRIAN_RADIUS, 0.0);
+            let dy = Vector2::new(0.0, PEDESTRIAN_RADIUS);
+            self.window.draw_planar_line(&(p - dx), &(p + dx), &color);
+            self.window.draw_planar_line(&(p - dy), &(p + dy), &color);
+        }
+    }
+
+    // Flags the pending mission closest to the cursor as urgent
+    pub fn flag_urgent_at_cursor(&mut self) {
+        let (x, y) = match self.window.cursor_pos() {
+            Some(pos) => pos,
+            None => return,
+        };
+        let size = self.window.size();
+        let p = self.camera.unproject(
+            &Point2::new(x as f32, y as f32),
+            &Vector2::new(size.x as f32, size.y as f32),
+        );
+        if self
+            .operator
+            .send(OperatorCommand::UrgentNear(p.coords))
+            .is_err()
+        {
+            warn!("System manager is gone, cannot flag an urgent mission");
+        }
+    }
+
+    // Writes what is currently on screen as a vector image
+    pub fn export_svg(&mut self) {
+        let config = self.config.lock().unwrap();
+        let cells = self
+            .cell_nodes
+            .iter()
+            .enumerate()
+            .map(|(k, (_, color))| {
+                let center = Vector2::new(
+                    (k % self.grid_width) as f32 * CELL_SIZE - GRID_HALF_SIZE,
+                    (k / self.grid_width) as f32 * CELL_SIZE - GRID_HALF_SIZE,
+                );
+                let color = if config.with_costs {
+                    *color
+                } else {
+                    Point3::new(0.6, 0.6, 0.6)
+                };
+                (center, color)
+            })
+            .collect();
+        let mut agents: Vec<SceneAgent> = self
+            .agent_nodes
+            .iter()
+            .map(|(&id, node)| SceneAgent {
+                id,
+                p: node.position,
+                theta: node.theta,
+                radius: node.radius,
+                goal: node.goal.filter(|_| config.shows_target_of(id)),
+                trail: node.trail.iter().cloned().collect(),
+            })
+            .collect();
+        agents.sort_by_key(|agent| agent.id);
+        let scene = Scene {
+            cells,
+            obstacles: &self.obstacles,
+            agents,
+            pedestrians: self.pedestrians.positions(),
+        };
+        match write_scene(&scene, Path::new(SCENE_SVG_PATH)) {
+            Ok(()) => info!("Exported the scene to {}", SCENE_SVG_PATH),
+            Err(e) => error!("Could not export the scene to {}: {}", SCENE_SVG_PATH, e),
+        }
+    }
+
+    pub fn cycle_allocation(&mut self) {
+        if self
+            .operator
+            .send(OperatorCommand::CycleAllocation)
+            .is_err()
+        {
+            warn!("System manager is gone, cannot switch the allocation strategy");
+        }
+    }
+
+    pub fn request_scenario_snapshot(&mut self) {
+        if self.operator.send(OperatorCommand::SaveScenario).is_err() {
+            warn!("System manager is gone, cannot save the scenario");
+        }
+    }
+
+    pub fn request_trace_dump(&mut self) {
+        if self.operator.send(OperatorCommand::DumpTrace).is_err() {
+            warn!("System manager is gone, cannot dump the message trace");
+        }
+    }
+
     pub fn run(mut self) {
+        self.start = Instant::now();
         while self.render_one() {}
     }
 
+    // Handles the pending input and draws a single frame, false once the window is closed
     pub fn render_one(&mut self) -> bool {
+        self.frames += 1;
+        if let Some(shedding) = &self.load_shedding {
+            if !self.frames.is_multiple_of(shedding.divider()) {
+                std::thread::sleep(Duration::from_millis(SHED_FRAME_PERIOD_MS));
+                return true;
+            }
+        }
         for mut event in self.window.events().iter() {
             if let kiss3d::event::WindowEvent::Key(button, Action::Press, _) = event.value {
                 event.inhibited = true;
                 match button {
                     kiss3d::event::Key::A => todo!(), // accel
+                    kiss3d::event::Key::C => self.show_all_targets(),
+                    kiss3d::event::Key::D => self.request_trace_dump(),
+                    kiss3d::event::Key::G => self.toggle_costs(),
+                    kiss3d::event::Key::I => self.toggle_intents(),
+                    kiss3d::event::Key::L => self.cycle_allocation(),
+                    kiss3d::event::Key::O => self.toggle_congestion(),
+                    kiss3d::event::Key::R => self.request_scenario_snapshot(),
+                    kiss3d::event::Key::S => self.export_svg(),
                     kiss3d::event::Key::T => self.toggle_target(),
+                    kiss3d::event::Key::U => self.flag_urgent_at_cursor(),
                     kiss3d::event::Key::V => todo!(), // velocity
+                    kiss3d::event::Key::Key0 => self.toggle_agent_target(0),
+                    kiss3d::event::Key::Key1 => self.toggle_agent_target(1),
+                    kiss3d::event::Key::Key2 => self.toggle_agent_target(2),
+                    kiss3d::event::Key::Key3 => self.toggle_agent_target(3),
+                    kiss3d::event::Key::Key4 => self.toggle_agent_target(4),
+                    kiss3d::event::Key::Key5 => self.toggle_agent_target(5),
+                    kiss3d::event::Key::Key6 => self.toggle_agent_target(6),
+                    kiss3d::event::Key::Key7 => self.toggle_agent_target(7),
+                    kiss3d::event::Key::Key8 => self.toggle_agent_target(8),
+                    kiss3d::event::Key::Key9 => self.toggle_agent_target(9),
                     _ => event.inhibited = false,
                 }
             }
         }
-        loop {
-            match self.rx.recv_timeout(Duration::from_millis(0)) {
-                Ok(agent_message) => {
-                    match self.agent_nodes.get_mut(&agent_message.id) {
-                        Some(node) => {
-                            Renderer::update_agent(
-                                node,
-                                &agent_message.kinematics,
-                                &agent_message.mission,
-                                &self.config.lock().unwrap(),
-                            );
-                        }
-                        None => self.add_agent(&agent_message),
-                    }
-                    self.window.draw_text(
-                        &agent_message.id.to_string(),
-                        &(Point2::origin()
-                            + Vector2::new(
-                                agent_message.kinematics.p.x,
-                                -agent_message.kinematics.p.y,
-                            )),
-                        10.0,
-                        &self.font,
-                        &Point3::new(1.0, 0.0, 0.0),
-                    )
+        for agent_message in self.states.take() {
+            match self.agent_nodes.get_mut(&agent_message.id) {
+                Some(node) => {
+                    Renderer::update_agent(
+                        node,
+                        agent_message.id,
+                        agent_message.completed,
+                        &agent_message.kinematics,
+                        &agent_message.mission,
+                        &agent_message.waypoints,
+                        &self.config.lock().unwrap(),
+                    );
                 }
-                Err(e) => match e {
-                    std::sync::mpsc::RecvTimeoutError::Timeout => break,
-                    std::sync::mpsc::RecvTimeoutError::Disconnected => {}
-                },
+                None => self.add_agent(&agent_message),
             }
         }
-        self.window.render()
+        for (id, node) in &self.agent_nodes {
+            self.window.draw_text(
+                &id.to_string(),
+                &(Point2::origin() + Vector2::new(node.position.x, -node.position.y)),
+                10.0,
+                &self.font,
+                &Point3::new(1.0, 0.0, 0.0),
+            )
+        }
+        if self.config.lock().unwrap().with_costs {
+            self.draw_legend();
+        }
+        self.draw_leaderboard();
+        self.draw_roads();
+        self.draw_labels();
+        self.draw_congestion();
+        self.draw_pedestrians();
+        self.draw_obstacles();
+        self.draw_cameras();
+        self.draw_intents();
+        if let Some(tour) = &self.camera_tour {
+            let (center, zoom) = tour.at(self.start.elapsed().as_secs_f32());
+            self.camera.look_at(Point2::from(center), zoom);
+        }
+        self.frame_stats.record();
+        self.window.render_with(None, Some(&mut self.camera), None)
     }
 
     fn update_agent(
         agent_node: &mut AgentNode,
+        id: usize,
+        completed: usize,
         kinematics: &Kinematics,
         mission: &Option<Mission>,
+        waypoints: &[Vector2<f32>],
         config: &RendererConfig,
     ) {
         let agent_t = Translation2::new(kinematics.p.x, kinematics.p.y);
+        agent_node.position = kinematics.p;
+        agent_node.theta = kinematics.theta;
+        agent_node.completed = completed;
+        agent_node.trail.push_back(kinematics.p);
+        if agent_node.trail.len() > TRAIL_LENGTH {
+            agent_node.trail.pop_front();
+        }
+        agent_node.waypoints = waypoints.to_vec();
+        agent_node.goal = mission.as_ref().map(|mission| mission.current_goal().0);
+        agent_node.camera = mission
+            .as_ref()
+            .and_then(|mission| mission.camera.filter(|_| !mission.visited));
 
-        if let Some(mission) = mission {
-            let delta = mission.target - kinematics.p;
+        if let Some(goal) = agent_node.goal {
+            let delta = goal - kinematics.p;
             let center_target_line = delta / 2.0 + kinematics.p;
             agent_node
                 .to_target
@@ -162,7 +744,7 @@ impl Renderer {
             agent_node
                 .to_target
                 .target_cross
-                .set_local_translation(mission.target.into());
+                .set_local_translation(goal.into());
             agent_node
                 .to_target
                 .target_line
@@ -178,11 +760,11 @@ impl Renderer {
             agent_node
                 .to_target
                 .target_line
-                .set_visible(config.with_target);
+                .set_visible(config.shows_target_of(id));
             agent_node
                 .to_target
                 .target_cross
-                .set_visible(config.with_target);
+                .set_visible(config.shows_target_of(id));
         } else {
             agent_node.to_target.target_line.set_visible(false);
             agent_node.to_target.target_cross.set_visible(false);
@@ -216,16 +798,17 @@ impl Renderer {
 
     pub fn add_agent(&mut self, agent_message: &AgentMessage) {
         let mut main = self.window.add_planar_group();
+        let radius = agent_message.kinematics.radius;
 
-        let mut main_radius_out = main.add_circle(AGENT_RADIUS);
-        let mut main_radius_in = main.add_circle(AGENT_RADIUS * 0.9);
+        let mut main_radius_out = main.add_circle(radius);
+        let mut main_radius_in = main.add_circle(radius * 0.9);
         let mut main_triangle = main.add_convex_polygon(
             vec![
                 Point2::new(0.0, 1.0),
                 Point2::new(-FRAC_1_SQRT_2, FRAC_1_SQRT_2),
                 Point2::new(FRAC_1_SQRT_2, FRAC_1_SQRT_2),
             ],
-            Matrix2x1::new(AGENT_RADIUS, AGENT_RADIUS),
+            Matrix2x1::new(radius, radius),
         );
         let mut velocity = self.window.add_rectangle(LINE_WIDTH, 1.0);
         let mut accel = self.window.add_rectangle(LINE_WIDTH, 1.0);
@@ -238,6 +821,14 @@ impl Renderer {
         main_radius_in.set_color(1.0, 1.0, 1.0);
 
         let mut agent_node = AgentNode {
+            position: agent_message.kinematics.p,
+            theta: agent_message.kinematics.theta,
+            radius,
+            goal: None,
+            camera: None,
+            trail: VecDeque::new(),
+            waypoints: Vec::new(),
+            completed: agent_message.completed,
             main,
             velocity,
             accel,
@@ -245,8 +836,11 @@ impl Renderer {
         };
         Renderer::update_agent(
             &mut agent_node,
+            agent_message.id,
+            agent_message.completed,
             &agent_message.kinematics,
             &None,
+            &[],
             &self.config.lock().unwrap(),
         );
         assert!(self
@@ -0,0 +1,103 @@
+use crate::consts::ARRIVALS_STREAM;
+use rand::Rng;
+use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+// Decides how many missions to add to a region, given how many agents it hosts and how many
+// missions are still pending there
+pub trait ReplenishmentPolicy: Send {
+    fn missions_to_create(&mut self, n_agents: usize, pending: usize) -> usize;
+}
+
+// Tops the region up with one mission per agent whenever fewer than `ratio` missions per agent
+// are left
+pub struct PendingPerAgent {
+    ratio: f32,
+}
+
+impl ReplenishmentPolicy for PendingPerAgent {
+    fn missions_to_create(&mut self, n_agents: usize, pending: usize) -> usize {
+        if (pending as f32) < self.ratio * n_agents as f32 {
+            n_agents
+        } else {
+            0
+        }
+    }
+}
+
+// Keeps a constant number of missions pending, whatever the number of agents
+pub struct FixedPool {
+    size: usize,
+}
+
+impl ReplenishmentPolicy for FixedPool {
+    fn missions_to_create(&mut self, _n_agents: usize, pending: usize) -> usize {
+        self.size.saturating_sub(pending)
+    }
+}
+
+// Missions arrive on their own as a Poisson process, regardless of the backlog
+pub struct Arrivals {
+    rate_per_s: f64,
+    start: Instant,
+    next_arrival_s: f64,
+    rng: Pcg64,
+}
+
+impl Arrivals {
+    fn new(rate_per_s: f64, seed: u128, stream: u128) -> Self {
+        let mut arrivals = Arrivals {
+            rate_per_s,
+            start: Instant::now(),
+            next_arrival_s: 0.0,
+            rng: Pcg64::new(seed, stream),
+        };
+        arrivals.next_arrival_s = arrivals.inter_arrival();
+        arrivals
+    }
+
+    fn inter_arrival(&mut self) -> f64 {
+        -(1.0 - self.rng.gen::<f64>()).ln() / self.rate_per_s
+    }
+}
+
+impl ReplenishmentPolicy for Arrivals {
+    fn missions_to_create(&mut self, _n_agents: usize, _pending: usize) -> usize {
+        let now = self.start.elapsed().as_secs_f64();
+        let mut count = 0;
+        while self.next_arrival_s <= now {
+            count += 1;
+            self.next_arrival_s += self.inter_arrival();
+        }
+        count
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplenishmentConfig {
+    PendingPerAgent { ratio: f32 },
+    FixedPool { size: usize },
+    Arrivals { rate_per_s: f64 },
+}
+
+impl Default for ReplenishmentConfig {
+    fn default() -> Self {
+        ReplenishmentConfig::PendingPerAgent { ratio: 2.0 }
+    }
+}
+
+impl ReplenishmentConfig {
+    pub fn build(&self, region: usize, seed: u128) -> Box<dyn ReplenishmentPolicy> {
+        match *self {
+            ReplenishmentConfig::PendingPerAgent { ratio } => Box::new(PendingPerAgent { ratio }),
+            ReplenishmentConfig::FixedPool { size } => Box::new(FixedPool { size }),
+            ReplenishmentConfig::Arrivals { rate_per_s } => Box::new(Arrivals::new(
+                rate_per_s,
+                seed,
+                ARRIVALS_STREAM + region as u128,
+            )),
+        }
+    }
+}
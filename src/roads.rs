@@ -0,0 +1,334 @@
+use crate::congestion::Congestion;
+use crate::consts::GRID_HALF_SIZE;
+use crate::error::{Error, Result};
+use nalgebra::Vector2;
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::path::Path;
+
+// On-disk layout: node positions in world coordinates, and two-way edges as pairs of node indices
+#[derive(Deserialize)]
+struct RoadFile {
+    nodes: Vec<[f32; 2]>,
+    edges: Vec<[usize; 2]>,
+}
+
+// Roads agents travel along instead of crossing the grid freely, e.g. converted from an OSM extract
+pub struct RoadNetwork {
+    nodes: Vec<Vector2<f32>>,
+    edges: Vec<(usize, usize)>,
+    // Neighbours of every node, with the length of the edge leading to them
+    adjacency: Vec<Vec<(usize, f32)>>,
+}
+
+// Node to expand, by cost so far or estimated total cost
+pub struct Visit {
+    pub cost: f32,
+    pub node: usize,
+}
+
+impl PartialEq for Visit {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Visit {}
+
+impl PartialOrd for Visit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed, so that the binary heap pops the cheapest visit first
+impl Ord for Visit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl RoadNetwork {
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        RoadNetwork::parse(&json).map_err(|err| match err {
+            Error::Config(message) => Error::Config(format!(
+                "invalid road network {}: {}",
+                path.display(),
+                message
+            )),
+            err => err,
+        })
+    }
+
+    pub fn parse(json: &str) -> Result<Self> {
+        let roads: RoadFile =
+            serde_json::from_str(json).map_err(|err| Error::Config(err.to_string()))?;
+        RoadNetwork::new(roads.nodes, roads.edges).map_err(Error::Config)
+    }
+
+    fn new(nodes: Vec<[f32; 2]>, edges: Vec<[usize; 2]>) -> std::result::Result<Self, String> {
+        if nodes.is_empty() {
+            return Err("road network has no nodes".to_owned());
+        }
+        if let Some(node) = nodes
+            .iter()
+            .find(|n| n.iter().any(|c| !c.is_finite() || c.abs() > GRID_HALF_SIZE))
+        {
+            return Err(format!("road node {:?} lies outside of the grid", node));
+        }
+        let nodes: Vec<Vector2<f32>> = nodes.iter().map(|n| Vector2::new(n[0], n[1])).collect();
+        let mut adjacency = vec![Vec::new(); nodes.len()];
+        for &[a, b] in &edges {
+            if a >= nodes.len() || b >= nodes.len() || a == b {
+                return Err(format!(
+                    "road edge {} - {} does not join two of the {} nodes",
+                    a,
+                    b,
+                    nodes.len()
+                ));
+            }
+            let length = (nodes[a] - nodes[b]).norm();
+            adjacency[a].push((b, length));
+            adjacency[b].push((a, length));
+        }
+        Ok(RoadNetwork {
+            nodes,
+            edges: edges.iter().map(|&[a, b]| (a, b)).collect(),
+            adjacency,
+        })
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = (Vector2<f32>, Vector2<f32>)> + '_ {
+        self.edges
+            .iter()
+            .map(move |&(a, b)| (self.nodes[a], self.nodes[b]))
+    }
+
+    pub fn nearest_node(&self, p: &Vector2<f32>) -> Vector2<f32> {
+        self.nodes[self.nearest(p)]
+    }
+
+    fn nearest(&self, p: &Vector2<f32>) -> usize {
+        (0..self.nodes.len())
+            .min_by(|&a, &b| {
+                (self.nodes[a] - p)
+                    .norm_squared()
+                    .partial_cmp(&(self.nodes[b] - p).norm_squared())
+                    .unwrap_or(Ordering::Equal)
+            })
+            .unwrap_or(0)
+    }
+
+    // Point of the network nearest to p: on the nearest edge, or on the nearest node when there
+    // are no edges. Comes with the edge it lies on and the nodes it leads to, each with the length
+    // of road in between.
+    fn snap(&self, p: &Vector2<f32>) -> (Vector2<f32>, Option<usize>, Vec<(usize, f32)>) {
+        let on_edge = |&(a, b): &(usize, usize)| {
+            let (from, along) = (self.nodes[a], self.nodes[b] - self.nodes[a]);
+            let t = ((p - from).dot(&along) / along.norm_squared()).clamp(0.0, 1.0);
+            from + t * along
+        };
+        let nearest = self
+            .edges
+            .iter()
+            .enumerate()
+            .map(|(i, edge)| (i, on_edge(edge)))
+            .min_by(|(_, a), (_, b)| {
+                (a - p)
+                    .norm_squared()
+                    .partial_cmp(&(b - p).norm_squared())
+                    .unwrap_or(Ordering::Equal)
+            });
+        match nearest {
+            Some((i, q)) => {
+                let (a, b) = self.edges[i];
+                let ends = vec![
+                    (a, (q - self.nodes[a]).norm()),
+                    (b, (q - self.nodes[b]).norm()),
+                ];
+                (q, Some(i), ends)
+            }
+            None => {
+                let node = self.nearest(p);
+                (self.nodes[node], None, vec![(node, 0.0)])
+            }
+        }
+    }
+
+    // Dijkstra from the point of the network nearest to the start to the one nearest to the goal,
+    // ending on the goal itself, with crowded edges weighing more than their length. An error if
+    // the two are not connected.
+    pub fn route(
+        &self,
+        start: &Vector2<f32>,
+        goal: &Vector2<f32>,
+        congestion: &Congestion,
+    ) -> Result<Vec<Vector2<f32>>> {
+        let (entry, entry_edge, starts) = self.snap(start);
+        let (exit, exit_edge, ends) = self.snap(goal);
+        let mut costs = vec![f32::INFINITY; self.nodes.len()];
+        let mut previous = vec![None; self.nodes.len()];
+        let mut heap = BinaryHeap::new();
+        for &(node, length) in &starts {
+            let cost = length * congestion.cost_factor(&entry, &self.nodes[node]);
+            if cost < costs[node] {
+                costs[node] = cost;
+                heap.push(Visit { cost, node });
+            }
+        }
+        while let Some(Visit { cost, node }) = heap.pop() {
+            if cost > costs[node] {
+                continue;
+            }
+            for &(next, length) in &self.adjacency[node] {
+                let through =
+                    cost + length * congestion.cost_factor(&self.nodes[node], &self.nodes[next]);
+                if through < costs[next] {
+                    costs[next] = through;
+                    previous[next] = Some(node);
+                    heap.push(Visit {
+                        cost: through,
+                        node: next,
+                    });
+                }
+            }
+        }
+
+        // Through the network, leaving it by the cheapest end of the goal's edge, unless both
+        // points lie on the same edge and the way between them is shorter
+        let last = ends
+            .iter()
+            .map(|&(node, length)| {
+                let cost = costs[node] + length * congestion.cost_factor(&self.nodes[node], &exit);
+                (node, cost)
+            })
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+            .filter(|(_, cost)| cost.is_finite());
+        let direct = (entry_edge.is_some() && entry_edge == exit_edge)
+            .then(|| (exit - entry).norm() * congestion.cost_factor(&entry, &exit));
+        let mut path = vec![*goal, exit];
+        match (last, direct) {
+            (Some((_, through)), Some(direct)) if direct <= through => {}
+            (None, Some(_)) => {}
+            (Some((last, _)), _) => {
+                let mut node = Some(last);
+                while let Some(n) = node {
+                    path.push(self.nodes[n]);
+                    node = previous[n];
+                }
+            }
+            (None, None) => {
+                return Err(Error::Planning(format!(
+                    "no road leads from {} to {}",
+                    start, goal
+                )))
+            }
+        }
+        path.push(entry);
+        path.reverse();
+        path.dedup();
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_road_networks_are_rejected() {
+        let source = include_str!("../scenarios/roads.json").trim_end();
+        assert!(RoadNetwork::parse(source).is_ok());
+        for (end, _) in source.char_indices() {
+            assert!(matches!(
+                RoadNetwork::parse(&source[..end]),
+                Err(Error::Config(_))
+            ));
+        }
+        for json in &[
+            "{\"nodes\": [], \"edges\": []}",
+            "{\"nodes\": [[0, 0]], \"edges\": [[0, 1]]}",
+            "{\"nodes\": [[0, 0], [1, 1]], \"edges\": [[1, 1]]}",
+            "{\"nodes\": [[0, 1e39]], \"edges\": []}",
+            "{\"nodes\": [[0, 0]], \"edges\": [[0, -1]]}",
+        ] {
+            assert!(
+                matches!(RoadNetwork::parse(json), Err(Error::Config(_))),
+                "{:?}",
+                json
+            );
+        }
+    }
+
+    // Two ways from (0, 0) to (200, 0): through `middle`, or through (100, 30)
+    fn two_routes(middle: [f32; 2]) -> RoadNetwork {
+        RoadNetwork::new(
+            vec![[0.0, 0.0], middle, [200.0, 0.0], [100.0, 30.0]],
+            vec![[0, 1], [1, 2], [0, 3], [3, 2]],
+        )
+        .unwrap()
+    }
+
+    // Waypoints to the meter, every expected one lying on whole coordinates
+    fn route(roads: &RoadNetwork, start: [f32; 2], goal: [f32; 2]) -> Option<Vec<[f32; 2]>> {
+        let path = roads
+            .route(
+                &Vector2::new(start[0], start[1]),
+                &Vector2::new(goal[0], goal[1]),
+                &Congestion::new(),
+            )
+            .ok()?;
+        Some(path.iter().map(|p| [p.x.round(), p.y.round()]).collect())
+    }
+
+    #[test]
+    fn shortest_of_two_routes_is_taken() {
+        let straight = two_routes([100.0, 0.0]);
+        assert_eq!(
+            route(&straight, [0.0, 0.0], [200.0, 0.0]),
+            Some(vec![[0.0, 0.0], [100.0, 0.0], [200.0, 0.0]])
+        );
+
+        let detour = two_routes([100.0, -80.0]);
+        assert_eq!(
+            route(&detour, [0.0, 0.0], [200.0, 0.0]),
+            Some(vec![[0.0, 0.0], [100.0, 30.0], [200.0, 0.0]])
+        );
+    }
+
+    #[test]
+    fn disconnected_roads_give_no_route() {
+        let roads = RoadNetwork::new(
+            vec![[0.0, 0.0], [50.0, 0.0], [200.0, 200.0], [240.0, 200.0]],
+            vec![[0, 1], [2, 3]],
+        )
+        .unwrap();
+        assert_eq!(route(&roads, [0.0, 0.0], [240.0, 200.0]), None);
+        assert!(route(&roads, [0.0, 0.0], [50.0, 10.0]).is_some());
+    }
+
+    // Off-road points join and leave the network at the nearest point of the nearest edge, not at
+    // the nearest node
+    #[test]
+    fn off_road_points_snap_to_the_nearest_edge() {
+        let roads = two_routes([100.0, 0.0]);
+        assert_eq!(
+            route(&roads, [30.0, -10.0], [170.0, -10.0]),
+            Some(vec![
+                [30.0, 0.0],
+                [100.0, 0.0],
+                [170.0, 0.0],
+                [170.0, -10.0]
+            ])
+        );
+        assert_eq!(
+            route(&roads, [20.0, -5.0], [80.0, -5.0]),
+            Some(vec![[20.0, 0.0], [80.0, 0.0], [80.0, -5.0]])
+        );
+    }
+}
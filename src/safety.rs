@@ -0,0 +1,55 @@
+use crate::units::MetersPerSecondSquared;
+use nalgebra::Vector2;
+
+// Something the agent must keep a gap from, seen from the agent
+pub struct Constraint {
+    // Unit vector pointing from the constraint to the agent
+    pub normal: Vector2<f32>,
+    // Free space left before the minimum separation is breached
+    pub gap: f32,
+    // Agent velocity relative to the constraint
+    pub relative_velocity: Vector2<f32>,
+    // Share of the braking this agent is responsible for: all of it for static obstacles, half
+    // of it for other agents running the same filter
+    pub share: f32,
+}
+
+// Analytic barrier on the braking distance: whenever an approaching constraint needs more than
+// SAFETY_ACTIVATION of the agent's share of braking to stop within the gap, the acceleration
+// along the normal is raised to what stopping in time requires. The tangential part is shrunk
+// first when the result exceeds the cap. Returns the filtered acceleration and the number of
+// constraints that needed an intervention.
+pub fn filter(
+    nominal: Vector2<f32>,
+    constraints: &[Constraint],
+    max_acceleration: MetersPerSecondSquared,
+    activation: f32,
+) -> (Vector2<f32>, usize) {
+    let max_acceleration = max_acceleration.0;
+    let mut a = nominal;
+    let mut interventions = 0;
+    for c in constraints {
+        let closing = -c.relative_velocity.dot(&c.normal);
+        if closing <= 0.0 {
+            continue;
+        }
+        let budget = c.share * max_acceleration;
+        let required = (closing * closing / (2.0 * c.gap.max(f32::EPSILON))).min(budget);
+        let along = a.dot(&c.normal);
+        if required < activation * budget || along >= required {
+            continue;
+        }
+        interventions += 1;
+        let tangential = a - along * c.normal;
+        let room = (max_acceleration * max_acceleration - required * required)
+            .max(0.0)
+            .sqrt();
+        let tangential = if tangential.norm() > room {
+            tangential * room / tangential.norm()
+        } else {
+            tangential
+        };
+        a = tangential + required * c.normal;
+    }
+    (a, interventions)
+}
@@ -0,0 +1,893 @@
+use crate::agent::{AllocationStrategy, BoundaryPolicy, Grid};
+use crate::consts::{
+    GRID_HALF_SIZE, MAX_COST, MAX_REGION_SPLIT, RATE_LIMIT_PER_S, REGION_SPLIT, SEED,
+};
+use crate::error::{Error, Result};
+use crate::missions::{CameraSensor, RewardConfig, TimeWindowConfig};
+use crate::observation::ObservationConfig;
+use crate::obstacles::Obstacle;
+use crate::planning::PlannerKind;
+use crate::replenishment::ReplenishmentConfig;
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::time::Instant;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScenarioAction {
+    CloseZone { min: [f32; 2], max: [f32; 2] },
+    SpawnAgents { count: usize, position: [f32; 2] },
+    KillAgent { id: usize },
+    SwitchAllocation { strategy: AllocationStrategy },
+    AddMission { mission: PendingMission },
+}
+
+fn on_grid(p: &[f32; 2]) -> bool {
+    p.iter().all(|c| c.is_finite() && c.abs() <= GRID_HALF_SIZE)
+}
+
+impl ScenarioAction {
+    pub fn validate(&self, scenario: &Scenario) -> std::result::Result<(), String> {
+        match self {
+            ScenarioAction::CloseZone { min, max } => {
+                if !on_grid(min) || !on_grid(max) || min[0] > max[0] || min[1] > max[1] {
+                    return Err(format!(
+                        "zone from {:?} to {:?} is not a box on the grid",
+                        min, max
+                    ));
+                }
+            }
+            ScenarioAction::SpawnAgents { count, position } => {
+                if *count == 0 || !on_grid(position) {
+                    return Err(format!(
+                        "spawning {} agents at {:?} needs at least one agent on the grid",
+                        count, position
+                    ));
+                }
+            }
+            ScenarioAction::AddMission { mission } => {
+                mission.validate(scenario.groups.len(), &scenario.labeled_regions)?
+            }
+            ScenarioAction::KillAgent { .. } | ScenarioAction::SwitchAllocation { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TimedEvent {
+    pub at: f32,
+    pub action: ScenarioAction,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CameraKeyframe {
+    pub at: f32,
+    pub center: [f32; 2],
+    pub zoom: f32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AgentGroup {
+    pub name: String,
+    pub agents: Vec<usize>,
+    // Fraction of the new missions only this group is told about
+    #[serde(default)]
+    pub mission_share: f64,
+}
+
+// Named box of the grid, which missions can target by name
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LabeledRegion {
+    pub name: String,
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+// Box of the grid whose cells get another crossing cost, up to MAX_COST, or are walled off
+// without one
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TerrainPatch {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+    #[serde(default)]
+    pub cost: Option<f32>,
+}
+
+// Either a point, or the name of a labeled region to pick a random point in
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MissionTarget {
+    Point([f32; 2]),
+    Region(String),
+}
+
+impl MissionTarget {
+    // The point itself, or the center of the region; None if the region is unknown
+    pub fn anchor(&self, grid: &Grid) -> Option<Vector2<f32>> {
+        match self {
+            MissionTarget::Point(p) => Some(Vector2::new(p[0], p[1])),
+            MissionTarget::Region(name) => Some(grid.region(name)?.center()),
+        }
+    }
+}
+
+// A mission waiting at startup, e.g. captured from a running simulation
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PendingMission {
+    pub target: MissionTarget,
+    #[serde(default)]
+    pub target_theta: Option<f32>,
+    #[serde(default)]
+    pub return_to: Option<[f32; 2]>,
+    #[serde(default)]
+    pub group: Option<usize>,
+    // Service window, in seconds from when the mission is added
+    #[serde(default)]
+    pub window_s: Option<[f32; 2]>,
+    // Observed from a distance rather than visited
+    #[serde(default)]
+    pub camera: Option<CameraSensor>,
+    // Overrides the reward of the scenario for this mission
+    #[serde(default)]
+    pub reward: Option<RewardConfig>,
+}
+
+// Mission added over and over by the event scheduler, e.g. a pickup at the dock every 20s
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecurringMission {
+    pub every_s: f32,
+    // Time of the first one
+    #[serde(default)]
+    pub start_s: f32,
+    // How many are added in all; no limit without it
+    #[serde(default)]
+    pub count: Option<usize>,
+    pub mission: PendingMission,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub events: Vec<TimedEvent>,
+    #[serde(default)]
+    pub camera: Vec<CameraKeyframe>,
+    // Where missions bringing something back have to return; none are created without it
+    #[serde(default)]
+    pub base: Option<[f32; 2]>,
+    // Control loop frequency of individual agents, by id; the others run at CONTROL_RATE_HZ
+    #[serde(default)]
+    pub tick_rates: HashMap<usize, f32>,
+    // Path planner of individual agents, by id; the others use the planner below
+    #[serde(default)]
+    pub planners: HashMap<usize, PlannerKind>,
+    // Path planner of the agents not listed in planners; they head straight for their goal without
+    // it
+    #[serde(default)]
+    pub planner: PlannerKind,
+    #[serde(default)]
+    pub replenishment: ReplenishmentConfig,
+    // Painted in order over the default cost layout of the grid, before the obstacles
+    #[serde(default)]
+    pub terrain: Vec<TerrainPatch>,
+    // Continuous obstacles agents collide with, rasterized into the grid as uncrossable cells
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+    // Number of pedestrians walking around, which agents avoid but do not coordinate with
+    #[serde(default)]
+    pub pedestrians: usize,
+    // Pending missions gain priority as they wait, and are made urgent after waiting this long
+    #[serde(default)]
+    pub max_mission_wait_s: Option<f32>,
+    // Feature layout of the observations agents build of their surroundings
+    #[serde(default)]
+    pub observation: ObservationConfig,
+    // Named sets of agents, each with missions scoped to it; agents may belong to one group only
+    #[serde(default)]
+    pub groups: Vec<AgentGroup>,
+    // Number of transport missions a vehicle can pick up before dropping them all off
+    #[serde(default = "default_vehicle_capacity")]
+    pub vehicle_capacity: usize,
+    // Service windows outside of which missions cannot be completed
+    #[serde(default)]
+    pub time_windows: Option<TimeWindowConfig>,
+    // Starting positions of the agents; four agents are spread over the grid without them
+    #[serde(default)]
+    pub agents: Vec<[f32; 2]>,
+    // Missions pending from the start, on top of those the replenishment policy creates
+    #[serde(default)]
+    pub missions: Vec<PendingMission>,
+    // Named areas of the grid, e.g. docks or aisles
+    #[serde(default)]
+    pub labeled_regions: Vec<LabeledRegion>,
+    // Steady workloads, on top of the replenishment policy
+    #[serde(default)]
+    pub recurring: Vec<RecurringMission>,
+    // Spots agents notice when passing by, and propose to investigate
+    #[serde(default)]
+    pub anomalies: Vec<[f32; 2]>,
+    // Agents only act upon the agents and missions in view within this range
+    #[serde(default)]
+    pub sensing_range: Option<f32>,
+    // Reward of every mission, decaying until it is completed
+    #[serde(default)]
+    pub reward: Option<RewardConfig>,
+    // How agents are kept inside the world
+    #[serde(default)]
+    pub boundary: BoundaryPolicy,
+    // Number of regions along each side of the grid, each with its own mission manager
+    #[serde(default = "default_region_split")]
+    pub region_split: usize,
+    // Seed of every random generator of the run
+    #[serde(default = "default_seed")]
+    pub seed: u64,
+}
+
+fn default_vehicle_capacity() -> usize {
+    1
+}
+
+fn default_region_split() -> usize {
+    REGION_SPLIT
+}
+
+fn default_seed() -> u64 {
+    SEED as u64
+}
+
+// The same as an empty scenario file
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            events: Vec::new(),
+            camera: Vec::new(),
+            base: None,
+            tick_rates: HashMap::new(),
+            planners: HashMap::new(),
+            planner: PlannerKind::default(),
+            replenishment: ReplenishmentConfig::default(),
+            terrain: Vec::new(),
+            obstacles: Vec::new(),
+            pedestrians: 0,
+            max_mission_wait_s: None,
+            observation: ObservationConfig::default(),
+            groups: Vec::new(),
+            vehicle_capacity: default_vehicle_capacity(),
+            time_windows: None,
+            agents: Vec::new(),
+            missions: Vec::new(),
+            labeled_regions: Vec::new(),
+            recurring: Vec::new(),
+            anomalies: Vec::new(),
+            sensing_range: None,
+            reward: None,
+            boundary: BoundaryPolicy::default(),
+            region_split: default_region_split(),
+            seed: default_seed(),
+        }
+    }
+}
+
+impl Scenario {
+    // Also returns the file as read, so that its bytes can go in the config hash; files ending in
+    // .toml are read as TOML, all others as JSON
+    pub fn load(path: &Path) -> Result<(Self, String)> {
+        let source = std::fs::read_to_string(path)?;
+        let parsed = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Scenario::parse_toml(&source),
+            _ => Scenario::parse(&source),
+        };
+        let scenario = parsed.map_err(|err| match err {
+            Error::Config(message) => {
+                Error::Config(format!("invalid scenario {}: {}", path.display(), message))
+            }
+            err => err,
+        })?;
+        Ok((scenario, source))
+    }
+
+    pub fn parse(json: &str) -> Result<Self> {
+        let scenario = serde_json::from_str(json).map_err(|err| Error::Config(err.to_string()))?;
+        Scenario::checked(scenario)
+    }
+
+    pub fn parse_toml(toml: &str) -> Result<Self> {
+        let scenario = toml::from_str(toml).map_err(|err| Error::Config(err.to_string()))?;
+        Scenario::checked(scenario)
+    }
+
+    // Whatever the input, malformed scenarios are rejected here rather than panicking the run
+    fn checked(scenario: Scenario) -> Result<Self> {
+        for event in &scenario.events {
+            if !event.at.is_finite() || event.at < 0.0 {
+                return Err(Error::Config(format!(
+                    "event time must not be negative, got {}s",
+                    event.at
+                )));
+            }
+            event.action.validate(&scenario).map_err(|err| {
+                Error::Config(format!("invalid event at t={}s: {}", event.at, err))
+            })?;
+        }
+        if let Some(keyframe) = scenario
+            .camera
+            .iter()
+            .find(|k| !k.at.is_finite() || !k.zoom.is_finite() || k.zoom <= 0.0)
+        {
+            return Err(Error::Config(format!(
+                "camera keyframe at t={}s has an invalid zoom: {}",
+                keyframe.at, keyframe.zoom
+            )));
+        }
+        if let Some(base) = &scenario.base {
+            if !on_grid(base) {
+                return Err(Error::Config(format!(
+                    "base {:?} lies outside of the grid",
+                    base
+                )));
+            }
+        }
+        if let Some((id, rate)) = scenario
+            .tick_rates
+            .iter()
+            .find(|(_, &rate)| !(1.0..=RATE_LIMIT_PER_S).contains(&rate))
+        {
+            return Err(Error::Config(format!(
+                "tick rate of agent {} must be between 1 and {} Hz, got {}",
+                id, RATE_LIMIT_PER_S, rate
+            )));
+        }
+        for patch in &scenario.terrain {
+            let (min, max) = (patch.min, patch.max);
+            if !on_grid(&min) || !on_grid(&max) || min[0] > max[0] || min[1] > max[1] {
+                return Err(Error::Config(format!(
+                    "terrain from {:?} to {:?} is not a box on the grid",
+                    min, max
+                )));
+            }
+            if let Some(cost) = patch.cost {
+                if !(0.0..=MAX_COST).contains(&cost) {
+                    return Err(Error::Config(format!(
+                        "terrain cost must be between 0 and {}, got {}",
+                        MAX_COST, cost
+                    )));
+                }
+            }
+        }
+        for obstacle in &scenario.obstacles {
+            obstacle
+                .validate()
+                .map_err(|err| Error::Config(format!("invalid obstacle: {}", err)))?;
+        }
+        match scenario.replenishment {
+            ReplenishmentConfig::Arrivals { rate_per_s }
+                if !rate_per_s.is_finite() || rate_per_s <= 0.0 =>
+            {
+                return Err(Error::Config(format!(
+                    "mission arrival rate must be positive, got {}",
+                    rate_per_s
+                )));
+            }
+            ReplenishmentConfig::PendingPerAgent { ratio } if !ratio.is_finite() || ratio < 0.0 => {
+                return Err(Error::Config(format!(
+                    "pending missions per agent must not be negative, got {}",
+                    ratio
+                )));
+            }
+            _ => {}
+        }
+        if let Some(max_wait) = scenario.max_mission_wait_s {
+            if !max_wait.is_finite() || max_wait <= 0.0 {
+                return Err(Error::Config(format!(
+                    "maximum mission wait must be positive, got {}s",
+                    max_wait
+                )));
+            }
+        }
+        if !(1..=MAX_REGION_SPLIT).contains(&scenario.region_split) {
+            return Err(Error::Config(format!(
+                "region split must be between 1 and {}, got {}",
+                MAX_REGION_SPLIT, scenario.region_split
+            )));
+        }
+        if scenario.vehicle_capacity == 0 {
+            return Err(Error::Config(
+                "vehicle capacity must be at least 1".to_owned(),
+            ));
+        }
+        if let Some(windows) = &scenario.time_windows {
+            windows
+                .validate()
+                .map_err(|err| Error::Config(format!("invalid time windows: {}", err)))?;
+        }
+        if let Some(reward) = &scenario.reward {
+            reward
+                .validate()
+                .map_err(|err| Error::Config(format!("invalid reward: {}", err)))?;
+        }
+        if let Some(agent) = scenario.agents.iter().find(|p| !on_grid(p)) {
+            return Err(Error::Config(format!(
+                "agent at {:?} lies outside of the grid",
+                agent
+            )));
+        }
+        scenario.validate_labeled_regions()?;
+        for recurring in &scenario.recurring {
+            let every_s = recurring.every_s;
+            if !every_s.is_finite() || every_s <= 0.0 {
+                return Err(Error::Config(format!(
+                    "recurring missions need a positive period, got {}s",
+                    every_s
+                )));
+            }
+            if !recurring.start_s.is_finite() || recurring.start_s < 0.0 {
+                return Err(Error::Config(format!(
+                    "recurring missions cannot start at {}s",
+                    recurring.start_s
+                )));
+            }
+            recurring
+                .mission
+                .validate(scenario.groups.len(), &scenario.labeled_regions)
+                .map_err(|err| Error::Config(format!("invalid recurring mission: {}", err)))?;
+        }
+        for mission in &scenario.missions {
+            mission
+                .validate(scenario.groups.len(), &scenario.labeled_regions)
+                .map_err(|err| Error::Config(format!("invalid mission: {}", err)))?;
+        }
+        if let Some(anomaly) = scenario.anomalies.iter().find(|p| !on_grid(p)) {
+            return Err(Error::Config(format!(
+                "anomaly at {:?} lies outside of the grid",
+                anomaly
+            )));
+        }
+        if let Some(range) = scenario.sensing_range {
+            if !range.is_finite() || range <= 0.0 {
+                return Err(Error::Config(format!(
+                    "sensing range must be positive, got {}",
+                    range
+                )));
+            }
+        }
+        scenario.validate_groups()?;
+        Ok(scenario)
+    }
+
+    // Settings the hub only honours in centralized mode, by name in the scenario file
+    pub fn centralized_only(&self) -> Vec<&'static str> {
+        let windows = self.time_windows.is_some()
+            || self.missions.iter().any(|m| m.window_s.is_some())
+            || self.recurring.iter().any(|r| r.mission.window_s.is_some());
+        [
+            ("events", !self.events.is_empty()),
+            ("recurring", !self.recurring.is_empty()),
+            ("max_mission_wait_s", self.max_mission_wait_s.is_some()),
+            ("pedestrians", self.pedestrians > 0),
+            ("time_windows", windows),
+            ("anomalies", !self.anomalies.is_empty()),
+            ("vehicle_capacity", self.vehicle_capacity > 1),
+            ("groups", !self.groups.is_empty()),
+        ]
+        .iter()
+        .filter(|(_, used)| *used)
+        .map(|(name, _)| *name)
+        .collect()
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    fn validate_labeled_regions(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        for region in &self.labeled_regions {
+            if !names.insert(region.name.as_str()) {
+                return Err(Error::Config(format!(
+                    "region {} is labeled twice",
+                    region.name
+                )));
+            }
+            let (min, max) = (region.min, region.max);
+            if !on_grid(&min) || !on_grid(&max) || min[0] > max[0] || min[1] > max[1] {
+                return Err(Error::Config(format!(
+                    "region {} from {:?} to {:?} is not a box on the grid",
+                    region.name, min, max
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_groups(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        let mut members = HashMap::new();
+        for group in &self.groups {
+            if !names.insert(group.name.as_str()) {
+                return Err(Error::Config(format!(
+                    "agent group {} is defined twice",
+                    group.name
+                )));
+            }
+            if !(0.0..=1.0).contains(&group.mission_share) {
+                return Err(Error::Config(format!(
+                    "mission share of group {} must be between 0 and 1, got {}",
+                    group.name, group.mission_share
+                )));
+            }
+            for agent in &group.agents {
+                if let Some(other) = members.insert(agent, &group.name) {
+                    return Err(Error::Config(format!(
+                        "agent {} belongs to both groups {} and {}",
+                        agent, other, group.name
+                    )));
+                }
+            }
+        }
+        let total: f64 = self.groups.iter().map(|g| g.mission_share).sum();
+        if total > 1.0 {
+            return Err(Error::Config(format!(
+                "mission shares of the agent groups add up to more than 1: {}",
+                total
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl PendingMission {
+    // Plain visit of a point, with no heading, return, group or window
+    pub fn at(p: &Vector2<f32>) -> Self {
+        PendingMission {
+            target: MissionTarget::Point([p.x, p.y]),
+            target_theta: None,
+            return_to: None,
+            group: None,
+            window_s: None,
+            camera: None,
+            reward: None,
+        }
+    }
+
+    fn validate(
+        &self,
+        n_groups: usize,
+        regions: &[LabeledRegion],
+    ) -> std::result::Result<(), String> {
+        let on_target_grid = match &self.target {
+            MissionTarget::Point(p) => on_grid(p),
+            MissionTarget::Region(name) => {
+                if !regions.iter().any(|r| &r.name == name) {
+                    return Err(format!("mission to unknown region {}", name));
+                }
+                true
+            }
+        };
+        if !on_target_grid || self.return_to.is_some_and(|p| !on_grid(&p)) {
+            return Err(format!("mission to {:?} leaves the grid", self.target));
+        }
+        if self.target_theta.is_some_and(|theta| !theta.is_finite()) {
+            return Err(format!(
+                "mission to {:?} has an invalid heading",
+                self.target
+            ));
+        }
+        if let Some(camera) = &self.camera {
+            camera
+                .validate()
+                .map_err(|err| format!("mission to {:?}: {}", self.target, err))?;
+        }
+        if let Some(reward) = &self.reward {
+            reward
+                .validate()
+                .map_err(|err| format!("mission to {:?}: {}", self.target, err))?;
+        }
+        if self.group.is_some_and(|group| group >= n_groups) {
+            return Err(format!(
+                "mission to {:?} is scoped to group {:?}, but there are {} groups",
+                self.target, self.group, n_groups
+            ));
+        }
+        if let Some([earliest, latest]) = self.window_s {
+            if !earliest.is_finite() || !latest.is_finite() || earliest < 0.0 || latest < earliest {
+                return Err(format!(
+                    "mission to {:?} has an invalid window: {}s to {}s",
+                    self.target, earliest, latest
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Camera center and zoom at a given time, eased between keyframes and held before the first and
+// after the last one
+pub struct CameraTour {
+    keyframes: Vec<CameraKeyframe>,
+}
+
+impl CameraTour {
+    pub fn new(mut keyframes: Vec<CameraKeyframe>) -> Option<Self> {
+        if keyframes.is_empty() {
+            return None;
+        }
+        keyframes.sort_by(|a, b| a.at.partial_cmp(&b.at).unwrap_or(std::cmp::Ordering::Equal));
+        Some(CameraTour { keyframes })
+    }
+
+    pub fn at(&self, time: f32) -> (Vector2<f32>, f32) {
+        let next = self.keyframes.iter().position(|k| k.at > time);
+        let (from, to) = match next {
+            Some(0) => (&self.keyframes[0], &self.keyframes[0]),
+            Some(i) => (&self.keyframes[i - 1], &self.keyframes[i]),
+            None => {
+                let last = &self.keyframes[self.keyframes.len() - 1];
+                (last, last)
+            }
+        };
+        let span = to.at - from.at;
+        let t = if span > 0.0 {
+            ((time - from.at) / span).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let eased = t * t * (3.0 - 2.0 * t);
+        let center = Vector2::from(from.center).lerp(&Vector2::from(to.center), eased);
+        let zoom = from.zoom * (to.zoom / from.zoom).powf(eased);
+        (center, zoom)
+    }
+}
+
+// Hands out the scenario events once their time, counted from the start of the run, has come
+pub struct EventScheduler {
+    start: Instant,
+    pending: VecDeque<TimedEvent>,
+    // With the time of their next occurrence
+    recurring: Vec<(RecurringMission, f32)>,
+    // Zones stay closed for the rest of the run, so they are kept for snapshots
+    closed_zones: Vec<ScenarioAction>,
+}
+
+impl EventScheduler {
+    pub fn new(scenario: Scenario) -> Self {
+        let mut events = scenario.events;
+        events.sort_by(|a, b| a.at.partial_cmp(&b.at).unwrap_or(std::cmp::Ordering::Equal));
+        EventScheduler {
+            start: Instant::now(),
+            pending: events.into(),
+            recurring: scenario
+                .recurring
+                .into_iter()
+                .map(|r| {
+                    let start_s = r.start_s;
+                    (r, start_s)
+                })
+                .collect(),
+            closed_zones: Vec::new(),
+        }
+    }
+
+    pub fn restart(&mut self) {
+        self.start = Instant::now();
+    }
+
+    pub fn due(&mut self) -> Vec<TimedEvent> {
+        let now = self.start.elapsed().as_secs_f32();
+        let mut out = Vec::new();
+        while self.pending.front().is_some_and(|event| event.at <= now) {
+            out.extend(self.pending.pop_front());
+        }
+        for (recurring, next) in &mut self.recurring {
+            while *next <= now && recurring.count != Some(0) {
+                out.push(TimedEvent {
+                    at: *next,
+                    action: ScenarioAction::AddMission {
+                        mission: recurring.mission.clone(),
+                    },
+                });
+                *next += recurring.every_s;
+                if let Some(count) = &mut recurring.count {
+                    *count -= 1;
+                }
+            }
+        }
+        out.sort_by(|a, b| a.at.partial_cmp(&b.at).unwrap_or(std::cmp::Ordering::Equal));
+        self.closed_zones.extend(
+            out.iter()
+                .filter(|event| matches!(event.action, ScenarioAction::CloseZone { .. }))
+                .map(|event| event.action.clone()),
+        );
+        out
+    }
+
+    // The events of a run restarting from now: zones closed so far right away, then the pending
+    // events at the same delay as they would have had
+    pub fn remaining(&self) -> Vec<TimedEvent> {
+        let now = self.start.elapsed().as_secs_f32();
+        self.closed_zones
+            .iter()
+            .map(|action| TimedEvent {
+                at: 0.0,
+                action: action.clone(),
+            })
+            .chain(self.pending.iter().map(|event| TimedEvent {
+                at: (event.at - now).max(0.0),
+                action: event.action.clone(),
+            }))
+            .collect()
+    }
+
+    // The recurring missions still to come, starting from now
+    pub fn remaining_recurring(&self) -> Vec<RecurringMission> {
+        let now = self.start.elapsed().as_secs_f32();
+        self.recurring
+            .iter()
+            .filter(|(recurring, _)| recurring.count != Some(0))
+            .map(|(recurring, next)| RecurringMission {
+                start_s: (next - now).max(0.0),
+                ..recurring.clone()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejects(json: &str) -> bool {
+        matches!(Scenario::parse(json), Err(Error::Config(_)))
+    }
+
+    #[test]
+    fn malformed_json_is_rejected() {
+        for json in &[
+            "",
+            "null",
+            "[1]",
+            "{",
+            "{\"agents\": [[0, 0]],}",
+            "{\"agents\": \"everywhere\"}",
+            "{\"agents\": [[0]]}",
+            "{\"events\": [{\"at\": 1, \"action\": {\"teleport\": {}}}]}",
+            "\u{0}\u{ff}",
+        ] {
+            assert!(rejects(json), "{:?}", json);
+        }
+    }
+
+    // JSON has no NaN, but overflowing an f32 gives an infinite coordinate; TOML has both
+    #[test]
+    fn coordinates_must_be_finite() {
+        assert!(rejects("{\"agents\": [[1e39, 0]]}"));
+        assert!(rejects("{\"agents\": [[NaN, 0]]}"));
+        assert!(rejects("{\"base\": [0, -1e39]}"));
+        assert!(rejects("{\"anomalies\": [[1e39, 1e39]]}"));
+        assert!(rejects(
+            "{\"events\": [{\"at\": 1, \"action\": {\"spawn_agents\": {\"count\": 1, \"position\": [1e39, 0]}}}]}"
+        ));
+        for toml in &[
+            "agents = [[nan, 0]]",
+            "agents = [[0, inf]]",
+            "base = [-inf, 0]",
+            "sensing_range = nan",
+            "[[terrain]]\nmin = [0, 0]\nmax = [nan, 10]",
+            "[[terrain]]\nmin = [0, 0]\nmax = [10, 10]\ncost = nan",
+        ] {
+            assert!(
+                matches!(Scenario::parse_toml(toml), Err(Error::Config(_))),
+                "{:?}",
+                toml
+            );
+        }
+    }
+
+    #[test]
+    fn integers_out_of_range_are_rejected() {
+        for json in &[
+            "{\"region_split\": 0}",
+            "{\"region_split\": -1}",
+            "{\"region_split\": 1e3}",
+            "{\"seed\": -1}",
+            "{\"seed\": 18446744073709551616}",
+            "{\"seed\": 0.5}",
+            "{\"vehicle_capacity\": 0}",
+            "{\"vehicle_capacity\": -2}",
+            "{\"pedestrians\": 99999999999999999999}",
+        ] {
+            assert!(rejects(json), "{:?}", json);
+        }
+        let largest = format!("{{\"region_split\": {}}}", MAX_REGION_SPLIT + 1);
+        assert!(rejects(&largest));
+        assert!(Scenario::parse(&format!("{{\"region_split\": {}}}", MAX_REGION_SPLIT)).is_ok());
+        assert!(Scenario::parse("{\"seed\": 18446744073709551615}").is_ok());
+    }
+
+    #[test]
+    fn truncated_scenarios_are_rejected() {
+        for source in &[
+            include_str!("../scenarios/warehouse.json"),
+            include_str!("../scenarios/stress.json"),
+            include_str!("../scenarios/obstacles.json"),
+        ] {
+            let source = source.trim_end();
+            assert!(Scenario::parse(source).is_ok());
+            for (end, _) in source.char_indices() {
+                assert!(rejects(&source[..end]), "{:?}", &source[..end]);
+            }
+        }
+    }
+
+    #[test]
+    fn terrain_patches_keep_their_order() {
+        let scenario = Scenario::parse(
+            r#"{"terrain": [
+                {"min": [-10, -10], "max": [10, 10], "cost": 4},
+                {"min": [0, 0], "max": [20, 20]}
+            ]}"#,
+        )
+        .unwrap();
+        let costs: Vec<_> = scenario.terrain.iter().map(|patch| patch.cost).collect();
+        assert_eq!(costs, vec![Some(4.0), None]);
+    }
+
+    #[test]
+    fn terrain_patches_must_lie_on_the_grid() {
+        let edge = GRID_HALF_SIZE;
+        let patch = |min: [f32; 2], max: [f32; 2]| {
+            format!(
+                r#"{{"terrain": [{{"min": [{}, {}], "max": [{}, {}], "cost": 2}}]}}"#,
+                min[0], min[1], max[0], max[1]
+            )
+        };
+        assert!(Scenario::parse(&patch([-edge, -edge], [edge, edge])).is_ok());
+        assert!(rejects(&patch([0.0, 0.0], [edge + 1.0, 10.0])));
+        assert!(rejects(&patch([-edge - 1.0, 0.0], [10.0, 10.0])));
+        assert!(rejects(&patch([10.0, 10.0], [0.0, 0.0])));
+    }
+
+    #[test]
+    fn terrain_costs_must_not_exceed_the_maximum() {
+        let patch = |cost: f32| {
+            format!(
+                r#"{{"terrain": [{{"min": [0, 0], "max": [10, 10], "cost": {}}}]}}"#,
+                cost
+            )
+        };
+        assert!(Scenario::parse(&patch(MAX_COST)).is_ok());
+        assert!(Scenario::parse(&patch(0.0)).is_ok());
+        assert!(rejects(&patch(MAX_COST + 1.0)));
+        assert!(rejects(&patch(-1.0)));
+    }
+
+    #[test]
+    fn toml_scenarios_are_checked_like_json_ones() {
+        let scenario = Scenario::parse_toml(
+            r#"
+            agents = [[0, 0], [10, -10]]
+            seed = 7
+
+            [[terrain]]
+            min = [-10, -10]
+            max = [10, 10]
+            cost = 4
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scenario.agents, vec![[0.0, 0.0], [10.0, -10.0]]);
+        assert_eq!(scenario.seed, 7);
+        assert_eq!(scenario.terrain[0].cost, Some(4.0));
+        assert!(matches!(
+            Scenario::parse_toml("region_split = 0"),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            Scenario::parse_toml("agents = [[0, 0"),
+            Err(Error::Config(_))
+        ));
+    }
+}
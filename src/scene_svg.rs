@@ -0,0 +1,154 @@
+use crate::consts::*;
+use crate::obstacles::Obstacle;
+use nalgebra::{Point3, Vector2};
+use std::fmt::Write as _;
+use std::path::Path;
+
+pub struct SceneAgent {
+    pub id: usize,
+    pub p: Vector2<f32>,
+    pub theta: f32,
+    pub radius: f32,
+    pub goal: Option<Vector2<f32>>,
+    pub trail: Vec<Vector2<f32>>,
+}
+
+// Everything currently drawn by the renderer, in world coordinates
+pub struct Scene<'a> {
+    pub cells: Vec<(Vector2<f32>, Point3<f32>)>,
+    pub obstacles: &'a [Obstacle],
+    pub agents: Vec<SceneAgent>,
+    pub pedestrians: Vec<Vector2<f32>>,
+}
+
+pub fn write_scene(scene: &Scene, path: &Path) -> std::io::Result<()> {
+    std::fs::write(path, render_scene(scene))
+}
+
+fn color(c: &Point3<f32>) -> String {
+    format!(
+        "rgb({},{},{})",
+        (c.x * 255.0) as u8,
+        (c.y * 255.0) as u8,
+        (c.z * 255.0) as u8
+    )
+}
+
+// SVG has y pointing down, the world has it pointing up
+fn to_svg(p: &Vector2<f32>) -> (f32, f32) {
+    (p.x + GRID_HALF_SIZE, GRID_HALF_SIZE - p.y)
+}
+
+fn polyline(points: &[Vector2<f32>]) -> String {
+    points
+        .iter()
+        .map(|p| {
+            let (x, y) = to_svg(p);
+            format!("{},{}", x, y)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn render_scene(scene: &Scene) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="{} {} {size} {size}" font-family="sans-serif" font-size="8">"#,
+        -CELL_SIZE / 2.0,
+        CELL_SIZE / 2.0,
+        size = GRID_SIZE
+    );
+
+    let _ = writeln!(svg, r#"<g id="grid" shape-rendering="crispEdges">"#);
+    for (center, c) in &scene.cells {
+        let (x, y) = to_svg(center);
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{size}" height="{size}" fill="{}"/>"#,
+            x - CELL_SIZE / 2.0,
+            y - CELL_SIZE / 2.0,
+            color(c),
+            size = CELL_SIZE
+        );
+    }
+    svg.push_str("</g>\n");
+
+    let _ = writeln!(svg, r#"<g id="obstacles" fill="none" stroke="white">"#);
+    for obstacle in scene.obstacles {
+        let _ = writeln!(
+            svg,
+            r#"<polygon points="{}"/>"#,
+            polyline(&obstacle.outline())
+        );
+    }
+    svg.push_str("</g>\n");
+
+    let _ = writeln!(
+        svg,
+        r#"<g id="trails" fill="none" stroke="gray" stroke-width="{}">"#,
+        LINE_WIDTH
+    );
+    for agent in &scene.agents {
+        if agent.trail.len() > 1 {
+            let _ = writeln!(svg, r#"<polyline points="{}"/>"#, polyline(&agent.trail));
+        }
+    }
+    svg.push_str("</g>\n");
+
+    let _ = writeln!(
+        svg,
+        r#"<g id="targets" stroke="rgb(25,25,25)" stroke-width="{}">"#,
+        LINE_WIDTH
+    );
+    for agent in &scene.agents {
+        if let Some(goal) = &agent.goal {
+            let (x, y) = to_svg(&agent.p);
+            let (gx, gy) = to_svg(goal);
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{}" y1="{}" x2="{gx}" y2="{gy}"/><rect x="{}" y="{}" width="5" height="5" fill="rgb(25,25,25)"/>"#,
+                x,
+                y,
+                gx - 2.5,
+                gy - 2.5,
+                gx = gx,
+                gy = gy
+            );
+        }
+    }
+    svg.push_str("</g>\n");
+
+    let _ = writeln!(svg, r#"<g id="pedestrians" fill="rgb(229,229,51)">"#);
+    for p in &scene.pedestrians {
+        let (x, y) = to_svg(p);
+        let _ = writeln!(
+            svg,
+            r#"<circle cx="{}" cy="{}" r="{}"/>"#,
+            x, y, PEDESTRIAN_RADIUS
+        );
+    }
+    svg.push_str("</g>\n");
+
+    let _ = writeln!(svg, r#"<g id="agents">"#);
+    for agent in &scene.agents {
+        let (x, y) = to_svg(&agent.p);
+        let heading = agent.p + agent.radius * Vector2::new(agent.theta.cos(), agent.theta.sin());
+        let (hx, hy) = to_svg(&heading);
+        let _ = writeln!(
+            svg,
+            r#"<g><title>agent {id}</title><circle cx="{x}" cy="{y}" r="{}" fill="white" stroke="black"/><line x1="{x}" y1="{y}" x2="{}" y2="{}" stroke="rgb(127,127,255)" stroke-width="{}"/><text x="{x}" y="{y}" fill="red">{id}</text></g>"#,
+            agent.radius,
+            hx,
+            hy,
+            2.0 * LINE_WIDTH,
+            id = agent.id,
+            x = x,
+            y = y
+        );
+    }
+    svg.push_str("</g>\n");
+
+    svg.push_str("</svg>\n");
+    svg
+}
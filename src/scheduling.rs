@@ -0,0 +1,67 @@
+use crate::agent::AgentMessage;
+use crate::consts::{
+    CONFLICT_MARGIN, MIN_CROSSING_SPEED, PLAN_SAMPLES, PLAN_SAMPLE_PERIOD_S, SPEED_SCALES,
+};
+use nalgebra::Vector2;
+use std::collections::HashMap;
+
+// Where another agent is expected t seconds after its message: along the plan it shared if any,
+// at constant velocity otherwise
+fn predicted_position(agent: &AgentMessage, t: f32) -> Vector2<f32> {
+    if agent.plan.is_empty() {
+        return agent.kinematics.p + t * agent.kinematics.v;
+    }
+    let index = t / PLAN_SAMPLE_PERIOD_S;
+    let i = index.floor() as usize;
+    let from = match i {
+        0 => agent.kinematics.p,
+        i => match agent.plan.get(i - 1) {
+            Some(p) => *p,
+            None => return *agent.plan.last().unwrap(),
+        },
+    };
+    let to = agent.plan.get(i).copied().unwrap_or(from);
+    from + (to - from) * index.fract()
+}
+
+// Largest fraction of its planned speed at which the agent keeps clear of every agent it yields
+// to over the planning horizon. Agents yield to moving agents with lower ids, so that two agents
+// about to cross do not both slow down; waiting on an agent standing in the way would never end.
+// When no fraction is clear, the one keeping the largest separation wins.
+pub fn speed_scale(
+    own: &AgentMessage,
+    future: impl Fn(f32) -> Vector2<f32>,
+    agents: &HashMap<usize, AgentMessage>,
+) -> f32 {
+    let priority: Vec<&AgentMessage> = agents
+        .values()
+        .filter(|a| a.id < own.id && a.kinematics.v.norm() > MIN_CROSSING_SPEED)
+        .collect();
+    if priority.is_empty() {
+        return 1.0;
+    }
+    let separation = |scale: f32| {
+        (1..=PLAN_SAMPLES)
+            .map(|k| k as f32 * PLAN_SAMPLE_PERIOD_S)
+            .flat_map(|t| {
+                let p = future(scale * t);
+                priority.iter().map(move |other| {
+                    (p - predicted_position(other, t)).norm()
+                        - own.kinematics.radius
+                        - other.kinematics.radius
+                })
+            })
+            .fold(f32::INFINITY, f32::min)
+    };
+    let mut best = (1.0, f32::NEG_INFINITY);
+    for &scale in &SPEED_SCALES {
+        let clearance = separation(scale);
+        if clearance >= CONFLICT_MARGIN {
+            return scale;
+        }
+        if clearance > best.1 {
+            best = (scale, clearance);
+        }
+    }
+    best.0
+}
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+// What the sequence number of a message tells, compared with the last one from the same sender
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arrival {
+    InOrder,
+    // This many messages were sent in between and never arrived, or are still on their way
+    AfterGap(u64),
+    // Duplicate of, or older than, a message already received
+    Late,
+}
+
+// Sequence numbers heard from every sender on a stream, to tell how many messages were lost. A
+// stream starts at the first message heard from the sender, whatever its number.
+#[derive(Default)]
+pub struct SequenceTracker {
+    last: HashMap<usize, u64>,
+    received: u64,
+    lost: u64,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        SequenceTracker::default()
+    }
+
+    pub fn observe(&mut self, sender: usize, seq: u64) -> Arrival {
+        let last = match self.last.get(&sender) {
+            Some(&last) if seq <= last => return Arrival::Late,
+            Some(&last) => last,
+            None => seq - 1,
+        };
+        self.last.insert(sender, seq);
+        self.received += 1;
+        let gap = seq - last - 1;
+        self.lost += gap;
+        if gap == 0 {
+            Arrival::InOrder
+        } else {
+            Arrival::AfterGap(gap)
+        }
+    }
+
+    // Share of the messages sent since the start of their stream that never arrived
+    pub fn loss_ratio(&self) -> f64 {
+        let sent = self.received + self.lost;
+        if sent == 0 {
+            0.0
+        } else {
+            self.lost as f64 / sent as f64
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use crate::consts::*;
+use crate::events::{EventKind, EventLog};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// Layout documented in summary.schema.json; bump SUMMARY_SCHEMA_VERSION on incompatible changes
+pub const SUMMARY_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+pub struct RunSummary {
+    pub schema_version: u32,
+    pub seed: u128,
+    pub config_hash: String,
+    pub mode: String,
+    pub strategy: String,
+    pub strategy_switches: Vec<StrategySwitch>,
+    pub duration_s: f32,
+    pub agents: Vec<AgentSummary>,
+    pub missions: Vec<MissionSummary>,
+    pub totals: Totals,
+}
+
+#[derive(Serialize)]
+pub struct StrategySwitch {
+    pub time_s: f32,
+    pub strategy: String,
+}
+
+#[derive(Default, Serialize)]
+pub struct AgentSummary {
+    pub id: usize,
+    pub missions_completed: usize,
+    pub missions_abandoned: usize,
+    pub distance: f32,
+}
+
+#[derive(Default, Serialize)]
+pub struct MissionSummary {
+    pub id: usize,
+    pub created_s: Option<f32>,
+    pub assignments: Vec<Assignment>,
+    pub completed_s: Option<f32>,
+    pub completed_by: Option<usize>,
+    pub window_s: Option<[f32; 2]>,
+    pub expired_s: Option<f32>,
+    pub reward: Option<f32>,
+}
+
+#[derive(Serialize)]
+pub struct Assignment {
+    pub agent: usize,
+    pub start_s: f32,
+    pub end_s: Option<f32>,
+    pub outcome: Outcome,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Pending,
+    Abandoned,
+    Completed,
+    Expired,
+}
+
+#[derive(Serialize)]
+pub struct Totals {
+    pub missions_created: usize,
+    pub missions_completed: usize,
+    pub mean_completion_time_s: Option<f32>,
+    pub missions_expired: usize,
+    pub reward: Option<f32>,
+}
+
+// FNV-1a, so that the hash is stable across toolchains and platforms
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// Everything a run was configured with, beyond the compiled in constants
+pub struct ConfigDigest<'a> {
+    pub mode: &'a str,
+    pub seed: u128,
+    pub n_agents: usize,
+    pub byzantine_agents: usize,
+    pub allocation: &'a str,
+    pub idle: &'a str,
+    // Command line and scenario file bytes
+    pub source: &'a str,
+}
+
+pub fn config_hash(digest: &ConfigDigest) -> String {
+    let config = format!(
+        "{};{};{};{};{};{};{};{};{};{};{};{};{};{}",
+        digest.seed,
+        digest.mode,
+        digest.n_agents,
+        digest.byzantine_agents,
+        digest.allocation,
+        digest.idle,
+        digest.source,
+        GRID_SPLIT,
+        CELL_SIZE,
+        MAX_SPEED.0,
+        SPEED_SPREAD,
+        REGION_SPLIT,
+        RATE_LIMIT_PER_S,
+        GOSSIP_FANOUT
+    );
+    format!("{:016x}", fnv1a(config.as_bytes()))
+}
+
+impl RunSummary {
+    pub fn from_log(
+        log: &EventLog,
+        mode: &str,
+        strategy: &str,
+        seed: u128,
+        config_hash: String,
+    ) -> Self {
+        let mut agents: BTreeMap<usize, AgentSummary> = BTreeMap::new();
+        let mut missions: BTreeMap<usize, MissionSummary> = BTreeMap::new();
+        for event in log.events() {
+            let mission = missions
+                .entry(event.mission)
+                .or_insert_with(|| MissionSummary {
+                    id: event.mission,
+                    ..Default::default()
+                });
+            match event.kind {
+                EventKind::Created => mission.created_s = Some(event.time),
+                EventKind::Expired => {
+                    mission.expired_s = Some(event.time);
+                    for assignment in &mut mission.assignments {
+                        if assignment.outcome == Outcome::Pending {
+                            assignment.end_s = Some(event.time);
+                            assignment.outcome = Outcome::Expired;
+                        }
+                    }
+                }
+                EventKind::Assigned { agent } => mission.assignments.push(Assignment {
+                    agent,
+                    start_s: event.time,
+                    end_s: None,
+                    outcome: Outcome::Pending,
+                }),
+                EventKind::Abandoned { agent } | EventKind::Completed { agent } => {
+                    let completed = matches!(event.kind, EventKind::Completed { .. });
+                    if completed {
+                        mission.completed_s = Some(event.time);
+                        mission.completed_by = Some(agent);
+                    }
+                    if let Some(assignment) = mission
+                        .assignments
+                        .iter_mut()
+                        .rev()
+                        .find(|a| a.agent == agent && a.outcome == Outcome::Pending)
+                    {
+                        assignment.end_s = Some(event.time);
+                        assignment.outcome = if completed {
+                            Outcome::Completed
+                        } else {
+                            Outcome::Abandoned
+                        };
+                    }
+
+                    let stats = agents.entry(agent).or_insert_with(|| AgentSummary {
+                        id: agent,
+                        ..Default::default()
+                    });
+                    if completed {
+                        stats.missions_completed += 1;
+                    } else {
+                        stats.missions_abandoned += 1;
+                    }
+                }
+            }
+        }
+        for (id, &(earliest, latest)) in log.windows() {
+            if let Some(mission) = missions.get_mut(id) {
+                mission.window_s = Some([earliest, latest]);
+            }
+        }
+        for (id, &reward) in log.rewards() {
+            if let Some(mission) = missions.get_mut(id) {
+                mission.reward = Some(reward);
+            }
+        }
+        for (&id, &distance) in log.distances() {
+            agents
+                .entry(id)
+                .or_insert_with(|| AgentSummary {
+                    id,
+                    ..Default::default()
+                })
+                .distance = distance;
+        }
+
+        let completion_times: Vec<f32> = missions
+            .values()
+            .filter_map(|m| Some(m.completed_s? - m.created_s?))
+            .collect();
+        let totals = Totals {
+            missions_created: missions.values().filter(|m| m.created_s.is_some()).count(),
+            missions_completed: missions
+                .values()
+                .filter(|m| m.completed_s.is_some())
+                .count(),
+            mean_completion_time_s: if completion_times.is_empty() {
+                None
+            } else {
+                Some(completion_times.iter().sum::<f32>() / completion_times.len() as f32)
+            },
+            missions_expired: missions.values().filter(|m| m.expired_s.is_some()).count(),
+            reward: if log.rewards().is_empty() {
+                None
+            } else {
+                Some(log.rewards().values().sum())
+            },
+        };
+
+        RunSummary {
+            schema_version: SUMMARY_SCHEMA_VERSION,
+            seed,
+            config_hash,
+            mode: mode.to_owned(),
+            strategy: strategy.to_owned(),
+            strategy_switches: log
+                .strategy_switches()
+                .iter()
+                .map(|&(time_s, strategy)| StrategySwitch {
+                    time_s,
+                    strategy: strategy.to_owned(),
+                })
+                .collect(),
+            duration_s: log.elapsed(),
+            agents: agents.into_values().collect(),
+            missions: missions.into_values().collect(),
+            totals,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
@@ -1,33 +1,455 @@
-use crate::agent::{Agent, AgentMessage, Kinematics, Message};
+use crate::agent::{
+    Agent, AgentMessage, AllocationStrategy, BoundaryPolicy, Grid, Handoff, IdlePolicy, Kinematics,
+    Message, Misbehavior, MissionProposal, Personality,
+};
+use crate::claims::ClaimArbiter;
+use crate::congestion::Congestion;
+use crate::consts::{
+    AGENT_RADIUS, CONTROL_RATE_HZ, DISTANCE_TO_TARGET, GANTT_PATH, HUB_LOOP_DEADLINE_MS,
+    INVARIANT_DUMP_PATH, MAINTENANCE_WEAR_THRESHOLD, MAX_PEDESTRIAN_STEP_S,
+    METRICS_REPORT_PERIOD_S, RATE_LIMIT_BURST, RATE_LIMIT_PER_S, REGION_SPLIT,
+    SCENARIO_SNAPSHOT_PATH, SEED, SPAWN_SPACING, SUMMARY_PATH, TRACE_DUMP_PATH,
+};
+use crate::crash::CrashContext;
+use crate::dataset::DatasetRecorder;
+use crate::demand::DemandMap;
+use crate::error::Error;
+use crate::events::EventLog;
+use crate::gantt::write_gantt;
+use crate::gossip::{GossipNode, GossipView, Peer};
+use crate::groups::Groups;
+use crate::integrity::IntegrityMonitor;
+use crate::invariants;
+use crate::metrics::Metrics;
 use crate::missions::*;
+use crate::observation::ObservationConfig;
+use crate::pedestrians::{Crowd, Pedestrians};
+use crate::planning::{Planner, PlannerKind};
+use crate::realtime::{DeadlineMonitor, LoadShedding};
+use crate::regions::Coordinator;
+use crate::render_sink::{RenderFrame, RenderSink};
+use crate::scenario::{
+    EventScheduler, MissionTarget, PendingMission, Scenario, ScenarioAction, TimedEvent,
+};
+use crate::sequence::{Arrival, SequenceTracker};
+use crate::summary::{config_hash, ConfigDigest, RunSummary};
+use crate::units::Seconds;
 use log::*;
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::time::Duration;
+use nalgebra::Vector2;
+use rand_pcg::Pcg64;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub enum CoordinationMode {
+    Centralized,
+    Gossip,
+}
+
+impl CoordinationMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CoordinationMode::Centralized => "centralized",
+            CoordinationMode::Gossip => "gossip",
+        }
+    }
+}
+
+// Requests coming from a human operator, e.g. through the renderer
+pub enum OperatorCommand {
+    UrgentNear(Vector2<f32>),
+    DumpTrace,
+    CycleAllocation,
+    SaveScenario,
+}
+
+// The simulation tick, advanced by the system manager at CONTROL_RATE_HZ and read by everyone
+// stamping messages. Consumers drop messages older than what they already have.
+#[derive(Clone, Default)]
+pub struct SimClock {
+    tick: Arc<AtomicU64>,
+}
+
+impl SimClock {
+    pub fn now(&self) -> u64 {
+        self.tick.load(Ordering::Relaxed)
+    }
+
+    fn advance(&self, since_start: Duration) {
+        let tick = (since_start.as_secs_f32() * CONTROL_RATE_HZ) as u64;
+        self.tick.fetch_max(tick, Ordering::Relaxed);
+    }
+}
 
 pub struct SystemManager {
+    mode: CoordinationMode,
+    grid: Arc<Grid>,
     connection_manager: ConnectionManager,
-    mission_manager: MissionManager,
-    rendered_tx: Sender<AgentMessage>,
+    gossip_nodes: Vec<GossipNode>,
+    coordinator: Coordinator,
+    groups: Groups,
+    claims: ClaimArbiter,
+    integrity_monitor: IntegrityMonitor,
+    metrics: Metrics,
+    event_log: EventLog,
+    shutdown: Arc<AtomicBool>,
+    write_reports: bool,
+    render_sink: Arc<dyn RenderSink>,
+    agent_states: HashMap<usize, AgentMessage>,
+    operator_tx: Sender<OperatorCommand>,
+    operator_rx: Receiver<OperatorCommand>,
+    scheduler: EventScheduler,
+    scenario: Scenario,
     id_counter: usize,
+    byzantine_agents: usize,
+    allocation: AllocationStrategy,
+    initial_allocation: AllocationStrategy,
+    keep_connected: bool,
+    safety_filter: bool,
+    load_shedding: Option<LoadShedding>,
+    idle: IdlePolicy,
+    observation: ObservationConfig,
+    dataset: Option<DatasetRecorder>,
+    check_invariants: bool,
+    max_mission_wait_s: Option<f32>,
+    vehicle_capacity: usize,
+    time_windows: bool,
+    // Drop-offs and missions delivered by them, per agent
+    deliveries: HashMap<usize, (usize, usize)>,
+    crash: CrashContext,
+    crowd: Crowd,
+    pedestrians: Pedestrians,
+    congestion: Option<Congestion>,
+    anomalies: Vec<Vector2<f32>>,
+    sensing_range: Option<f32>,
+    boundary: BoundaryPolicy,
+    // Targets of the missions agents proposed, so that each is only registered once
+    proposed: Vec<Vector2<f32>>,
+    sequences: SequenceTracker,
+    // Missions handed off and not yet taken over, with the agent that gave them up and when
+    handoffs: HashMap<usize, (usize, u64)>,
+    // Total time handed off missions waited for another agent, and how many were taken over
+    handoff_latency: (f64, usize),
+    clock: SimClock,
+    last_ticks: HashMap<usize, u64>,
+    tick_rates: HashMap<usize, f32>,
+    planners: HashMap<usize, PlannerKind>,
+    default_planner: PlannerKind,
+    seed: u128,
+    rng: Pcg64,
+    // Command line and scenario file of the run, hashed into the summary
+    config_source: String,
 }
 
 impl SystemManager {
-    pub fn new(rendered_tx: Sender<AgentMessage>) -> Self {
+    pub fn new(grid: Arc<Grid>, render_sink: Arc<dyn RenderSink>, mode: CoordinationMode) -> Self {
+        let metrics = Metrics::new();
+        let (operator_tx, operator_rx) = channel();
+        let mut coordinator = Coordinator::new(REGION_SPLIT, SEED);
+        if let Some(roads) = &grid.roads {
+            coordinator.set_roads(roads);
+        }
         SystemManager {
-            connection_manager: ConnectionManager::new(),
-            mission_manager: MissionManager::new(),
+            mode,
+            grid,
+            connection_manager: ConnectionManager::new(metrics.clone()),
+            gossip_nodes: Vec::new(),
+            coordinator,
+            groups: Groups::new(&[], metrics.clone()),
+            claims: ClaimArbiter::new(),
+            integrity_monitor: IntegrityMonitor::new(),
+            metrics,
+            event_log: EventLog::new(),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            write_reports: true,
             id_counter: 0,
-            rendered_tx,
+            byzantine_agents: 0,
+            allocation: AllocationStrategy::Nearest,
+            initial_allocation: AllocationStrategy::Nearest,
+            render_sink,
+            agent_states: HashMap::new(),
+            operator_tx,
+            operator_rx,
+            scheduler: EventScheduler::new(Scenario::default()),
+            scenario: Scenario::default(),
+            keep_connected: false,
+            safety_filter: false,
+            load_shedding: None,
+            idle: IdlePolicy::Drift,
+            observation: ObservationConfig::default(),
+            dataset: None,
+            check_invariants: false,
+            max_mission_wait_s: None,
+            vehicle_capacity: 1,
+            time_windows: false,
+            deliveries: HashMap::new(),
+            crash: CrashContext::new(String::new()),
+            crowd: Crowd::new(0, SEED),
+            pedestrians: Pedestrians::new(),
+            congestion: None,
+            anomalies: Vec::new(),
+            sensing_range: None,
+            boundary: BoundaryPolicy::default(),
+            proposed: Vec::new(),
+            sequences: SequenceTracker::new(),
+            handoffs: HashMap::new(),
+            handoff_latency: (0.0, 0),
+            clock: SimClock::default(),
+            last_ticks: HashMap::new(),
+            tick_rates: HashMap::new(),
+            planners: HashMap::new(),
+            default_planner: PlannerKind::default(),
+            seed: SEED,
+            rng: Pcg64::new(SEED, 1),
+            config_source: String::new(),
+        }
+    }
+
+    pub fn disable_reports(&mut self) {
+        self.write_reports = false;
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    pub fn shutdown_handle(&self) -> Arc<AtomicBool> {
+        self.shutdown.clone()
+    }
+
+    pub fn operator_handle(&self) -> Sender<OperatorCommand> {
+        self.operator_tx.clone()
+    }
+
+    pub fn set_config_source(&mut self, source: String) {
+        self.config_source = source;
+    }
+
+    pub fn set_scenario(&mut self, scenario: Scenario) {
+        self.seed = scenario.seed as u128;
+        self.rng = Pcg64::new(self.seed, 1);
+        if scenario.region_split != self.coordinator.split() || self.seed != self.coordinator.seed()
+        {
+            self.coordinator = Coordinator::new(scenario.region_split, self.seed);
+            if let Some(roads) = &self.grid.roads {
+                self.coordinator.set_roads(roads);
+            }
+            for (id, state) in &self.agent_states {
+                self.coordinator.register_agent(*id, &state.kinematics.p);
+            }
+        }
+        if let Some(base) = scenario.base {
+            self.coordinator.set_base(Vector2::new(base[0], base[1]));
+        }
+        self.tick_rates = scenario.tick_rates.clone();
+        self.planners = scenario.planners.clone();
+        self.default_planner = scenario.planner;
+        self.coordinator.set_obstacles(&scenario.obstacles);
+        self.observation = scenario.observation.clone();
+        self.max_mission_wait_s = scenario.max_mission_wait_s;
+        self.vehicle_capacity = scenario.vehicle_capacity;
+        self.time_windows = scenario.time_windows.is_some()
+            || scenario.missions.iter().any(|m| m.window_s.is_some())
+            || scenario
+                .recurring
+                .iter()
+                .any(|r| r.mission.window_s.is_some());
+        if let Some(windows) = &scenario.time_windows {
+            self.coordinator.set_time_windows(windows);
         }
+        if let Some(reward) = &scenario.reward {
+            self.coordinator.set_reward(reward);
+        }
+        self.crowd = Crowd::new(scenario.pedestrians, self.seed);
+        self.sensing_range = scenario.sensing_range;
+        self.boundary = scenario.boundary;
+        self.anomalies = scenario
+            .anomalies
+            .iter()
+            .map(|a| Vector2::new(a[0], a[1]))
+            .collect();
+        self.coordinator.set_replenishment(&scenario.replenishment);
+        let shares: Vec<f64> = scenario.groups.iter().map(|g| g.mission_share).collect();
+        if shares.iter().any(|&share| share > 0.0) {
+            self.coordinator.set_group_shares(&shares);
+        }
+        self.groups = Groups::new(&scenario.groups, self.metrics.clone());
+        // No agent is there yet to be told about them
+        for pending in &scenario.missions {
+            self.add_mission(pending);
+        }
+        self.scheduler = EventScheduler::new(scenario.clone());
+        self.scenario = scenario;
+    }
+
+    pub fn observation_config(&self) -> &ObservationConfig {
+        &self.observation
+    }
+
+    pub fn record_dataset(&mut self, dataset: DatasetRecorder) {
+        self.dataset = Some(dataset);
+    }
+
+    pub fn pedestrians(&self) -> Pedestrians {
+        self.pedestrians.clone()
+    }
+
+    // Agent density is only tracked, and acted upon, once enabled
+    pub fn enable_congestion(&mut self) {
+        self.congestion = Some(Congestion::new());
+    }
+
+    pub fn congestion(&self) -> Option<Congestion> {
+        self.congestion.clone()
+    }
+
+    pub fn set_allocation_strategy(&mut self, allocation: AllocationStrategy) {
+        self.allocation = allocation;
+        self.initial_allocation = allocation;
+    }
+
+    pub fn set_idle_policy(&mut self, idle: IdlePolicy) {
+        self.idle = idle;
+    }
+
+    pub fn enable_safety_filter(&mut self) {
+        self.safety_filter = true;
+    }
+
+    // Agents and the hub monitor their loop deadlines and shed load through this when late
+    pub fn set_load_shedding(&mut self, shedding: LoadShedding) {
+        self.load_shedding = Some(shedding);
+    }
+
+    pub fn keep_agents_connected(&mut self) {
+        self.keep_connected = true;
+    }
+
+    pub fn record_crashes(&mut self, crash: CrashContext) {
+        self.crash = crash;
+    }
+
+    pub fn check_invariants(&mut self) {
+        self.check_invariants = true;
+    }
+
+    pub fn set_byzantine_agents(&mut self, n: usize) {
+        self.byzantine_agents = n;
     }
 
     pub fn add_agent(&mut self, kinematics: Kinematics) -> (Agent, ConnectionHandle) {
-        let connection_handle = self.connection_manager.create_new_handle();
+        let connection_handle = match self.mode {
+            CoordinationMode::Centralized => {
+                self.connection_manager.create_new_handle(self.id_counter)
+            }
+            CoordinationMode::Gossip => {
+                let (node, handle) = GossipNode::new(
+                    self.id_counter,
+                    self.render_sink.clone(),
+                    self.grid.clone(),
+                    self.clock.clone(),
+                    self.seed,
+                );
+                self.gossip_nodes.push(node);
+                handle
+            }
+        };
+        let personality = Personality::random(&mut self.rng);
+        let home = kinematics.p;
+        // Agents joining during the run are brought up to date with the missions of their region
+        // and the rest of the fleet
+        let mut pending = self
+            .coordinator
+            .register_agent(self.id_counter, &kinematics.p)
+            .unwrap_or_default();
+        pending.retain(|m| self.groups.sees(self.id_counter, m));
+        if !pending.is_empty() || !self.agent_states.is_empty() {
+            self.crash.note(format!(
+                "world sync to agent {}: {} missions, {} agents",
+                self.id_counter,
+                pending.len(),
+                self.agent_states.len()
+            ));
+            self.connection_manager.send_world_sync(
+                self.id_counter,
+                WorldSync {
+                    tick: self.clock.now(),
+                    missions: pending,
+                    agents: self.agent_states.values().cloned().collect(),
+                },
+            );
+        }
+        info!("Agent {} created with {:?}", self.id_counter, personality);
+        let misbehavior = if self.id_counter < self.byzantine_agents {
+            let misbehavior = [
+                Misbehavior::LieAboutPosition,
+                Misbehavior::PhantomClaims,
+                Misbehavior::Spam,
+            ][self.id_counter % 3];
+            warn!(
+                "Agent {} will misbehave: {:?}",
+                self.id_counter, misbehavior
+            );
+            Some(misbehavior)
+        } else {
+            None
+        };
         let out = (
             Agent {
                 id: self.id_counter,
                 kinematics,
                 mission: None,
+                personality,
+                allocation: self.allocation,
+                next_allocation: None,
+                route: Vec::new(),
+                misbehavior,
+                last_broadcast: None,
+                metrics: self.metrics.clone(),
+                wear: 0.0,
+                in_maintenance: false,
+                completed: 0,
+                idle: self.idle,
+                home,
+                idle_target: None,
+                demand: DemandMap::new(),
+                observation: self.observation.clone(),
+                dataset: self.dataset.clone(),
+                pedestrians: self.pedestrians.clone(),
+                congestion: self.congestion.clone().unwrap_or_default(),
+                planner: Planner::new(
+                    self.planners
+                        .get(&self.id_counter)
+                        .copied()
+                        .unwrap_or(self.default_planner),
+                    self.id_counter,
+                    self.seed,
+                ),
+                control_rate_hz: self
+                    .tick_rates
+                    .get(&self.id_counter)
+                    .copied()
+                    .unwrap_or(CONTROL_RATE_HZ),
+                clock: self.clock.clone(),
+                keep_connected: self.keep_connected,
+                safety_filter: self.safety_filter,
+                load_shedding: self.load_shedding.clone(),
+                reconnect_to: None,
+                capacity: match self.mode {
+                    CoordinationMode::Centralized => self.vehicle_capacity,
+                    CoordinationMode::Gossip => 1,
+                },
+                cargo: Vec::new(),
+                anomalies: match self.mode {
+                    CoordinationMode::Centralized => self.anomalies.clone(),
+                    CoordinationMode::Gossip => Vec::new(),
+                },
+                sensing_range: self.sensing_range,
+                seq: 0,
+                boundary: self.boundary,
             },
             connection_handle,
         );
@@ -35,79 +457,1587 @@ impl SystemManager {
         out
     }
 
-    pub fn run(mut self) {
-        loop {
-            let number_missions_left = self.mission_manager.number_missions_left();
-            debug!("Missions left in the pool: {}", number_missions_left);
-            if number_missions_left < 2 * self.id_counter {
-                info!("Creating new batch of missions");
-                let new_missions = self.mission_manager.create_new_missions(self.id_counter);
-                self.connection_manager.send_new_missions(new_missions);
+    pub fn run(mut self) -> Result<(), Error> {
+        self.scheduler.restart();
+        match self.mode {
+            CoordinationMode::Centralized => self.run_centralized(),
+            CoordinationMode::Gossip => self.run_gossip()?,
+        }
+        if self.write_reports {
+            self.write_reports();
+        }
+        if let Some(dataset) = &self.dataset {
+            if let Err(err) = dataset.flush() {
+                error!("Could not write dataset: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_reports(&self) {
+        match write_gantt(&self.event_log, Path::new(GANTT_PATH)) {
+            Ok(()) => info!("Wrote mission Gantt chart to {}", GANTT_PATH),
+            Err(err) => error!("Could not write mission Gantt chart: {}", err),
+        }
+
+        let mode = self.mode.name();
+        let summary = RunSummary::from_log(
+            &self.event_log,
+            mode,
+            self.initial_allocation.name(),
+            self.seed,
+            config_hash(&ConfigDigest {
+                mode,
+                seed: self.seed,
+                n_agents: self.id_counter,
+                byzantine_agents: self.byzantine_agents,
+                allocation: self.initial_allocation.name(),
+                idle: self.idle.name(),
+                source: &self.config_source,
+            }),
+        );
+        match summary.write(Path::new(SUMMARY_PATH)) {
+            Ok(()) => info!("Wrote run summary to {}", SUMMARY_PATH),
+            Err(err) => error!("Could not write run summary: {}", err),
+        }
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.shutdown.load(Ordering::Relaxed)
+    }
+
+    fn run_gossip(&mut self) -> Result<(), Error> {
+        let (source_tx, source_rx) = channel();
+        let mut peers: Vec<_> = self.gossip_nodes.iter().map(|n| n.peer()).collect();
+        for (i, node) in self.gossip_nodes.drain(..).enumerate() {
+            let mut node_peers = peers.clone();
+            node_peers.remove(i);
+            node_peers.push(Peer {
+                id: None,
+                inbox: source_tx.clone(),
+            });
+            std::thread::Builder::new()
+                .name(format!("Gossip {}", i))
+                .spawn(move || node.run(node_peers))?;
+        }
+        drop(source_tx);
+
+        let mut view = GossipView::default();
+        // Missions given by the scenario are spread before the first batch
+        for mission in self.coordinator.missions() {
+            view.missions.insert(mission.id, mission.clone());
+        }
+        if !view.missions.is_empty() {
+            peers.retain(|peer| peer.inbox.send(view.clone()).is_ok());
+        }
+        let start = Instant::now();
+        while !self.is_shutting_down() {
+            self.clock.advance(start.elapsed());
+            while let Ok(command) = self.operator_rx.try_recv() {
+                match command {
+                    OperatorCommand::DumpTrace => self.dump_trace(),
+                    OperatorCommand::UrgentNear(_) => {
+                        warn!("Urgent missions are only supported in centralized mode, ignoring")
+                    }
+                    OperatorCommand::CycleAllocation => warn!(
+                        "Allocation switches are only supported in centralized mode, ignoring"
+                    ),
+                    OperatorCommand::SaveScenario => {
+                        warn!("Scenario snapshots are only supported in centralized mode, ignoring")
+                    }
+                }
+            }
+            for (_, new_missions) in self.coordinator.replenish(self.clock.now()) {
+                debug!("Injecting {} new missions", new_missions.len());
+                for mission in &new_missions {
+                    self.event_log.mission_created(mission);
+                    self.groups.mission_created(mission);
+                    self.crash.note(format!("mission created: {:?}", mission));
+                    view.missions.insert(mission.id, mission.clone());
+                }
+                peers.retain(|peer| peer.inbox.send(view.clone()).is_ok());
+            }
+
+            while let Ok(other) = source_rx.recv_timeout(Duration::from_millis(10)) {
+                let delta = view.merge(other);
+                for state in delta.states {
+                    self.event_log.agent_state(&state);
+                    self.crash.agent_state(&state);
+                    self.coordinator
+                        .register_agent(state.id, &state.kinematics.p);
+                }
+                for (mission_id, agent) in delta.finished {
+                    let mission = self
+                        .coordinator
+                        .missions()
+                        .find(|m| m.id == mission_id)
+                        .cloned();
+                    if let Some(mission) = mission {
+                        self.record_reward(&mission, agent);
+                    }
+                    self.coordinator.finish_mission(mission_id);
+                    let response_time = self.event_log.mission_completed(mission_id, agent);
+                    self.groups.mission_completed(agent, response_time);
+                    self.crash.note(format!(
+                        "mission {} completed by agent {}",
+                        mission_id, agent
+                    ));
+                    self.metrics.set(
+                        "missions.mean_response_time_s",
+                        self.event_log.mean_response_time(),
+                    );
+                    self.metrics
+                        .increment(&format!("agent.{}.completed", agent));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn step_crowd(&mut self, last_step: &mut Instant) {
+        let dt = last_step.elapsed().as_secs_f32().min(MAX_PEDESTRIAN_STEP_S);
+        *last_step = Instant::now();
+        if self.crowd.is_empty() {
+            return;
+        }
+        let agents: Vec<Vector2<f32>> = self
+            .agent_states
+            .values()
+            .map(|state| state.kinematics.p)
+            .collect();
+        self.crowd.step(dt, &agents);
+        self.pedestrians.publish(self.crowd.positions());
+    }
+
+    fn run_centralized(&mut self) {
+        let mut last_report = Instant::now();
+        let start = Instant::now();
+        let mut last_crowd_step = Instant::now();
+        let mut deadlines = self.load_shedding.clone().map(|shedding| {
+            DeadlineMonitor::new(
+                "hub".to_owned(),
+                Duration::from_millis(HUB_LOOP_DEADLINE_MS),
+                shedding,
+                self.metrics.clone(),
+            )
+        });
+        while !self.is_shutting_down() {
+            let iteration_start = Instant::now();
+            self.clock.advance(start.elapsed());
+            self.step_crowd(&mut last_crowd_step);
+            if let Some(congestion) = &self.congestion {
+                let densest =
+                    congestion.publish(self.agent_states.values().map(|s| s.kinematics.p));
+                self.metrics.set("congestion.max_density", densest as f64);
+            }
+            if last_report.elapsed().as_secs_f32() >= METRICS_REPORT_PERIOD_S {
+                last_report = Instant::now();
+                self.metrics.log_report();
+            }
+
+            debug!(
+                "Missions left in the pool: {}",
+                self.coordinator.number_missions_left()
+            );
+            for (agents, new_missions) in self.coordinator.replenish(self.clock.now()) {
+                for mission in &new_missions {
+                    self.event_log.mission_created(mission);
+                    self.groups.mission_created(mission);
+                    self.crash.note(format!("mission created: {:?}", mission));
+                }
+                self.send_missions(&agents, new_missions);
+            }
+            for (agents, moved) in self.coordinator.rebalance() {
+                self.metrics.add("regions.rebalanced", moved.len() as f64);
+                self.send_missions(&agents, moved);
+            }
+            if let Some(max_wait_s) = self.max_mission_wait_s {
+                self.age_missions(max_wait_s);
+            }
+            if self.time_windows {
+                self.expire_missions();
+            }
+
+            while let Ok(command) = self.operator_rx.try_recv() {
+                self.handle_operator_command(command);
+            }
+            for event in self.scheduler.due() {
+                info!(
+                    "Running scenario event at t={}s: {:?}",
+                    event.at, event.action
+                );
+                self.run_scenario_action(event.action);
             }
 
             loop {
                 match self
                     .connection_manager
-                    .rx
                     .recv_timeout(Duration::from_millis(10))
                 {
-                    Ok(agent_message) => {
-                        let to_cancel = self.mission_manager.mission_to_finish(&agent_message);
-                        for (i, tx) in self.connection_manager.txs.iter().enumerate() {
-                            if i != agent_message.id {
-                                debug!("Sending message from {} to {}", agent_message.id, i);
-                                tx.send(Message::Agent(agent_message.clone())).unwrap();
-                            }
-                            if let Some(mission_id) = to_cancel {
-                                tx.send(Message::MissionFinished(mission_id)).unwrap();
-                            }
+                    Ok(Message::MissionProposal(proposal)) => self.handle_proposal(proposal),
+                    Ok(Message::Handoff(handoff)) => self.handle_handoff(handoff),
+                    Ok(Message::Agent(agent_message)) => {
+                        self.observe_sequence(&agent_message);
+                        let last_tick = self.last_ticks.entry(agent_message.id).or_insert(0);
+                        if agent_message.tick < *last_tick {
+                            debug!(
+                                "Discarding state of agent {} from tick {} (already at {})",
+                                agent_message.id, agent_message.tick, last_tick
+                            );
+                            self.metrics
+                                .increment(&format!("connection.{}.stale", agent_message.id));
+                            continue;
+                        }
+                        *last_tick = agent_message.tick;
+                        let dropped = self.connection_manager.take_dropped(agent_message.id);
+                        if !self.integrity_monitor.inspect(&agent_message, dropped) {
+                            debug!("Ignoring message from agent {}", agent_message.id);
+                            continue;
+                        }
+                        if let Some(pending) = self
+                            .coordinator
+                            .register_agent(agent_message.id, &agent_message.kinematics.p)
+                        {
+                            self.send_missions(&[agent_message.id], pending);
+                        }
+                        self.event_log.agent_state(&agent_message);
+                        self.crash.agent_state(&agent_message);
+                        self.record_takeover(&agent_message);
+                        let finished = self
+                            .coordinator
+                            .missions_to_finish(&self.grid, &agent_message);
+                        self.record_delivery(agent_message.id, &finished);
+                        for mission in &finished {
+                            self.record_reward(mission, agent_message.id);
+                        }
+                        let finished: Vec<usize> = finished.iter().map(|m| m.id).collect();
+                        for &mission_id in &finished {
+                            let response_time = self
+                                .event_log
+                                .mission_completed(mission_id, agent_message.id);
+                            self.groups
+                                .mission_completed(agent_message.id, response_time);
+                            self.crash.note(format!(
+                                "mission {} completed by agent {}",
+                                mission_id, agent_message.id
+                            ));
+                            self.metrics.set(
+                                "missions.mean_response_time_s",
+                                self.event_log.mean_response_time(),
+                            );
+                            self.metrics
+                                .increment(&format!("agent.{}.completed", agent_message.id));
                         }
-                        self.rendered_tx.send(agent_message).unwrap();
+                        self.connection_manager
+                            .forward_agent_message(&agent_message, &finished);
+                        self.agent_states
+                            .insert(agent_message.id, agent_message.clone());
+                        let coordinator = &self.coordinator;
+                        let awards =
+                            self.claims
+                                .observe(&agent_message, &self.agent_states, |id| {
+                                    coordinator.mission(id).cloned()
+                                });
+                        for (mission, agent) in awards {
+                            self.crash
+                                .note(format!("mission {} awarded to agent {}", mission.id, agent));
+                            self.connection_manager.send_award(mission, agent);
+                        }
+                        self.render_sink.submit_frame(&RenderFrame {
+                            tick: self.clock.now(),
+                            agents: vec![agent_message],
+                        });
                     }
+                    Ok(_) => debug!("Ignoring a message agents are not supposed to send"),
                     Err(e) => match e {
-                        std::sync::mpsc::RecvTimeoutError::Timeout => break,
-                        std::sync::mpsc::RecvTimeoutError::Disconnected => {}
+                        RecvTimeoutError::Timeout => break,
+                        RecvTimeoutError::Disconnected => {}
                     },
                 }
             }
+
+            if self.check_invariants {
+                self.verify_invariants();
+            }
+            if let Some(deadlines) = &mut deadlines {
+                deadlines.record(iteration_start.elapsed());
+            }
+        }
+    }
+
+    // Sends the missions to those of the agents allowed to see them
+    fn send_missions(&mut self, agents: &[usize], missions: Vec<Mission>) {
+        for (recipients, missions) in self.groups.partition(agents, missions) {
+            self.connection_manager
+                .send_new_missions(&recipients, self.clock.now(), missions);
+        }
+    }
+
+    // Counts the states of the agent that did not make it to the hub, e.g. dropped by its rate
+    // limit, and those that arrived late
+    fn observe_sequence(&mut self, agent_message: &AgentMessage) {
+        let id = agent_message.id;
+        match self.sequences.observe(id, agent_message.seq) {
+            Arrival::InOrder => return,
+            Arrival::AfterGap(lost) => {
+                debug!("Lost {} states of agent {}", lost, id);
+                self.metrics
+                    .add(&format!("connection.{}.lost", id), lost as f64);
+            }
+            Arrival::Late => self.metrics.increment(&format!("connection.{}.late", id)),
+        }
+        self.metrics
+            .set("connection.loss_ratio", self.sequences.loss_ratio());
+    }
+
+    // Reward left on the mission when it was completed, if it has one
+    fn record_reward(&mut self, mission: &Mission, agent: usize) {
+        let reward = match mission.reward_at(self.clock.now()) {
+            Some(reward) => reward,
+            None => return,
+        };
+        self.event_log.mission_rewarded(mission.id, reward);
+        self.metrics.add("missions.reward", reward as f64);
+        self.metrics
+            .add(&format!("agent.{}.reward", agent), reward as f64);
+        self.metrics
+            .set("missions.mean_reward", self.event_log.mean_reward());
+    }
+
+    // Load factor of the drop-offs of transport missions, per agent and over the fleet
+    fn record_delivery(&mut self, agent: usize, finished: &[Mission]) {
+        let load = finished.iter().filter(|m| m.return_to.is_some()).count();
+        if load == 0 {
+            return;
+        }
+        let (deliveries, delivered) = self.deliveries.entry(agent).or_insert((0, 0));
+        *deliveries += 1;
+        *delivered += load;
+        let capacity = self.vehicle_capacity as f64;
+        self.metrics.set(
+            &format!("agent.{}.load_factor", agent),
+            *delivered as f64 / (*deliveries as f64 * capacity),
+        );
+        let (deliveries, delivered) = self
+            .deliveries
+            .values()
+            .fold((0, 0), |(n, load), (d, l)| (n + d, load + l));
+        self.metrics.set(
+            "vehicles.load_factor",
+            delivered as f64 / (deliveries as f64 * capacity),
+        );
+    }
+
+    fn verify_invariants(&self) {
+        let missions: Vec<&Mission> = self.coordinator.missions().collect();
+        let violations = invariants::violations(&self.agent_states, &missions);
+        if violations.is_empty() {
+            return;
+        }
+        for violation in &violations {
+            error!("Invariant violated: {}", violation);
+        }
+        match invariants::write_dump(
+            Path::new(INVARIANT_DUMP_PATH),
+            &violations,
+            &self.agent_states,
+            &missions,
+            &self.metrics.snapshot(),
+        ) {
+            Ok(()) => error!("Wrote world state to {}", INVARIANT_DUMP_PATH),
+            Err(err) => error!("Could not write world state: {}", err),
+        }
+        std::process::abort();
+    }
+
+    fn add_mission(&mut self, pending: &PendingMission) {
+        if let Some((agents, mission)) = self.register_mission(pending) {
+            self.send_missions(&agents, vec![mission]);
+        }
+    }
+
+    // Returns the mission along with the agents of its region, who are not told about it yet
+    fn register_mission(&mut self, pending: &PendingMission) -> Option<(Vec<usize>, Mission)> {
+        let (agents, mission) =
+            match self
+                .coordinator
+                .add_mission(pending, &self.grid, self.clock.now())
+            {
+                Some(added) => added,
+                None => {
+                    warn!("Leaving out mission to unlabeled {:?}", pending.target);
+                    return None;
+                }
+            };
+        self.event_log.mission_created(&mission);
+        self.groups.mission_created(&mission);
+        self.crash.note(format!("mission added: {:?}", mission));
+        Some((agents, mission))
+    }
+
+    // Proposed missions are registered like any other, then awarded to the idle agent best placed
+    // for them, which need not be the one proposing it. Without idle agents, they are left to the
+    // agents of their region like the other pending missions.
+    fn handle_proposal(&mut self, proposal: MissionProposal) {
+        let target = proposal.mission.target.anchor(&self.grid);
+        if target.is_some_and(|t| {
+            self.proposed
+                .iter()
+                .any(|p| (p - t).norm() < DISTANCE_TO_TARGET)
+        }) {
+            debug!(
+                "Agent {} proposed to {}, which is already taken care of",
+                proposal.agent, proposal.reason
+            );
+            self.metrics.increment("proposals.duplicates");
+            return;
+        }
+        info!("Agent {} proposes to {}", proposal.agent, proposal.reason);
+        let (agents, mut mission) = match self.register_mission(&proposal.mission) {
+            Some(registered) => registered,
+            None => return,
+        };
+        self.proposed.extend(target);
+        self.metrics.increment("proposals.accepted");
+        let assignee = match self.best_idle_agent(&mission, &mission.target, None) {
+            Some(assignee) => assignee,
+            None => {
+                self.send_missions(&agents, vec![mission]);
+                return;
+            }
+        };
+        if assignee != proposal.agent {
+            info!(
+                "Mission {} proposed by agent {} goes to agent {}, better placed for it",
+                mission.id, proposal.agent, assignee
+            );
+            self.metrics.increment("proposals.reassigned");
+        }
+        mission.agent = Some(assignee);
+        self.claims.grant(mission.id, assignee);
+        self.crash.note(format!(
+            "mission {} awarded to agent {}",
+            mission.id, assignee
+        ));
+        self.connection_manager.send_award(mission, assignee);
+    }
+
+    // Idle agent that can see the mission and is the cheapest to bring to the point, if any. Those
+    // worn enough to be heading to maintenance are not idle.
+    fn best_idle_agent(
+        &self,
+        mission: &Mission,
+        p: &Vector2<f32>,
+        excluded: Option<usize>,
+    ) -> Option<usize> {
+        self.agent_states
+            .values()
+            .filter(|state| state.mission.is_none() && state.cargo.is_empty())
+            .filter(|state| state.wear < MAINTENANCE_WEAR_THRESHOLD)
+            .filter(|state| Some(state.id) != excluded && self.groups.sees(state.id, mission))
+            .min_by(|a, b| {
+                a.cost_to(p)
+                    .partial_cmp(&b.cost_to(p))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .map(|state| state.id)
+    }
+
+    // Missions given up by worn agents get the priority of an abandoned mission, and are awarded
+    // to the idle agent best placed for what is left of them. Without idle agents, the agents of
+    // their region are told about them again.
+    fn handle_handoff(&mut self, handoff: Handoff) {
+        let (agents, mut mission) = match self.coordinator.hand_off(handoff.mission) {
+            Some(inherited) => inherited,
+            None => {
+                debug!(
+                    "Agent {} handed off mission {}, which is no longer pending",
+                    handoff.agent, handoff.mission
+                );
+                return;
+            }
+        };
+        info!(
+            "Agent {} hands off mission {}, with {} goals left",
+            handoff.agent,
+            mission.id,
+            handoff.remaining.len()
+        );
+        self.metrics.increment("missions.handoffs");
+        self.crash.note(format!(
+            "mission {} handed off by agent {}",
+            mission.id, handoff.agent
+        ));
+        self.handoffs
+            .insert(mission.id, (handoff.agent, self.clock.now()));
+        let next = handoff.remaining.first().copied().unwrap_or(mission.target);
+        match self.best_idle_agent(&mission, &next, Some(handoff.agent)) {
+            Some(assignee) => {
+                info!("Mission {} handed off to agent {}", mission.id, assignee);
+                mission.agent = Some(assignee);
+                self.claims.grant(mission.id, assignee);
+                self.connection_manager.send_award(mission, assignee);
+            }
+            None => self.send_missions(&agents, vec![mission]),
+        }
+    }
+
+    // Time handed off missions waited until another agent worked on them
+    fn record_takeover(&mut self, agent_message: &AgentMessage) {
+        let mission = match &agent_message.mission {
+            Some(mission) => mission.id,
+            None => return,
+        };
+        let since = match self.handoffs.get(&mission) {
+            Some(&(from, since)) if from != agent_message.id => since,
+            _ => return,
+        };
+        self.handoffs.remove(&mission);
+        let latency = self.clock.now().saturating_sub(since) as f64 / CONTROL_RATE_HZ as f64;
+        self.handoff_latency.0 += latency;
+        self.handoff_latency.1 += 1;
+        self.metrics.set(
+            "missions.mean_handoff_latency_s",
+            self.handoff_latency.0 / self.handoff_latency.1 as f64,
+        );
+    }
+
+    fn run_scenario_action(&mut self, action: ScenarioAction) {
+        match action {
+            ScenarioAction::AddMission { mission } => {
+                self.add_mission(&mission);
+                self.metrics.increment("scenario.added_missions");
+            }
+            ScenarioAction::CloseZone { min, max } => {
+                let cancelled = self
+                    .coordinator
+                    .close_zone(Vector2::new(min[0], min[1]), Vector2::new(max[0], max[1]));
+                for mission_id in cancelled {
+                    info!("Mission {} cancelled by a closed zone", mission_id);
+                    self.metrics.increment("scenario.cancelled_missions");
+                    self.crash.note(format!("mission {} cancelled", mission_id));
+                    self.connection_manager.cancel_mission(mission_id);
+                }
+            }
+            ScenarioAction::SpawnAgents { count, position } => {
+                // Agents are lined up so that they do not start on top of each other
+                for i in 0..count {
+                    let offset = (i as f32 - (count as f32 - 1.0) / 2.0) * SPAWN_SPACING;
+                    let kinematics = Kinematics {
+                        p: Vector2::new(position[0] + offset, position[1]),
+                        v: Vector2::zeros(),
+                        a: Vector2::zeros(),
+                        theta: 0.0,
+                        omega: 0.0,
+                        alpha: 0.0,
+                        radius: AGENT_RADIUS,
+                    };
+                    let (agent, handle) = self.add_agent(kinematics);
+                    if let Err(err) = agent.spawn(handle, self.grid.clone()) {
+                        error!("Could not spawn a scenario agent: {}", err);
+                    }
+                }
+            }
+            ScenarioAction::SwitchAllocation { strategy } => self.switch_allocation(strategy),
+            ScenarioAction::KillAgent { id } => {
+                if self.connection_manager.disconnect(id) {
+                    self.agent_states.remove(&id);
+                    self.metrics.increment("scenario.killed_agents");
+                } else {
+                    warn!("Cannot kill unknown agent {}", id);
+                }
+            }
+        }
+    }
+
+    fn handle_operator_command(&mut self, command: OperatorCommand) {
+        match command {
+            OperatorCommand::UrgentNear(p) => self.flag_urgent_near(p),
+            OperatorCommand::DumpTrace => self.dump_trace(),
+            OperatorCommand::CycleAllocation => self.switch_allocation(self.allocation.next()),
+            OperatorCommand::SaveScenario => self.save_scenario(),
+        }
+    }
+
+    // Captures the agents, missions and remaining events into a scenario restarting the run from
+    // its current situation. Agents are renumbered in order, along with everything referring to
+    // them, and missions in progress are pending again.
+    fn save_scenario(&self) {
+        let now = self.clock.now();
+        let mut ids: Vec<usize> = self.agent_states.keys().copied().collect();
+        ids.sort_unstable();
+        let index: HashMap<usize, usize> = ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+        // Agents spawned later keep their place after the current ones
+        let renumber = |id: usize| {
+            index
+                .get(&id)
+                .copied()
+                .or_else(|| id.checked_sub(self.id_counter).map(|k| k + ids.len()))
+        };
+        let point = |p: Vector2<f32>| [p.x, p.y];
+        let seconds = |tick: u64| tick.saturating_sub(now) as f32 / CONTROL_RATE_HZ;
+
+        let mut scenario = self.scenario.clone();
+        scenario.agents = ids
+            .iter()
+            .map(|id| point(self.agent_states[id].kinematics.p))
+            .collect();
+        scenario.missions = self
+            .coordinator
+            .missions()
+            .map(|mission| {
+                // Missions already visited only have their return left
+                let (target, target_theta) = mission.current_goal();
+                PendingMission {
+                    target: MissionTarget::Point(point(target)),
+                    target_theta,
+                    return_to: mission.return_to.filter(|_| !mission.visited).map(point),
+                    group: mission.group,
+                    window_s: mission
+                        .window
+                        .map(|(earliest, latest)| [seconds(earliest), seconds(latest)]),
+                    camera: mission.camera.filter(|_| !mission.visited),
+                    // What is left of it, decaying again from when the scenario is loaded
+                    reward: mission.reward.map(|reward| RewardConfig {
+                        value: mission.reward_at(now).unwrap_or(reward.value),
+                        ..reward
+                    }),
+                }
+            })
+            .collect();
+        scenario.recurring = self.scheduler.remaining_recurring();
+        // Those already proposed are among the missions
+        scenario.anomalies.retain(|a| {
+            !self
+                .proposed
+                .iter()
+                .any(|p| (p - Vector2::new(a[0], a[1])).norm() < DISTANCE_TO_TARGET)
+        });
+        scenario.events = self
+            .scheduler
+            .remaining()
+            .into_iter()
+            .filter_map(|event| match event.action {
+                ScenarioAction::KillAgent { id } => Some(TimedEvent {
+                    action: ScenarioAction::KillAgent { id: renumber(id)? },
+                    ..event
+                }),
+                _ => Some(event),
+            })
+            .collect();
+        scenario.tick_rates = scenario
+            .tick_rates
+            .iter()
+            .filter_map(|(&id, &rate)| Some((renumber(id)?, rate)))
+            .collect();
+        scenario.planners = scenario
+            .planners
+            .iter()
+            .filter_map(|(&id, &planner)| Some((renumber(id)?, planner)))
+            .collect();
+        for group in &mut scenario.groups {
+            group.agents = group.agents.iter().filter_map(|&id| renumber(id)).collect();
+        }
+
+        match scenario.write(Path::new(SCENARIO_SNAPSHOT_PATH)) {
+            Ok(()) => info!(
+                "Wrote scenario with {} agents and {} missions to {}",
+                scenario.agents.len(),
+                scenario.missions.len(),
+                SCENARIO_SNAPSHOT_PATH
+            ),
+            Err(err) => error!("Could not write scenario snapshot: {}", err),
+        }
+    }
+
+    // Agents finish the mission they hold before switching, and agents joining later start with
+    // the new strategy
+    fn switch_allocation(&mut self, allocation: AllocationStrategy) {
+        if allocation == self.allocation {
+            return;
+        }
+        info!(
+            "Switching allocation strategy from {} to {}",
+            self.allocation.name(),
+            allocation.name()
+        );
+        self.allocation = allocation;
+        self.metrics.increment("allocation.switches");
+        self.event_log.strategy_switched(allocation.name());
+        self.crash
+            .note(format!("allocation switched to {}", allocation.name()));
+        self.connection_manager.switch_allocation(allocation);
+    }
+
+    fn dump_trace(&self) {
+        match self.crash.dump_trace(Path::new(TRACE_DUMP_PATH)) {
+            Ok(()) => info!("Wrote message trace to {}", TRACE_DUMP_PATH),
+            Err(err) => error!("Could not write message trace: {}", err),
+        }
+    }
+
+    // Hands an urgent mission to the closest agent right away, pre-empting it from its current
+    // assignee if needed
+    fn flag_urgent_near(&mut self, p: Vector2<f32>) {
+        let mission = match self.coordinator.nearest_pending_mission(&p) {
+            Some(mission) => mission,
+            None => {
+                warn!("No pending mission to flag as urgent");
+                return;
+            }
+        };
+        self.metrics.increment("operator.urgent");
+        self.flag_urgent(mission, "flagged by the operator", &HashSet::new());
+    }
+
+    // Raises the priority of missions with their waiting time, and makes the ones that waited too
+    // long urgent so that none waits more than max_wait_s
+    fn age_missions(&mut self, max_wait_s: f32) {
+        let max_wait = Seconds(max_wait_s).ticks();
+        let mut taken = HashSet::new();
+        for (agents, raised, overdue) in self.coordinator.age(self.clock.now(), max_wait) {
+            if !raised.is_empty() {
+                debug!(
+                    "Raised the priority of missions {:?}",
+                    raised.iter().map(|m| m.id).collect::<Vec<_>>()
+                );
+                self.send_missions(&agents, raised);
+            }
+            for mission in overdue {
+                warn!(
+                    "Mission {} has been waiting for more than {}s",
+                    mission.id, max_wait_s
+                );
+                self.metrics.increment("missions.overdue");
+                taken.extend(self.flag_urgent(mission, "overdue", &taken));
+            }
+        }
+    }
+
+    fn expire_missions(&mut self) {
+        for mission in self.coordinator.expire(self.clock.now()) {
+            info!("Mission {} expired before being completed", mission.id);
+            self.metrics.increment("missions.expired");
+            self.event_log.mission_expired(mission.id);
+            self.crash.note(format!("mission {} expired", mission.id));
+            self.connection_manager.cancel_mission(mission.id);
+        }
+    }
+
+    // Hands the mission to the closest agent that is not already busy with an urgent mission or in
+    // `taken`, and returns it
+    fn flag_urgent(
+        &mut self,
+        mut mission: Mission,
+        reason: &str,
+        taken: &HashSet<usize>,
+    ) -> Option<usize> {
+        let assignee = self
+            .agent_states
+            .values()
+            .filter(|state| !taken.contains(&state.id) && self.groups.sees(state.id, &mission))
+            .filter(|state| {
+                !state
+                    .mission
+                    .as_ref()
+                    .is_some_and(|m| m.urgent && m.id != mission.id)
+            })
+            .min_by(|a, b| {
+                (a.kinematics.p - mission.target)
+                    .norm_squared()
+                    .partial_cmp(&(b.kinematics.p - mission.target).norm_squared())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .map(|state| state.id);
+        let assignee = match assignee {
+            Some(assignee) => assignee,
+            None => {
+                warn!("No agent available for urgent mission {}", mission.id);
+                return None;
+            }
+        };
+
+        for state in self.agent_states.values() {
+            if state.id != assignee && state.mission.as_ref().map(|m| m.id) == Some(mission.id) {
+                info!(
+                    "Pre-empting agent {} from urgent mission {}",
+                    state.id, mission.id
+                );
+                self.metrics.increment("operator.preemptions");
+            }
+        }
+        let abandoned = self.agent_states[&assignee]
+            .mission
+            .as_ref()
+            .map(|m| m.id)
+            .filter(|&id| id != mission.id);
+        if let Some((agents, inherited)) =
+            abandoned.and_then(|id| self.coordinator.inherit_priority(id))
+        {
+            info!(
+                "Mission {} abandoned by agent {} inherits priority {}",
+                inherited.id, assignee, inherited.priority
+            );
+            self.metrics.increment("missions.inherited_priority");
+            self.send_missions(&agents, vec![inherited]);
+        }
+
+        info!(
+            "Mission {} is urgent ({}), assigning it to agent {}",
+            mission.id, reason, assignee
+        );
+        self.coordinator.make_urgent(mission.id);
+        mission.agent = Some(assignee);
+        mission.urgent = true;
+        self.claims.grant(mission.id, assignee);
+        self.crash.note(format!(
+            "mission {} flagged urgent for agent {}",
+            mission.id, assignee
+        ));
+        self.connection_manager.send_urgent(mission, assignee);
+        Some(assignee)
+    }
+}
+
+struct TokenBucket {
+    tokens: f32,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new() -> Self {
+        TokenBucket {
+            tokens: RATE_LIMIT_BURST,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = (now - self.last_refill).as_secs_f32() * RATE_LIMIT_PER_S;
+        self.tokens = (self.tokens + refill).min(RATE_LIMIT_BURST);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
         }
     }
 }
 
 pub struct ConnectionManager {
-    rx: Receiver<AgentMessage>,
-    tx: Sender<AgentMessage>,
-    txs: Vec<Sender<Message>>,
+    rx: Receiver<Message>,
+    tx: Sender<Message>,
+    txs: HashMap<usize, Sender<Message>>,
+    buckets: HashMap<usize, TokenBucket>,
+    // Messages over the rate limit, by agent, not yet reported to the integrity monitor
+    dropped: HashMap<usize, usize>,
+    metrics: Metrics,
+}
+
+// What the hub sends out for a state received from an agent, recipient by recipient: the state to
+// every other agent, then the missions it finished to all of them, the sender included. Messages
+// to a recipient are in the order it must get them in.
+fn route_agent_message(
+    agent_message: &AgentMessage,
+    finished_missions: &[usize],
+    recipients: &[usize],
+) -> Vec<(usize, Message)> {
+    let mut messages = Vec::new();
+    for &id in recipients {
+        if id != agent_message.id {
+            debug!("Sending message from {} to {}", agent_message.id, id);
+            messages.push((id, Message::Agent(agent_message.clone())));
+        }
+        messages.extend(finished_missions.iter().map(|&mission_id| {
+            (
+                id,
+                Message::MissionCompleted {
+                    mission: mission_id,
+                    by_agent: agent_message.id,
+                },
+            )
+        }));
+    }
+    messages
 }
 
 pub struct ConnectionHandle {
-    pub tx: Sender<AgentMessage>,
+    pub tx: Sender<Message>,
     pub rx: Receiver<Message>,
 }
 
 impl ConnectionManager {
-    pub fn new() -> Self {
+    pub fn new(metrics: Metrics) -> Self {
         let (tx, rx) = channel();
         ConnectionManager {
             tx,
             rx,
-            txs: Vec::new(),
+            txs: HashMap::new(),
+            buckets: HashMap::new(),
+            dropped: HashMap::new(),
+            metrics,
         }
     }
 
-    pub fn create_new_handle(&mut self) -> ConnectionHandle {
+    // States and mission proposals from the agents, within their rate limit
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Message, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let message = self
+                .rx
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))?;
+            let id = match &message {
+                Message::Agent(agent_message) => agent_message.id,
+                Message::MissionProposal(proposal) => proposal.agent,
+                _ => return Ok(message),
+            };
+            if self
+                .buckets
+                .entry(id)
+                .or_insert_with(TokenBucket::new)
+                .try_take()
+            {
+                self.metrics
+                    .increment(&format!("connection.{}.accepted", id));
+                return Ok(message);
+            }
+            debug!("Agent {} exceeded its rate limit, dropping message", id);
+            *self.dropped.entry(id).or_insert(0) += 1;
+            self.metrics
+                .increment(&format!("connection.{}.dropped", id));
+        }
+    }
+
+    // Number of messages of the agent dropped since the last call
+    pub fn take_dropped(&mut self, id: usize) -> usize {
+        self.dropped.remove(&id).unwrap_or(0)
+    }
+
+    pub fn create_new_handle(&mut self, id: usize) -> ConnectionHandle {
         let (tx, rx) = channel();
-        self.txs.push(tx);
+        self.txs.insert(id, tx);
         ConnectionHandle {
             tx: self.tx.clone(),
             rx,
         }
     }
 
-    pub fn send_new_missions(&mut self, new_missions: Vec<Mission>) {
-        for tx in &self.txs {
-            tx.send(Message::Mission(MissionMessage(new_missions.clone())))
+    pub fn send_new_missions(&mut self, agents: &[usize], tick: u64, new_missions: Vec<Mission>) {
+        self.txs.retain(|&id, tx| {
+            !agents.contains(&id)
+                || ConnectionManager::send_or_drop(
+                    id,
+                    tx,
+                    Message::Mission(MissionMessage {
+                        tick,
+                        missions: new_missions.clone(),
+                    }),
+                )
+        });
+    }
+
+    pub fn forward_agent_message(
+        &mut self,
+        agent_message: &AgentMessage,
+        finished_missions: &[usize],
+    ) {
+        let recipients: Vec<usize> = self.txs.keys().copied().collect();
+        let mut dropped = HashSet::new();
+        for (id, message) in route_agent_message(agent_message, finished_missions, &recipients) {
+            if dropped.contains(&id) {
+                continue;
+            }
+            if !ConnectionManager::send_or_drop(id, &self.txs[&id], message) {
+                dropped.insert(id);
+            }
+        }
+        self.txs.retain(|id, _| !dropped.contains(id));
+    }
+
+    pub fn send_world_sync(&mut self, id: usize, sync: WorldSync) {
+        if let Some(tx) = self.txs.get(&id) {
+            if !ConnectionManager::send_or_drop(id, tx, Message::WorldSync(sync)) {
+                self.txs.remove(&id);
+            }
+        }
+    }
+
+    pub fn cancel_mission(&mut self, mission_id: usize) {
+        self.txs.retain(|&id, tx| {
+            ConnectionManager::send_or_drop(id, tx, Message::MissionCancelled(mission_id))
+        });
+    }
+
+    pub fn switch_allocation(&mut self, allocation: AllocationStrategy) {
+        self.txs.retain(|&id, tx| {
+            ConnectionManager::send_or_drop(id, tx, Message::SwitchAllocation(allocation))
+        });
+    }
+
+    // Dropping the agent's channel makes it stop on its next receive
+    pub fn disconnect(&mut self, id: usize) -> bool {
+        self.buckets.remove(&id);
+        self.dropped.remove(&id);
+        self.txs.remove(&id).is_some()
+    }
+
+    pub fn send_urgent(&mut self, mission: Mission, assignee: usize) {
+        self.txs.retain(|&id, tx| {
+            ConnectionManager::send_or_drop(
+                id,
+                tx,
+                Message::Urgent {
+                    mission: mission.clone(),
+                    assignee,
+                },
+            )
+        });
+    }
+
+    pub fn send_award(&mut self, mission: Mission, agent: usize) {
+        self.txs.retain(|&id, tx| {
+            ConnectionManager::send_or_drop(
+                id,
+                tx,
+                Message::Awarded {
+                    mission: mission.clone(),
+                    agent,
+                },
+            )
+        });
+    }
+
+    fn send_or_drop(id: usize, tx: &Sender<Message>, message: Message) -> bool {
+        match tx.send(message) {
+            Ok(()) => true,
+            Err(_) => {
+                warn!("Agent {} is unreachable, dropping its connection", id);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::Inbox;
+    use crate::consts::{CELL_SIZE, GRID_HALF_SIZE, MAX_REACTION_DELAY_MS};
+    use crate::render_sink::NullSink;
+    use proptest::prelude::*;
+    use rand::Rng;
+    use std::collections::VecDeque;
+
+    fn state(id: usize) -> AgentMessage {
+        AgentMessage {
+            id,
+            seq: 1,
+            tick: 0,
+            kinematics: Kinematics {
+                p: Vector2::zeros(),
+                v: Vector2::zeros(),
+                a: Vector2::zeros(),
+                theta: 0.0,
+                omega: 0.0,
+                alpha: 0.0,
+                radius: AGENT_RADIUS,
+            },
+            mission: None,
+            wear: 0.0,
+            completed: 0,
+            plan: Vec::new(),
+            waypoints: Vec::new(),
+            cargo: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn send_or_drop_reports_a_dropped_receiver() {
+        let (tx, rx) = channel();
+        assert!(ConnectionManager::send_or_drop(
+            0,
+            &tx,
+            Message::MissionCancelled(1)
+        ));
+        assert!(matches!(rx.try_recv(), Ok(Message::MissionCancelled(1))));
+        drop(rx);
+        assert!(!ConnectionManager::send_or_drop(
+            0,
+            &tx,
+            Message::MissionCancelled(2)
+        ));
+    }
+
+    #[test]
+    fn dead_peer_is_unregistered_and_the_others_still_served() {
+        let mut connections = ConnectionManager::new(Metrics::new());
+        let alive = connections.create_new_handle(0);
+        let dead = connections.create_new_handle(1);
+        drop(dead);
+
+        connections.cancel_mission(7);
+        assert!(!connections.txs.contains_key(&1));
+        assert!(matches!(
+            alive.rx.try_recv(),
+            Ok(Message::MissionCancelled(7))
+        ));
+
+        connections.cancel_mission(8);
+        assert!(matches!(
+            alive.rx.try_recv(),
+            Ok(Message::MissionCancelled(8))
+        ));
+        assert_eq!(connections.txs.len(), 1);
+    }
+
+    #[test]
+    fn messages_over_the_rate_limit_are_counted_for_the_integrity_monitor() {
+        let mut connections = ConnectionManager::new(Metrics::new());
+        let handle = connections.create_new_handle(0);
+        let sent = 2 * RATE_LIMIT_BURST as usize;
+        for _ in 0..sent {
+            handle.tx.send(Message::Agent(state(0))).unwrap();
+        }
+        let mut accepted = 0;
+        while connections.recv_timeout(Duration::from_millis(1)).is_ok() {
+            accepted += 1;
+        }
+        assert!(accepted < sent);
+        assert_eq!(accepted + connections.take_dropped(0), sent);
+        assert_eq!(connections.take_dropped(0), 0);
+    }
+
+    #[test]
+    fn peer_dying_mid_forward_is_unregistered() {
+        let mut connections = ConnectionManager::new(Metrics::new());
+        let sender = connections.create_new_handle(0);
+        let alive = connections.create_new_handle(1);
+        let dead = connections.create_new_handle(2);
+
+        connections.forward_agent_message(&state(0), &[]);
+        assert!(matches!(alive.rx.try_recv(), Ok(Message::Agent(s)) if s.id == 0));
+        assert!(matches!(dead.rx.try_recv(), Ok(Message::Agent(s)) if s.id == 0));
+        drop(dead);
+
+        connections.forward_agent_message(&state(0), &[3]);
+        assert!(!connections.txs.contains_key(&2));
+        assert!(matches!(alive.rx.try_recv(), Ok(Message::Agent(s)) if s.id == 0));
+        assert!(matches!(
+            alive.rx.try_recv(),
+            Ok(Message::MissionCompleted {
+                mission: 3,
+                by_agent: 0
+            })
+        ));
+        assert!(matches!(
+            sender.rx.try_recv(),
+            Ok(Message::MissionCompleted {
+                mission: 3,
+                by_agent: 0
+            })
+        ));
+    }
+
+    // Whatever the sender, recipients and finished missions, every recipient hears of each finished
+    // mission exactly once, and of the state of the sender once unless it is the sender
+    #[test]
+    fn every_recipient_hears_of_each_completion_exactly_once() {
+        let mut rng = Pcg64::new(SEED, 0);
+        for _ in 0..500 {
+            let sender = rng.gen_range(0..8);
+            let recipients: Vec<usize> = (0..8).filter(|_| rng.gen_bool(0.7)).collect();
+            let finished: Vec<usize> = (0..rng.gen_range(0..4)).map(|i| 10 + i).collect();
+
+            let messages = route_agent_message(&state(sender), &finished, &recipients);
+            for &id in &recipients {
+                let states = messages
+                    .iter()
+                    .filter(|(to, m)| *to == id && matches!(m, Message::Agent(s) if s.id == sender))
+                    .count();
+                assert_eq!(states, usize::from(id != sender));
+                for &mission in &finished {
+                    let completions = messages
+                        .iter()
+                        .filter(|(to, m)| {
+                            *to == id
+                                && matches!(m, Message::MissionCompleted { mission: done, by_agent }
+                                    if *done == mission && *by_agent == sender)
+                        })
+                        .count();
+                    assert_eq!(completions, 1);
+                }
+            }
+            assert!(messages.iter().all(|(to, _)| recipients.contains(to)));
+        }
+    }
+
+    #[test]
+    fn routed_state_is_not_echoed_to_its_sender() {
+        let messages = route_agent_message(&state(1), &[], &[0, 1, 2]);
+        let recipients: Vec<usize> = messages.iter().map(|(to, _)| *to).collect();
+        assert_eq!(recipients, vec![0, 2]);
+        assert!(messages
+            .iter()
+            .all(|(_, m)| matches!(m, Message::Agent(s) if s.id == 1)));
+    }
+
+    // Every recipient gets the state of the sender before hearing it finished its missions, so
+    // that the mission is never seen completed by an agent still heading for it
+    #[test]
+    fn finished_missions_follow_the_state_recipient_by_recipient() {
+        let messages = route_agent_message(&state(1), &[5, 6], &[2, 1, 0]);
+        let routed: Vec<(usize, Option<usize>)> = messages
+            .iter()
+            .map(|(to, m)| match m {
+                Message::Agent(_) => (*to, None),
+                Message::MissionCompleted {
+                    mission,
+                    by_agent: 1,
+                } => (*to, Some(*mission)),
+                _ => panic!("unexpected message to {}", to),
+            })
+            .collect();
+        assert_eq!(
+            routed,
+            vec![
+                (2, None),
+                (2, Some(5)),
+                (2, Some(6)),
+                (1, Some(5)),
+                (1, Some(6)),
+                (0, None),
+                (0, Some(5)),
+                (0, Some(6)),
+            ]
+        );
+    }
+
+    // Stand-in for an agent thread: the agent along with what it knows of the others
+    struct Member {
+        agent: Agent,
+        missions: MissionPool,
+        agents: HashMap<usize, AgentMessage>,
+        inbox: Inbox,
+    }
+
+    // The hub and its agents trading messages in memory rather than over channels between threads.
+    // Each link delivers in order, as a channel does, but which link delivers next is drawn from
+    // the seed, so that a seed always plays out the same way.
+    struct Allocator {
+        hub: SystemManager,
+        members: Vec<Member>,
+        // In flight, from each agent to the hub and from the hub to each agent
+        uplinks: Vec<VecDeque<AgentMessage>>,
+        downlinks: Vec<VecDeque<Message>>,
+        rng: Pcg64,
+        round: u64,
+        created: Vec<usize>,
+        completed: Vec<usize>,
+        expired: Vec<usize>,
+        // Missions held by several agents once every message was delivered, with the round
+        doubly_held: Vec<(u64, usize, Vec<usize>)>,
+        // Missions several agents held as granted to them at once, with the round
+        doubly_granted: Vec<(u64, usize, Vec<usize>)>,
+    }
+
+    const ROUND_MS: u64 = 100;
+    // How far agents get towards their goal every round, at about the top speed
+    const STEP: f32 = 15.0;
+    const SETTLE_EVERY: u64 = 10;
+    const MAX_ROUNDS: u64 = 1000;
+
+    impl Allocator {
+        fn new(seed: u64, n_agents: usize) -> Self {
+            let mut hub = SystemManager::new(
+                Arc::new(crate::init_grid()),
+                Arc::new(NullSink),
+                CoordinationMode::Centralized,
+            );
+            hub.disable_reports();
+            hub.rng = Pcg64::new(seed as u128, 1);
+            let mut rng = Pcg64::new(seed as u128, 2);
+            let members = (0..n_agents)
+                .map(|_| {
+                    let mut kinematics = state(0).kinematics;
+                    kinematics.p = random_point(&mut rng);
+                    let (agent, _connection_handle) = hub.add_agent(kinematics);
+                    Member {
+                        agent,
+                        missions: MissionPool::default(),
+                        agents: HashMap::new(),
+                        inbox: Inbox::default(),
+                    }
+                })
+                .collect();
+            Allocator {
+                hub,
+                members,
+                uplinks: vec![VecDeque::new(); n_agents],
+                downlinks: (0..n_agents).map(|_| VecDeque::new()).collect(),
+                rng,
+                round: 0,
+                created: Vec::new(),
+                completed: Vec::new(),
+                expired: Vec::new(),
+                doubly_held: Vec::new(),
+                doubly_granted: Vec::new(),
+            }
+        }
+
+        fn broadcast(&mut self, message: impl Fn() -> Message) {
+            for link in &mut self.downlinks {
+                link.push_back(message());
+            }
+        }
+
+        fn add_mission(&mut self) {
+            let target = random_point(&mut self.rng);
+            let window_s = if self.rng.gen_bool(0.3) {
+                let earliest = self.rng.gen_range(0.0..2.0);
+                Some([earliest, earliest + self.rng.gen_range(0.5..5.0)])
+            } else {
+                None
+            };
+            let pending = PendingMission {
+                target: MissionTarget::Point([target.x, target.y]),
+                target_theta: None,
+                return_to: None,
+                group: None,
+                window_s,
+                camera: None,
+                reward: None,
+            };
+            let now = self.hub.clock.now();
+            let (_, mission) = self
+                .hub
+                .coordinator
+                .add_mission(&pending, &self.hub.grid, now)
                 .unwrap();
+            self.created.push(mission.id);
+            self.broadcast(|| {
+                Message::Mission(MissionMessage {
+                    tick: now,
+                    missions: vec![mission.clone()],
+                })
+            });
+        }
+
+        // What run_centralized does with a state as far as missions go
+        fn hub_receives(&mut self, state: AgentMessage) {
+            let hub = &mut self.hub;
+            let finished: Vec<usize> = hub
+                .coordinator
+                .missions_to_finish(&hub.grid, &state)
+                .iter()
+                .map(|m| m.id)
+                .collect();
+            self.completed.extend(&finished);
+            let recipients: Vec<usize> = (0..self.downlinks.len()).collect();
+            for (id, message) in route_agent_message(&state, &finished, &recipients) {
+                self.downlinks[id].push_back(message);
+            }
+            hub.agent_states.insert(state.id, state.clone());
+            let coordinator = &hub.coordinator;
+            let awards = hub.claims.observe(&state, &hub.agent_states, |id| {
+                coordinator.mission(id).cloned()
+            });
+            for (mission, agent) in awards {
+                self.broadcast(|| Message::Awarded {
+                    mission: mission.clone(),
+                    agent,
+                });
+            }
+        }
+
+        fn expire(&mut self) {
+            for mission in self.hub.coordinator.expire(self.hub.clock.now()) {
+                self.expired.push(mission.id);
+                self.broadcast(|| Message::MissionCancelled(mission.id));
+            }
+        }
+
+        // The agent decides as if its reaction delay were over, moves a step towards its goal
+        // and sends its state
+        fn agent_turn(&mut self, id: usize) {
+            let member = &mut self.members[id];
+            let reacted = Instant::now() + Duration::from_millis(MAX_REACTION_DELAY_MS);
+            member.agent.decide(
+                &self.hub.grid,
+                &member.missions,
+                &member.agents,
+                &mut member.inbox,
+                reacted,
+            );
+            if let Some(mission) = &member.agent.mission {
+                let to_goal = mission.current_goal().0 - member.agent.kinematics.p;
+                member.agent.kinematics.p += to_goal * (STEP / to_goal.norm()).min(1.0);
+            }
+            self.uplinks[id].push_back(member.agent.next_state());
+        }
+
+        // Hands over the next message of a link drawn at random, if any is in flight
+        fn deliver_one(&mut self) -> bool {
+            let n = self.members.len();
+            let busy: Vec<usize> = (0..2 * n)
+                .filter(|&link| match link.checked_sub(n) {
+                    None => !self.uplinks[link].is_empty(),
+                    Some(id) => !self.downlinks[id].is_empty(),
+                })
+                .collect();
+            if busy.is_empty() {
+                return false;
+            }
+            let link = busy[self.rng.gen_range(0..busy.len())];
+            match link.checked_sub(n) {
+                None => {
+                    let state = self.uplinks[link].pop_front().unwrap();
+                    self.hub_receives(state);
+                }
+                Some(id) => {
+                    let message = self.downlinks[id].pop_front().unwrap();
+                    let member = &mut self.members[id];
+                    member.agent.receive(
+                        message,
+                        &mut member.missions,
+                        &mut member.agents,
+                        &mut member.inbox,
+                    );
+                }
+            }
+            self.check_grants();
+            true
+        }
+
+        // Agents holding each mission, only counting those it was granted to if `granted`
+        fn holders(&self, granted: bool) -> Vec<(usize, Vec<usize>)> {
+            let mut holders: HashMap<usize, Vec<usize>> = HashMap::new();
+            for member in &self.members {
+                let agent = &member.agent;
+                if let Some(mission) = &agent.mission {
+                    if !granted || mission.agent == Some(agent.id) {
+                        holders.entry(mission.id).or_default().push(agent.id);
+                    }
+                }
+            }
+            holders
+                .into_iter()
+                .filter(|(_, agents)| agents.len() > 1)
+                .collect()
+        }
+
+        fn check_grants(&mut self) {
+            for (mission, agents) in self.holders(true) {
+                self.doubly_granted.push((self.round, mission, agents));
+            }
+        }
+
+        // Lets everything in flight arrive, after which the hub and every agent heard all the
+        // others had to say
+        fn settle(&mut self) {
+            while self.deliver_one() {}
+            for (mission, agents) in self.holders(false) {
+                self.doubly_held.push((self.round, mission, agents));
+            }
+        }
+
+        fn pending(&self) -> usize {
+            self.created.len() - self.completed.len() - self.expired.len()
+        }
+
+        // Missions show up during the first rounds, then the agents are left to finish them
+        fn run(&mut self, n_missions: usize) {
+            while self.round < MAX_ROUNDS && (self.created.len() < n_missions || self.pending() > 0)
+            {
+                self.round += 1;
+                self.hub
+                    .clock
+                    .advance(Duration::from_millis(self.round * ROUND_MS));
+                if self.created.len() < n_missions && self.rng.gen_bool(0.3) {
+                    self.add_mission();
+                }
+                self.expire();
+                for id in 0..self.members.len() {
+                    self.agent_turn(id);
+                }
+                if self.round.is_multiple_of(SETTLE_EVERY) {
+                    self.settle();
+                } else {
+                    let in_flight: usize = self.uplinks.iter().map(|l| l.len()).sum::<usize>()
+                        + self.downlinks.iter().map(|l| l.len()).sum::<usize>();
+                    for _ in 0..self.rng.gen_range(0..=in_flight) {
+                        self.deliver_one();
+                    }
+                }
+            }
+            self.settle();
+        }
+    }
+
+    fn random_point(rng: &mut Pcg64) -> Vector2<f32> {
+        let bound = GRID_HALF_SIZE - AGENT_RADIUS - CELL_SIZE;
+        Vector2::new(rng.gen_range(-bound..bound), rng.gen_range(-bound..bound))
+    }
+
+    fn allocate(seed: u64, n_agents: usize, n_missions: usize) -> Allocator {
+        let mut allocator = Allocator::new(seed, n_agents);
+        allocator.run(n_missions);
+        allocator
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn no_mission_is_completed_twice(
+            seed in any::<u64>(),
+            n_agents in 2..6usize,
+            n_missions in 1..20usize,
+        ) {
+            let allocator = allocate(seed, n_agents, n_missions);
+            let mut completed = allocator.completed.clone();
+            completed.sort_unstable();
+            completed.dedup();
+            prop_assert_eq!(completed.len(), allocator.completed.len());
+        }
+
+        #[test]
+        fn every_mission_is_completed_or_expired(
+            seed in any::<u64>(),
+            n_agents in 2..6usize,
+            n_missions in 1..20usize,
+        ) {
+            let allocator = allocate(seed, n_agents, n_missions);
+            let mut over: Vec<usize> = allocator
+                .completed
+                .iter()
+                .chain(&allocator.expired)
+                .copied()
+                .collect();
+            over.sort_unstable();
+            let mut created = allocator.created.clone();
+            created.sort_unstable();
+            prop_assert_eq!(over, created);
+        }
+
+        #[test]
+        fn no_two_agents_hold_a_granted_claim_at_once(
+            seed in any::<u64>(),
+            n_agents in 2..6usize,
+            n_missions in 1..20usize,
+        ) {
+            let allocator = allocate(seed, n_agents, n_missions);
+            prop_assert!(
+                allocator.doubly_granted.is_empty(),
+                "granted to several agents: {:?}",
+                allocator.doubly_granted
+            );
+            prop_assert!(
+                allocator.doubly_held.is_empty(),
+                "still held by several agents once settled: {:?}",
+                allocator.doubly_held
+            );
         }
     }
 }
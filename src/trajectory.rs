@@ -0,0 +1,116 @@
+use crate::units::{MetersPerSecond, MetersPerSecondSquared, Seconds};
+use nalgebra::Vector2;
+
+// Minimum-time motion of a double integrator along one axis, from a position and velocity to
+// rest at a goal, with bounded speed and acceleration: a list of constant-acceleration phases
+#[derive(Clone, Debug)]
+struct Profile {
+    x0: f32,
+    v0: f32,
+    phases: Vec<(f32, f32)>,
+}
+
+impl Profile {
+    fn new(x0: f32, v0: f32, goal: f32, max_speed: f32, max_acceleration: f32) -> Self {
+        let a = max_acceleration;
+        // Moving toward where the goal lies once the current velocity is braked away
+        let s = if goal - x0 - v0 * v0.abs() / (2.0 * a) >= 0.0 {
+            1.0
+        } else {
+            -1.0
+        };
+        let distance = s * (goal - x0);
+        let v = s * v0;
+        let peak = ((2.0 * a * distance + v * v) / 2.0).max(0.0).sqrt();
+        let mut phases = Vec::with_capacity(3);
+        if peak <= max_speed {
+            phases.push(((peak - v).abs() / a, s * a * (peak - v).signum()));
+            phases.push((peak / a, -s * a));
+        } else {
+            // Signed: starting off backwards, the ramp ends behind where it started
+            let ramp = (max_speed * max_speed - v * v) / (2.0 * a) * (max_speed - v).signum();
+            let brake = max_speed * max_speed / (2.0 * a);
+            phases.push(((max_speed - v).abs() / a, s * a * (max_speed - v).signum()));
+            phases.push((((distance - ramp - brake) / max_speed).max(0.0), 0.0));
+            phases.push((max_speed / a, -s * a));
+        }
+        Profile { x0, v0, phases }
+    }
+
+    fn duration(&self) -> f32 {
+        self.phases.iter().map(|(t, _)| t).sum()
+    }
+
+    // Position, velocity and acceleration t seconds after the start
+    fn at(&self, mut t: f32) -> (f32, f32, f32) {
+        let (mut x, mut v) = (self.x0, self.v0);
+        for &(duration, a) in &self.phases {
+            if t < duration {
+                return (x + v * t + a * t * t / 2.0, v + a * t, a);
+            }
+            x += v * duration + a * duration * duration / 2.0;
+            v += a * duration;
+            t -= duration;
+        }
+        (x, 0.0, 0.0)
+    }
+}
+
+pub struct Reference {
+    pub p: Vector2<f32>,
+    pub v: Vector2<f32>,
+    pub a: Vector2<f32>,
+}
+
+// Time-parameterized trajectory to a goal. Both axes are planned independently with limits
+// divided by sqrt(2), so that the combined speed and acceleration stay within the agent's caps.
+pub struct Trajectory {
+    pub goal: Vector2<f32>,
+    axes: [Profile; 2],
+    elapsed: f32,
+}
+
+impl Trajectory {
+    pub fn new(
+        p: Vector2<f32>,
+        v: Vector2<f32>,
+        goal: Vector2<f32>,
+        max_speed: MetersPerSecond,
+        max_acceleration: MetersPerSecondSquared,
+    ) -> Self {
+        let speed = max_speed.0 * std::f32::consts::FRAC_1_SQRT_2;
+        let acceleration = max_acceleration.0 * std::f32::consts::FRAC_1_SQRT_2;
+        Trajectory {
+            goal,
+            axes: [
+                Profile::new(p.x, v.x, goal.x, speed, acceleration),
+                Profile::new(p.y, v.y, goal.y, speed, acceleration),
+            ],
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn duration(&self) -> Seconds {
+        Seconds(self.axes[0].duration().max(self.axes[1].duration()))
+    }
+
+    // Planned position t of trajectory time from now
+    pub fn position_ahead(&self, t: Seconds) -> Vector2<f32> {
+        Vector2::new(
+            self.axes[0].at(self.elapsed + t.0).0,
+            self.axes[1].at(self.elapsed + t.0).0,
+        )
+    }
+
+    // Moves along the trajectory by dt and returns the new reference state
+    pub fn advance(&mut self, dt: Seconds) -> Reference {
+        self.elapsed += dt.0;
+        let (x, vx, ax) = self.axes[0].at(self.elapsed);
+        let (y, vy, ay) = self.axes[1].at(self.elapsed);
+        Reference {
+            p: Vector2::new(x, y),
+            v: Vector2::new(vx, vy),
+            a: Vector2::new(ax, ay),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::consts::CONTROL_RATE_HZ;
+use std::ops::{Add, Div, Mul, Sub};
+
+// Scalar quantities passed between modules, so that e.g. a speed cannot be given where an
+// acceleration is expected. Positions and velocities stay nalgebra vectors, in meters and meters
+// per second; the inner value is only unwrapped to be combined with them.
+macro_rules! quantity {
+    ($name:ident) => {
+        #[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+        pub struct $name(pub f32);
+
+        impl Add for $name {
+            type Output = $name;
+            fn add(self, other: $name) -> $name {
+                $name(self.0 + other.0)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = $name;
+            fn sub(self, other: $name) -> $name {
+                $name(self.0 - other.0)
+            }
+        }
+
+        impl Mul<$name> for f32 {
+            type Output = $name;
+            fn mul(self, quantity: $name) -> $name {
+                $name(self * quantity.0)
+            }
+        }
+
+        impl Mul<f32> for $name {
+            type Output = $name;
+            fn mul(self, factor: f32) -> $name {
+                $name(self.0 * factor)
+            }
+        }
+
+        // Ratio of two quantities of the same unit
+        impl Div for $name {
+            type Output = f32;
+            fn div(self, other: $name) -> f32 {
+                self.0 / other.0
+            }
+        }
+    };
+}
+
+quantity!(Meters);
+quantity!(MetersPerSecond);
+quantity!(MetersPerSecondSquared);
+quantity!(Seconds);
+
+impl Seconds {
+    // Number of simulation ticks in the duration, to the nearest: truncating would lose a tick to
+    // rounding on durations such as 2.1s
+    pub fn ticks(self) -> u64 {
+        (self.0 * CONTROL_RATE_HZ).round() as u64
+    }
+}
+
+impl Div<MetersPerSecond> for Meters {
+    type Output = Seconds;
+    fn div(self, speed: MetersPerSecond) -> Seconds {
+        Seconds(self.0 / speed.0)
+    }
+}
+
+impl Div<Seconds> for Meters {
+    type Output = MetersPerSecond;
+    fn div(self, duration: Seconds) -> MetersPerSecond {
+        MetersPerSecond(self.0 / duration.0)
+    }
+}
+
+impl Mul<Seconds> for MetersPerSecond {
+    type Output = Meters;
+    fn mul(self, duration: Seconds) -> Meters {
+        Meters(self.0 * duration.0)
+    }
+}
+
+impl Div<MetersPerSecondSquared> for MetersPerSecond {
+    type Output = Seconds;
+    fn div(self, acceleration: MetersPerSecondSquared) -> Seconds {
+        Seconds(self.0 / acceleration.0)
+    }
+}
+
+impl Div<Seconds> for MetersPerSecond {
+    type Output = MetersPerSecondSquared;
+    fn div(self, duration: Seconds) -> MetersPerSecondSquared {
+        MetersPerSecondSquared(self.0 / duration.0)
+    }
+}
+
+impl Mul<Seconds> for MetersPerSecondSquared {
+    type Output = MetersPerSecond;
+    fn mul(self, duration: Seconds) -> MetersPerSecond {
+        MetersPerSecond(self.0 * duration.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_convert_to_exact_tick_counts() {
+        assert_eq!(Seconds(0.0).ticks(), 0);
+        assert_eq!(Seconds(1.0 / CONTROL_RATE_HZ).ticks(), 1);
+        assert_eq!(Seconds(1.0).ticks(), CONTROL_RATE_HZ as u64);
+        assert_eq!(Seconds(2.1).ticks(), 105);
+        assert_eq!(Seconds(4.2).ticks(), 210);
+        assert_eq!(Seconds(30.0).ticks(), 1500);
+        assert_eq!(Seconds(-1.0).ticks(), 0);
+        for ms in (0..200_000).step_by(20) {
+            assert_eq!(Seconds(ms as f32 / 1000.0).ticks(), ms / 20, "{}ms", ms);
+        }
+    }
+
+    #[test]
+    fn quantities_combine_into_the_expected_units() {
+        assert_eq!(Meters(3.0) + Meters(4.5), Meters(7.5));
+        assert_eq!(
+            MetersPerSecond(10.0) - MetersPerSecond(4.0),
+            MetersPerSecond(6.0)
+        );
+        assert_eq!(2.0 * Meters(3.0), Meters(6.0));
+        assert_eq!(Seconds(1.5) * 2.0, Seconds(3.0));
+        assert_eq!(Meters(9.0) / Meters(3.0), 3.0);
+        assert_eq!(Meters(100.0) / MetersPerSecond(20.0), Seconds(5.0));
+        assert_eq!(Meters(100.0) / Seconds(4.0), MetersPerSecond(25.0));
+        assert_eq!(MetersPerSecond(20.0) * Seconds(2.5), Meters(50.0));
+        assert_eq!(
+            MetersPerSecond(30.0) / MetersPerSecondSquared(10.0),
+            Seconds(3.0)
+        );
+        assert_eq!(
+            MetersPerSecond(30.0) / Seconds(2.0),
+            MetersPerSecondSquared(15.0)
+        );
+        assert_eq!(
+            MetersPerSecondSquared(4.0) * Seconds(2.5),
+            MetersPerSecond(10.0)
+        );
+    }
+}